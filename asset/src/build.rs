@@ -91,7 +91,7 @@ impl Builder {
 		}
 		Ok(())
 	}
-	pub fn ctdata_data<A, B>(&mut self, dest: impl AsRef<Path>, source: impl AsRef<[u8]>) {}
+	pub fn ctdata_data<A, B>(&mut self, _dest: impl AsRef<Path>, _source: impl AsRef<[u8]>) {}
 }
 
 impl Default for Builder {
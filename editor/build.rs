@@ -1,16 +1,38 @@
-// use std::path::Path;
+use std::path::Path;
+use std::{env, fs};
 
 fn main() {
+	rkpk::build::logger::init_from_env();
+	println!("cargo:rerun-if-env-changed=RKPK_DEBUG_IMAGES");
 	// rkpk::build::auto_make(Path::new("src/assets/graph/"));
 	let mut builder = asset::build::Builder::new();
 	// builder.bundle_path("assets", "src/assets/").unwrap();
 	builder
 		.bundle_data("generated.txt", br#"data generated at build time"#)
 		.unwrap();
-	let mut packer = rkpk::build::Packer::new();
+	// reuse the last build's placements so adding a sprite doesn't move the rest
+	let out_dir = env::var("OUT_DIR").unwrap();
+	let previous_path = Path::new(&out_dir).join("graph.meta");
+	let previous = fs::read(&previous_path)
+		.ok()
+		.and_then(|v| rkpk::common::AtlasMeta::from_bytes(&v).ok());
+	let mut packer = rkpk::build::Packer::with_options(rkpk::build::PackOptions {
+		trim: true,
+		mip_levels: 2,
+		previous,
+		debug_images: env::var_os("RKPK_DEBUG_IMAGES").map(Into::into),
+		premultiply: true,
+		..Default::default()
+	});
 	packer.add_dir("src/assets/graph/").unwrap();
-	packer
+	let meta = packer
 		.save_build_info("assets/graph", "graph", &mut builder)
 		.unwrap();
+	fs::write(previous_path, meta.to_bytes().unwrap()).unwrap();
+	fs::write(
+		Path::new(&out_dir).join("graph.report.json"),
+		packer.report().to_json().unwrap(),
+	)
+	.unwrap();
 	builder.build("bundle.w64").unwrap();
 }
@@ -0,0 +1,88 @@
+//! playing animations from the atlas
+
+use rkpk::common::{AnimationMeta, SpriteMeta};
+use rkpk::runtime::Atlas;
+
+use crate::ecs::{Component, UpdateInfo};
+use crate::render::{RenderContext, Vert2};
+
+/// steps through a clip of an image's frames and draws the current one
+#[derive(Debug, Clone)]
+pub struct AnimationPlayer {
+	/// every frame of the animated image
+	frames: Vec<SpriteMeta>,
+	clip: AnimationMeta,
+	/// seconds since the clip started
+	time: f64,
+	/// multiplier for `UpdateInfo::delta_time`
+	pub speed: f64,
+	pub paused: bool,
+	pub position: Vert2,
+	pub origin: Vert2,
+	pub scale: Vert2,
+	pub col: [u8; 4],
+}
+
+impl AnimationPlayer {
+	pub fn new(frames: Vec<SpriteMeta>, clip: AnimationMeta) -> Self {
+		Self {
+			frames,
+			clip,
+			time: 0.0,
+			speed: 1.0,
+			paused: false,
+			position: [0.0, 0.0],
+			origin: [0.0, 0.0],
+			scale: [1.0, 1.0],
+			col: [0xFF; 4],
+		}
+	}
+	/// player for a clip in the atlas, `None` if the image or clip is missing
+	pub fn from_atlas(
+		atlas: &Atlas,
+		group: &str,
+		layer: &str,
+		image: &str,
+		clip: &str,
+	) -> Option<Self> {
+		let frames = atlas.layer(group, layer)?.images.get(image)?.clone();
+		let clip = atlas.animation(group, layer, image, clip)?.clone();
+		Some(Self::new(frames, clip))
+	}
+	/// switch to another clip of the same image and start it from the
+	/// beginning
+	pub fn play(&mut self, clip: AnimationMeta) {
+		self.clip = clip;
+		self.time = 0.0;
+	}
+	pub fn restart(&mut self) {
+		self.time = 0.0;
+	}
+	pub fn finished(&self) -> bool {
+		self.clip.finished(self.time)
+	}
+	/// frame that's currently shown
+	pub fn sprite(&self) -> Option<&SpriteMeta> {
+		self.frames.get(self.clip.frame_at(self.time)?)
+	}
+}
+
+impl Component for AnimationPlayer {
+	fn update(&mut self, info: &UpdateInfo) {
+		if !self.paused {
+			self.time += info.delta_time * self.speed;
+		}
+	}
+	fn render(&self, context: &mut RenderContext) {
+		if let Some(sprite) = self.sprite() {
+			context.sprite(
+				self.position,
+				self.origin,
+				self.scale,
+				0.0,
+				sprite,
+				self.col,
+			);
+		}
+	}
+}
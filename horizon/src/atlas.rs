@@ -0,0 +1,632 @@
+//! atlas pages on the gpu
+use std::iter;
+
+use rkpk::common::{
+	Companion, CompositeImage, ImagePos, PageMeta, RectWH, RectXYWH, RkPkResult, SpriteMeta,
+};
+use rkpk::format::PixelFormat;
+use rkpk::rectpack2d::empty_spaces::EmptySpaces;
+use rkpk::rectpack2d::PackingRoot;
+use wgpu::util::DeviceExt;
+
+/// size of the page, the shader uses it to turn pixel uvs into texture
+/// coordinates
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct PageUniform {
+	size: [f32; 2],
+	/// 1 if the texture only has one channel that should be used as alpha
+	mask: u32,
+	/// 1 if the texture holds indices into the palette page
+	palette: u32,
+	/// 1 if the texture holds distance fields
+	sdf: u32,
+	_padding: u32,
+}
+
+/// what the texels of a page mean
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PageKind {
+	Color,
+	Palette,
+	DistanceField,
+}
+
+/// a single atlas page with everything needed to draw from it
+pub struct AtlasPage {
+	pub texture: wgpu::Texture,
+	pub format: wgpu::TextureFormat,
+	pub size: [u32; 2],
+	pub bind_group: wgpu::BindGroup,
+	/// normal and emissive maps of the frames on this page, see
+	/// `companion_bind_group`
+	pub companion_bind_group: Option<wgpu::BindGroup>,
+}
+
+impl AtlasPage {
+	pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+		device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			label: Some("AtlasBindGroupLayout"),
+			entries: &[
+				wgpu::BindGroupLayoutEntry {
+					binding: 0,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Texture {
+						multisampled: false,
+						view_dimension: wgpu::TextureViewDimension::D2,
+						sample_type: wgpu::TextureSampleType::Float { filterable: true },
+					},
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 1,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 2,
+					visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Buffer {
+						ty: wgpu::BufferBindingType::Uniform,
+						has_dynamic_offset: false,
+						min_binding_size: None,
+					},
+					count: None,
+				},
+			],
+		})
+	}
+	/// texture format a page can be uploaded as without decoding it first,
+	/// distance fields aren't colours so they skip the srgb conversion
+	fn texture_format(
+		format: PixelFormat,
+		linear: bool,
+		features: wgpu::Features,
+	) -> Option<wgpu::TextureFormat> {
+		use wgpu::TextureFormat as T;
+		let bc = features.contains(wgpu::Features::TEXTURE_COMPRESSION_BC);
+		let pick = |srgb, unorm| Some(if linear { unorm } else { srgb });
+		match format {
+			PixelFormat::R8 | PixelFormat::Palette => Some(T::R8Unorm),
+			PixelFormat::Bc1 if bc => pick(T::Bc1RgbaUnormSrgb, T::Bc1RgbaUnorm),
+			PixelFormat::Bc3 if bc => pick(T::Bc3RgbaUnormSrgb, T::Bc3RgbaUnorm),
+			PixelFormat::Bc7 if bc => pick(T::Bc7RgbaUnormSrgb, T::Bc7RgbaUnorm),
+			_ => None,
+		}
+	}
+	/// upload a packed page and its mip levels, formats the device can't use
+	/// get decoded to rgba
+	pub fn new(
+		device: &wgpu::Device,
+		queue: &wgpu::Queue,
+		layout: &wgpu::BindGroupLayout,
+		sampler: &wgpu::Sampler,
+		meta: &PageMeta,
+		levels: &[Vec<u8>],
+	) -> RkPkResult<Self> {
+		let size = [meta.size.w as u32, meta.size.h as u32];
+		let level_size = |i: usize| [(size[0] >> i).max(1), (size[1] >> i).max(1)];
+		let kind = if meta.format == PixelFormat::Palette {
+			PageKind::Palette
+		} else if meta.distance_field {
+			PageKind::DistanceField
+		} else {
+			PageKind::Color
+		};
+		// normals and distances aren't colours
+		let linear = kind == PageKind::DistanceField || meta.companion == Some(Companion::Normal);
+		let (format, levels) = match Self::texture_format(meta.format, linear, device.features()) {
+			Some(format) => (format, levels.to_vec()),
+			None => {
+				if meta.format != PixelFormat::Rgba8 {
+					log::debug!("decoding {:?} atlas page", meta.format);
+				}
+				let levels = levels
+					.iter()
+					.enumerate()
+					.map(|(i, level)| {
+						let [w, h] = level_size(i);
+						let size = RectWH::new(w as ImagePos, h as ImagePos);
+						Ok(meta.format.decode(size, level)?.data)
+					})
+					.collect::<RkPkResult<Vec<_>>>()?;
+				let format = if linear {
+					wgpu::TextureFormat::Rgba8Unorm
+				} else {
+					wgpu::TextureFormat::Rgba8UnormSrgb
+				};
+				(format, levels)
+			}
+		};
+		let page = Self::create(
+			device,
+			layout,
+			sampler,
+			size,
+			levels.len() as u32,
+			format,
+			kind,
+		);
+		for (i, level) in levels.iter().enumerate() {
+			page.write(queue, i as u32, [0, 0], level_size(i), level);
+		}
+		Ok(page)
+	}
+	/// a fully transparent page
+	pub fn blank(
+		device: &wgpu::Device,
+		layout: &wgpu::BindGroupLayout,
+		sampler: &wgpu::Sampler,
+		size: [u32; 2],
+		mip_level_count: u32,
+		format: wgpu::TextureFormat,
+	) -> Self {
+		Self::create(
+			device,
+			layout,
+			sampler,
+			size,
+			mip_level_count,
+			format,
+			PageKind::Color,
+		)
+	}
+	fn create(
+		device: &wgpu::Device,
+		layout: &wgpu::BindGroupLayout,
+		sampler: &wgpu::Sampler,
+		size: [u32; 2],
+		mip_level_count: u32,
+		format: wgpu::TextureFormat,
+		kind: PageKind,
+	) -> Self {
+		let palette = kind == PageKind::Palette;
+		let texture = device.create_texture(&wgpu::TextureDescriptor {
+			label: Some("AtlasTexture"),
+			size: wgpu::Extent3d {
+				width: size[0],
+				height: size[1],
+				depth_or_array_layers: 1,
+			},
+			mip_level_count,
+			sample_count: 1,
+			dimension: wgpu::TextureDimension::D2,
+			format,
+			usage: wgpu::TextureUsages::TEXTURE_BINDING
+				| wgpu::TextureUsages::COPY_SRC
+				| wgpu::TextureUsages::COPY_DST,
+		});
+		let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+		let uniform = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+			label: Some("AtlasPageBuffer"),
+			contents: bytemuck::cast_slice(&[PageUniform {
+				size: [size[0] as f32, size[1] as f32],
+				mask: (format == wgpu::TextureFormat::R8Unorm && !palette) as u32,
+				palette: palette as u32,
+				sdf: (kind == PageKind::DistanceField) as u32,
+				_padding: 0,
+			}]),
+			usage: wgpu::BufferUsages::UNIFORM,
+		});
+		let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("AtlasBindGroup"),
+			layout,
+			entries: &[
+				wgpu::BindGroupEntry {
+					binding: 0,
+					resource: wgpu::BindingResource::TextureView(&view),
+				},
+				wgpu::BindGroupEntry {
+					binding: 1,
+					resource: wgpu::BindingResource::Sampler(sampler),
+				},
+				wgpu::BindGroupEntry {
+					binding: 2,
+					resource: uniform.as_entire_binding(),
+				},
+			],
+		});
+		Self {
+			texture,
+			format,
+			size,
+			bind_group,
+			companion_bind_group: None,
+		}
+	}
+	pub fn palette_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+		device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			label: Some("PaletteBindGroupLayout"),
+			entries: &[wgpu::BindGroupLayoutEntry {
+				binding: 0,
+				visibility: wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Texture {
+					multisampled: false,
+					view_dimension: wgpu::TextureViewDimension::D2,
+					sample_type: wgpu::TextureSampleType::Float { filterable: true },
+				},
+				count: None,
+			}],
+		})
+	}
+	pub fn companion_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+		let texture = |binding| wgpu::BindGroupLayoutEntry {
+			binding,
+			visibility: wgpu::ShaderStages::FRAGMENT,
+			ty: wgpu::BindingType::Texture {
+				multisampled: false,
+				view_dimension: wgpu::TextureViewDimension::D2,
+				sample_type: wgpu::TextureSampleType::Float { filterable: true },
+			},
+			count: None,
+		};
+		device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			label: Some("CompanionBindGroupLayout"),
+			entries: &[texture(0), texture(1)],
+		})
+	}
+	/// bind a normal and an emissive page, sampled with the same uvs as the
+	/// page they belong to
+	pub fn companion_bind_group(
+		device: &wgpu::Device,
+		layout: &wgpu::BindGroupLayout,
+		normal: &AtlasPage,
+		emissive: &AtlasPage,
+	) -> wgpu::BindGroup {
+		let normal = normal
+			.texture
+			.create_view(&wgpu::TextureViewDescriptor::default());
+		let emissive = emissive
+			.texture
+			.create_view(&wgpu::TextureViewDescriptor::default());
+		device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("CompanionBindGroup"),
+			layout,
+			entries: &[
+				wgpu::BindGroupEntry {
+					binding: 0,
+					resource: wgpu::BindingResource::TextureView(&normal),
+				},
+				wgpu::BindGroupEntry {
+					binding: 1,
+					resource: wgpu::BindingResource::TextureView(&emissive),
+				},
+			],
+		})
+	}
+	/// bind this page as the palette page that palette indices look up into
+	pub fn palette_bind_group(
+		&self,
+		device: &wgpu::Device,
+		layout: &wgpu::BindGroupLayout,
+	) -> wgpu::BindGroup {
+		let view = self
+			.texture
+			.create_view(&wgpu::TextureViewDescriptor::default());
+		device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("PaletteBindGroup"),
+			layout,
+			entries: &[wgpu::BindGroupEntry {
+				binding: 0,
+				resource: wgpu::BindingResource::TextureView(&view),
+			}],
+		})
+	}
+	/// overwrite a region of one of the page's levels, compressed formats
+	/// have to write whole blocks
+	pub fn write(
+		&self,
+		queue: &wgpu::Queue,
+		mip_level: u32,
+		origin: [u32; 2],
+		size: [u32; 2],
+		data: &[u8],
+	) {
+		let info = self.format.describe();
+		let (block_w, block_h) = (
+			info.block_dimensions.0 as u32,
+			info.block_dimensions.1 as u32,
+		);
+		let blocks = [size[0].div_ceil(block_w), size[1].div_ceil(block_h)];
+		queue.write_texture(
+			wgpu::ImageCopyTexture {
+				texture: &self.texture,
+				mip_level,
+				origin: wgpu::Origin3d {
+					x: origin[0],
+					y: origin[1],
+					z: 0,
+				},
+				aspect: wgpu::TextureAspect::All,
+			},
+			data,
+			wgpu::ImageDataLayout {
+				offset: 0,
+				bytes_per_row: std::num::NonZeroU32::new(blocks[0] * info.block_size as u32),
+				rows_per_image: std::num::NonZeroU32::new(blocks[1]),
+			},
+			wgpu::Extent3d {
+				width: blocks[0] * block_w,
+				height: blocks[1] * block_h,
+				depth_or_array_layers: 1,
+			},
+		);
+	}
+}
+
+/// transparent pixels kept around every image in a `DynamicAtlas` so linear
+/// filtering doesn't pick up its neighbours
+const PADDING: ImagePos = 1;
+
+/// `rect` with the padding around it
+fn padded(rect: RectXYWH) -> RectXYWH {
+	RectXYWH::new(
+		rect.x - PADDING,
+		rect.y - PADDING,
+		rect.w + PADDING * 2,
+		rect.h + PADDING * 2,
+	)
+}
+
+/// an atlas page that images get added to and removed from while running, for
+/// anything that can't be packed at build time
+///
+/// this only keeps track of where things go, the pixels get uploaded when the
+/// state flushes it before drawing
+#[derive(Debug)]
+pub struct DynamicAtlas {
+	/// index of the page that draws from this atlas
+	page: usize,
+	size: RectWH,
+	max_size: ImagePos,
+	spaces: EmptySpaces,
+	/// images that haven't been freed yet
+	live: usize,
+	/// pixels that still have to be written into the page
+	uploads: Vec<(RectXYWH, Vec<u8>)>,
+	/// premultiply inserted images to match the packed pages
+	premultiplied: bool,
+}
+
+impl DynamicAtlas {
+	pub fn new(page: usize, size: RectWH, max_size: ImagePos, premultiplied: bool) -> Self {
+		let mut spaces = EmptySpaces::new();
+		spaces.reset(size);
+		Self {
+			page,
+			size,
+			max_size,
+			spaces,
+			live: 0,
+			uploads: vec![],
+			premultiplied,
+		}
+	}
+	pub fn page(&self) -> usize {
+		self.page
+	}
+	pub fn size(&self) -> RectWH {
+		self.size
+	}
+	/// find room for an image and queue it for uploading, growing the page if
+	/// it's full
+	pub fn insert(&mut self, image: &image::RgbaImage) -> Option<SpriteMeta> {
+		let w = ImagePos::try_from(image.width()).ok()?;
+		let h = ImagePos::try_from(image.height()).ok()?;
+		let mut sprite = SpriteMeta {
+			page: self.page,
+			size: RectWH::new(w, h),
+			..Default::default()
+		};
+		if w == 0 || h == 0 {
+			return Some(sprite);
+		}
+		let space = RectXYWH::new(
+			0,
+			0,
+			w.checked_add(PADDING * 2)?,
+			h.checked_add(PADDING * 2)?,
+		);
+		let space = loop {
+			if let Some(rect) = self.spaces.insert_best_fit(space) {
+				break rect;
+			}
+			// double the shorter side until it fits
+			let mut size = self.size;
+			if size.w <= size.h {
+				size.w = size.w.saturating_mul(2).min(self.max_size);
+			} else {
+				size.h = size.h.saturating_mul(2).min(self.max_size);
+			}
+			if size == self.size {
+				return None;
+			}
+			self.spaces.grow(self.size, size);
+			self.size = size;
+		};
+		sprite.rect = RectXYWH::new(space.x + PADDING, space.y + PADDING, w, h);
+		self.live += 1;
+		let mut image = CompositeImage::from(image.clone());
+		if self.premultiplied {
+			image.premultiply_srgb();
+		}
+		self.uploads.push((sprite.rect, image.data));
+		Some(sprite)
+	}
+	/// give an image's space back, its pixels get cleared with the next flush
+	/// so nothing can sample them through the padding of a later image
+	pub fn free(&mut self, sprite: &SpriteMeta) {
+		if sprite.rect.area() == 0 {
+			return;
+		}
+		self.uploads.retain(|(rect, _)| *rect != sprite.rect);
+		let space = padded(sprite.rect);
+		self.uploads
+			.push((space, vec![0; space.area() as usize * 4]));
+		self.live -= 1;
+		if self.live == 0 {
+			// the spaces freed rects leave behind don't always merge back
+			// together, so start over once nothing is left
+			self.spaces.reset(self.size);
+		} else {
+			self.spaces.free(space);
+		}
+	}
+	/// resize the gpu page if the atlas grew and upload everything that was
+	/// inserted since the last flush
+	pub fn flush(
+		&mut self,
+		device: &wgpu::Device,
+		queue: &wgpu::Queue,
+		layout: &wgpu::BindGroupLayout,
+		sampler: &wgpu::Sampler,
+		pages: &mut [AtlasPage],
+	) {
+		let size = [self.size.w as u32, self.size.h as u32];
+		let old = &pages[self.page];
+		if old.size != size {
+			log::debug!("dynamic atlas grew {:?} -> {:?}", old.size, size);
+			let new = AtlasPage::blank(
+				device,
+				layout,
+				sampler,
+				size,
+				1,
+				wgpu::TextureFormat::Rgba8UnormSrgb,
+			);
+			let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+				label: Some("DynamicAtlasGrowEncoder"),
+			});
+			encoder.copy_texture_to_texture(
+				old.texture.as_image_copy(),
+				new.texture.as_image_copy(),
+				wgpu::Extent3d {
+					width: old.size[0],
+					height: old.size[1],
+					depth_or_array_layers: 1,
+				},
+			);
+			// submitted now so the copy can't overwrite the uploads below
+			queue.submit(iter::once(encoder.finish()));
+			pages[self.page] = new;
+		}
+		for (rect, data) in self.uploads.drain(..) {
+			pages[self.page].write(
+				queue,
+				0,
+				[rect.x as u32, rect.y as u32],
+				[rect.w as u32, rect.h as u32],
+				&data,
+			);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn image(w: u32, h: u32) -> image::RgbaImage {
+		image::RgbaImage::from_pixel(w, h, image::Rgba([0xFF; 4]))
+	}
+
+	fn overlaps(a: RectXYWH, b: RectXYWH) -> bool {
+		a.x < b.x + b.w && b.x < a.x + a.w && a.y < b.y + b.h && b.y < a.y + a.h
+	}
+
+	/// every image is inside the page and nothing is inside another's padding
+	fn check(atlas: &DynamicAtlas, sprites: &[SpriteMeta]) {
+		for (i, a) in sprites.iter().enumerate() {
+			let space = padded(a.rect);
+			assert!(
+				space.x + space.w <= atlas.size.w && space.y + space.h <= atlas.size.h,
+				"{:?} is outside of {:?}",
+				a.rect,
+				atlas.size
+			);
+			for b in &sprites[..i] {
+				assert!(!overlaps(space, b.rect), "{:?} {:?}", a.rect, b.rect);
+			}
+		}
+	}
+
+	#[test]
+	fn images_get_padding() {
+		let mut atlas = DynamicAtlas::new(0, RectWH::new(32, 32), 32, false);
+		let sprites = (0..9)
+			.map(|_| atlas.insert(&image(8, 8)).unwrap())
+			.collect::<Vec<_>>();
+		check(&atlas, &sprites);
+		assert!(sprites
+			.iter()
+			.all(|v| v.rect.x >= PADDING && v.rect.y >= PADDING));
+		// 10x10 with the padding, so a tenth doesn't fit
+		assert!(atlas.insert(&image(8, 8)).is_none());
+		assert_eq!(atlas.uploads.len(), 9);
+	}
+
+	#[test]
+	fn freed_space_is_reused_and_cleared() {
+		let mut atlas = DynamicAtlas::new(0, RectWH::new(32, 32), 32, false);
+		let a = atlas.insert(&image(6, 4)).unwrap();
+		let b = atlas.insert(&image(6, 4)).unwrap();
+		atlas.uploads.clear();
+		atlas.free(&a);
+		let c = atlas.insert(&image(6, 4)).unwrap();
+		assert_eq!(c.rect, a.rect);
+		check(&atlas, &[b, c]);
+		// the old pixels and padding get cleared before the new ones go in
+		assert_eq!(atlas.uploads.len(), 2);
+		assert_eq!(atlas.uploads[0].0, padded(a.rect));
+		assert!(atlas.uploads[0].1.iter().all(|&v| v == 0));
+		assert_eq!(atlas.uploads[1].0, c.rect);
+		// freeing before the flush drops the upload
+		atlas.free(&c);
+		assert!(atlas.uploads.iter().all(|v| v.0 != c.rect));
+	}
+
+	#[test]
+	fn grows_when_full() {
+		let mut atlas = DynamicAtlas::new(0, RectWH::new(8, 8), 16, false);
+		let a = atlas.insert(&image(6, 6)).unwrap();
+		assert_eq!(atlas.size(), RectWH::new(8, 8));
+		let b = atlas.insert(&image(6, 6)).unwrap();
+		assert_eq!(atlas.size(), RectWH::new(16, 8));
+		let c = atlas.insert(&image(14, 6)).unwrap();
+		assert_eq!(atlas.size(), RectWH::new(16, 16));
+		check(&atlas, &[a, b, c]);
+		// the page can't get any bigger
+		assert!(atlas.insert(&image(6, 6)).is_none());
+		assert!(atlas.insert(&image(16, 1)).is_none());
+		assert_eq!(atlas.size(), RectWH::new(16, 16));
+	}
+
+	#[test]
+	fn churn_never_overlaps() {
+		let mut atlas = DynamicAtlas::new(0, RectWH::new(64, 64), 64, false);
+		let mut live = vec![];
+		let mut seed = 1u32;
+		let mut next = || {
+			seed ^= seed << 13;
+			seed ^= seed >> 17;
+			seed ^= seed << 5;
+			seed
+		};
+		for _ in 0..500 {
+			if next().is_multiple_of(3) && !live.is_empty() {
+				let i = next() as usize % live.len();
+				atlas.free(&live.swap_remove(i));
+			} else if let Some(sprite) = atlas.insert(&image(next() % 12 + 1, next() % 12 + 1)) {
+				live.push(sprite);
+			}
+			check(&atlas, &live);
+		}
+		// once everything is gone the whole page is free again
+		for sprite in live.drain(..) {
+			atlas.free(&sprite);
+		}
+		let whole = atlas.insert(&image(62, 62)).unwrap();
+		assert_eq!(whole.rect, RectXYWH::new(1, 1, 62, 62));
+	}
+}
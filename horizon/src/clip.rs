@@ -0,0 +1,219 @@
+//! cutting geometry down to what's inside of a clip polygon
+//!
+//! polygons are any simple polygon going either way round, everything that
+//! comes out goes clockwise on screen
+
+use crate::render::Vert2;
+
+/// anything with less area than this is a sliver left over from an edge
+const EPSILON: f32 = 1e-4;
+
+fn cross(o: Vert2, a: Vert2, b: Vert2) -> f32 {
+	(a[0] - o[0]) * (b[1] - o[1]) - (a[1] - o[1]) * (b[0] - o[0])
+}
+
+/// twice the area, positive if the points go clockwise on screen
+fn signed_area(poly: &[Vert2]) -> f32 {
+	let mut res = 0.0;
+	for (i, a) in poly.iter().enumerate() {
+		let b = poly[(i + 1) % poly.len()];
+		res += a[0] * b[1] - b[0] * a[1];
+	}
+	res
+}
+
+/// `poly` going clockwise on screen
+fn clockwise(poly: &[Vert2]) -> Vec<Vert2> {
+	let mut res = poly.to_vec();
+	if signed_area(poly) < 0.0 {
+		res.reverse();
+	}
+	res
+}
+
+fn is_convex(poly: &[Vert2]) -> bool {
+	(0..poly.len()).all(|i| {
+		let a = poly[i];
+		let b = poly[(i + 1) % poly.len()];
+		let c = poly[(i + 2) % poly.len()];
+		cross(a, b, c) >= 0.0
+	})
+}
+
+/// split `poly` into convex pieces, itself if it already is
+pub fn convex_pieces(poly: &[Vert2]) -> Vec<Vec<Vert2>> {
+	if poly.len() < 3 {
+		return vec![];
+	}
+	let poly = clockwise(poly);
+	if is_convex(&poly) {
+		return vec![poly];
+	}
+	polygon2::triangulate(&poly)
+		.chunks_exact(3)
+		.map(|v| vec![poly[v[0]], poly[v[1]], poly[v[2]]])
+		.collect()
+}
+
+/// sutherland-hodgman, both have to be convex and clockwise
+fn clip_convex(subject: &[Vert2], clip: &[Vert2]) -> Vec<Vert2> {
+	let mut res = subject.to_vec();
+	for (i, &a) in clip.iter().enumerate() {
+		let b = clip[(i + 1) % clip.len()];
+		let input = std::mem::take(&mut res);
+		for (j, &p) in input.iter().enumerate() {
+			let q = input[(j + 1) % input.len()];
+			let dp = cross(a, b, p);
+			let dq = cross(a, b, q);
+			if dp >= 0.0 {
+				res.push(p);
+			}
+			if (dp >= 0.0) != (dq >= 0.0) {
+				let t = dp / (dp - dq);
+				res.push([p[0] + (q[0] - p[0]) * t, p[1] + (q[1] - p[1]) * t]);
+			}
+		}
+		if res.is_empty() {
+			break;
+		}
+	}
+	res
+}
+
+/// the part of `subject` inside `clip` as convex pieces, ready to be drawn
+/// as fans
+pub fn clip(subject: &[Vert2], clip: &[Vert2]) -> Vec<Vec<Vert2>> {
+	let clips = convex_pieces(clip);
+	let mut res = vec![];
+	for piece in convex_pieces(subject) {
+		for clip in &clips {
+			let clipped = clip_convex(&piece, clip);
+			if clipped.len() >= 3 && signed_area(&clipped) > EPSILON {
+				res.push(clipped);
+			}
+		}
+	}
+	res
+}
+
+fn total_area(polys: &[Vec<Vert2>]) -> f32 {
+	polys.iter().map(|v| signed_area(v).abs()).sum()
+}
+
+/// the part of `subject` inside `clip` as whole polygons, as few of them as
+/// it can manage
+pub fn intersection(subject: &[Vert2], clip: &[Vert2]) -> Vec<Vec<Vert2>> {
+	let pieces = self::clip(subject, clip);
+	if pieces.len() <= 1 {
+		return pieces;
+	}
+	// greiner-hormann joins the pieces back up, but it can't be trusted with
+	// edges that don't cross or some concave shapes, so only use it if it
+	// covers the same area
+	let joined = polygon2::intersection(subject, clip);
+	let area = total_area(&pieces);
+	if (total_area(&joined) - area).abs() <= area * 1e-3 {
+		joined.iter().map(|v| clockwise(v)).collect()
+	} else {
+		pieces
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn rect(x0: f32, y0: f32, x1: f32, y1: f32) -> Vec<Vert2> {
+		vec![[x0, y0], [x1, y0], [x1, y1], [x0, y1]]
+	}
+
+	/// a 10x10 window with a 4x8 notch cut up into it from the bottom
+	fn u_shape() -> Vec<Vert2> {
+		vec![
+			[0.0, 10.0],
+			[0.0, 0.0],
+			[10.0, 0.0],
+			[10.0, 10.0],
+			[7.0, 10.0],
+			[7.0, 2.0],
+			[3.0, 2.0],
+			[3.0, 10.0],
+		]
+	}
+
+	fn area(polys: &[Vec<Vert2>]) -> f32 {
+		total_area(polys) / 2.0
+	}
+
+	fn assert_area(polys: &[Vec<Vert2>], expected: f32) {
+		let area = area(polys);
+		assert!(
+			(area - expected).abs() < 1e-3,
+			"{} isn't {}",
+			area,
+			expected
+		);
+	}
+
+	#[test]
+	fn rects() {
+		let window = rect(0.0, 0.0, 10.0, 10.0);
+		let cases = [
+			(rect(2.0, 2.0, 5.0, 5.0), 9.0),
+			(rect(0.0, 0.0, 10.0, 10.0), 100.0),
+			(rect(5.0, 5.0, 15.0, 15.0), 25.0),
+			(rect(-5.0, -5.0, 15.0, 15.0), 100.0),
+			(rect(20.0, 20.0, 25.0, 25.0), 0.0),
+			(rect(10.0, 0.0, 15.0, 5.0), 0.0),
+		];
+		for (subject, expected) in cases {
+			let pieces = clip(&subject, &window);
+			assert_area(&pieces, expected);
+			let joined = intersection(&subject, &window);
+			assert_area(&joined, expected);
+			assert!(joined.len() <= 1);
+			assert!(joined.iter().all(|v| signed_area(v) > 0.0));
+		}
+	}
+
+	#[test]
+	fn concave_clips_keep_every_piece() {
+		for window in [u_shape(), u_shape().into_iter().rev().collect()] {
+			// a bar across the arms of the u comes out as two pieces
+			let bar = rect(-1.0, 4.0, 11.0, 6.0);
+			let joined = intersection(&bar, &window);
+			assert_eq!(joined.len(), 2);
+			assert_area(&joined, 12.0);
+			assert_area(&clip(&bar, &window), 12.0);
+			// and drawing through them is the same as drawing through the u
+			let sprite = rect(2.0, 0.0, 8.0, 8.0);
+			let through = joined
+				.iter()
+				.flat_map(|v| clip(&sprite, v))
+				.collect::<Vec<_>>();
+			assert_area(&through, 4.0);
+			assert_area(&clip(&sprite, &window), 24.0);
+			assert_area(&clip(&rect(-1.0, 4.0, 11.0, 8.0), &window), 24.0);
+		}
+	}
+
+	#[test]
+	fn concave_subjects() {
+		let window = rect(0.0, 5.0, 10.0, 10.0);
+		assert_area(&clip(&u_shape(), &window), 30.0);
+		assert_area(&intersection(&u_shape(), &window), 30.0);
+		assert_area(&convex_pieces(&u_shape()), 68.0);
+	}
+
+	#[test]
+	fn pieces_are_convex_and_clockwise() {
+		for window in [u_shape(), u_shape().into_iter().rev().collect()] {
+			let pieces = clip(&rect(-1.0, 1.0, 11.0, 9.0), &window);
+			assert_area(&pieces, 52.0);
+			for piece in &pieces {
+				assert!(is_convex(piece), "{:?}", piece);
+				assert!(signed_area(piece) > 0.0, "{:?}", piece);
+			}
+		}
+	}
+}
@@ -71,7 +71,7 @@ impl egui_util::EguiComponent for DebuggerState {
 					.collect();
 				let mut lines = vec![];
 				let mut real_time = self.sample_time;
-				if items.len() > 0 {
+				if !items.is_empty() {
 					let line_count = items.iter().map(|v| v.1 .1.len()).max().unwrap_or(0);
 					for i in 0..line_count {
 						let mut res = vec![];
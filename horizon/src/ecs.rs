@@ -48,6 +48,9 @@ impl Entity {
 		}
 		res
 	}
+	pub fn add_component(&mut self, component: Rc<RefCell<dyn Component>>) {
+		self.components.push(component);
+	}
 }
 
 impl Component for Entity {
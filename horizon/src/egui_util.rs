@@ -7,10 +7,7 @@ pub struct PanelState {
 
 impl PanelState {
 	pub fn new(side: bool) -> Self {
-		Self {
-			open: false,
-			side: side,
-		}
+		Self { open: false, side }
 	}
 	pub fn is_open(&self) -> bool {
 		self.open
@@ -6,15 +6,24 @@ use winit::event;
 use winit::event_loop;
 use winit::window;
 
+mod animation;
+mod atlas;
+mod clip;
 mod debugger;
 mod ecs;
 mod egui_util;
+pub mod math;
 mod render;
 mod state;
 
 /// game-specific info goes here
 pub struct StartInfo {
 	pub integer_mode: bool,
+	/// packed atlas metadata
+	pub atlas_meta: rkpk::common::AtlasMeta,
+	/// encoded images of every atlas page in page order, each one starting
+	/// with the full size image followed by its mip levels
+	pub atlas_pages: Vec<Vec<Vec<u8>>>,
 }
 
 /// start horizon
@@ -61,23 +70,19 @@ pub async fn start(info: StartInfo) {
 				event::Event::WindowEvent {
 					ref event,
 					window_id,
-				} if window_id == window.id() => {
-					if !state.input(event) {
-						match event {
-							event::WindowEvent::CloseRequested => {
-								log::info!("quit requested");
-								*control_flow = event_loop::ControlFlow::Exit
-							}
-							event::WindowEvent::Resized(physical_size) => {
-								state.resize(*physical_size);
-							}
-							event::WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-								state.resize(**new_inner_size);
-							}
-							_ => {}
-						}
+				} if window_id == window.id() && !state.input(event) => match event {
+					event::WindowEvent::CloseRequested => {
+						log::info!("quit requested");
+						*control_flow = event_loop::ControlFlow::Exit
 					}
-				}
+					event::WindowEvent::Resized(physical_size) => {
+						state.resize(*physical_size);
+					}
+					event::WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+						state.resize(**new_inner_size);
+					}
+					_ => {}
+				},
 				_ => {}
 			}
 		}
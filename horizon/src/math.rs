@@ -0,0 +1,212 @@
+//! bits of geometry that don't belong to anything in particular
+
+use crate::render::Vert2;
+
+type Vec2 = [f64; 2];
+
+fn sub(a: Vec2, b: Vec2) -> Vec2 {
+	[a[0] - b[0], a[1] - b[1]]
+}
+
+fn cross(a: Vec2, b: Vec2) -> f64 {
+	a[0] * b[1] - a[1] * b[0]
+}
+
+fn dot(a: Vec2, b: Vec2) -> f64 {
+	a[0] * b[0] + a[1] * b[1]
+}
+
+/// how far `v` is from being in `0..=1`
+fn outside_unit(v: f64) -> f64 {
+	(-v).max(v - 1.0).max(0.0)
+}
+
+/// the point `st` of the way across a quad with corners clockwise from the
+/// top left, `s` goes from the first corner to the second and `t` from the
+/// first to the last
+pub fn bilinear(quad: [Vert2; 4], st: Vert2) -> Vert2 {
+	let lerp = |a: Vert2, b: Vert2, t: f32| [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t];
+	lerp(
+		lerp(quad[0], quad[1], st[0]),
+		lerp(quad[3], quad[2], st[0]),
+		st[1],
+	)
+}
+
+/// the `st` that `bilinear` turns into `p`
+///
+/// any quad works, including rotated and flipped ones. concave quads fold
+/// over themselves so some points have two `st`, the one closest to the
+/// inside of the quad wins and if that's a tie either can come back. quads
+/// with no area get `p` projected onto their longest side. points outside of
+/// the quad extrapolate and always come out finite
+pub fn inverse_bilinear(quad: [Vert2; 4], p: Vert2) -> Vert2 {
+	let [a, b, c, d] = quad.map(|v| [v[0] as f64, v[1] as f64]);
+	let p = [p[0] as f64, p[1] as f64];
+	let e = sub(b, a);
+	let f = sub(d, a);
+	let g = sub(sub(c, d), e);
+	let h = sub(p, a);
+	let scale = [e, f, sub(c, a), sub(d, b)]
+		.iter()
+		.map(|v| dot(*v, *v))
+		.fold(0.0, f64::max);
+	if scale == 0.0 {
+		return [0.0, 0.0];
+	}
+	let eps = scale * 1e-12;
+	// p = a + e s + f t + g s t, crossing out s leaves k2 t² + k1 t + k0 = 0
+	let k2 = cross(g, f);
+	let k1 = cross(e, f) + cross(h, g);
+	let k0 = cross(h, e);
+	let roots = if k2.abs() > eps {
+		// clamped so points past the fold of the quad still get the closest t
+		let disc = (k1 * k1 - 4.0 * k0 * k2).max(0.0).sqrt();
+		let q = -0.5 * (k1 + k1.signum() * disc);
+		if q == 0.0 {
+			vec![-k1 / (2.0 * k2)]
+		} else {
+			vec![q / k2, k0 / q]
+		}
+	} else if k1.abs() > eps {
+		vec![-k0 / k1]
+	} else {
+		// flat, every corner is on one line
+		return if dot(e, e) >= dot(f, f) {
+			[(dot(h, e) / dot(e, e)) as f32, 0.0]
+		} else {
+			[0.0, (dot(h, f) / dot(f, f)) as f32]
+		};
+	};
+	let mut best = (f64::INFINITY, [0.0; 2]);
+	for t in roots {
+		// the edge across the quad at this t, s is how far along it p is
+		let edge = [e[0] + g[0] * t, e[1] + g[1] * t];
+		let along = [h[0] - f[0] * t, h[1] - f[1] * t];
+		let axis = if edge[0].abs() >= edge[1].abs() { 0 } else { 1 };
+		let (s, penalty) = if edge[axis].abs() > eps.sqrt() {
+			(along[axis] / edge[axis], 0.0)
+		} else {
+			// the edge collapsed to a point, any s is right
+			(0.5, 1e-9)
+		};
+		let score = outside_unit(s) + outside_unit(t) + penalty;
+		if score < best.0 {
+			best = (score, [s, t]);
+		}
+	}
+	best.1.map(|v| v as f32)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SQUARE: [Vert2; 4] = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+
+	fn close(a: Vert2, b: Vert2, tolerance: f32) -> bool {
+		(a[0] - b[0]).abs() <= tolerance && (a[1] - b[1]).abs() <= tolerance
+	}
+
+	/// points spread over the inside of the unit square, edges included
+	fn grid() -> impl Iterator<Item = Vert2> {
+		(0..=8).flat_map(|y| (0..=8).map(move |x| [x as f32 / 8.0, y as f32 / 8.0]))
+	}
+
+	fn round_trips(quad: [Vert2; 4]) {
+		for st in grid() {
+			let p = bilinear(quad, st);
+			let res = inverse_bilinear(quad, p);
+			assert!(
+				close(res, st, 1e-4),
+				"{:?} in {:?} came back as {:?}",
+				st,
+				quad,
+				res
+			);
+		}
+	}
+
+	/// the inverse might not be unique, but it still has to land on `p`
+	fn lands_on(quad: [Vert2; 4]) {
+		for st in grid() {
+			let p = bilinear(quad, st);
+			let res = inverse_bilinear(quad, p);
+			assert!(res[0].is_finite() && res[1].is_finite());
+			assert!(
+				close(bilinear(quad, res), p, 1e-3),
+				"{:?} in {:?} came back as {:?}",
+				st,
+				quad,
+				res
+			);
+		}
+	}
+
+	#[test]
+	fn unit_square() {
+		for st in grid() {
+			assert_eq!(bilinear(SQUARE, st), st);
+			assert!(close(inverse_bilinear(SQUARE, st), st, 1e-6));
+		}
+	}
+
+	#[test]
+	fn rects_and_parallelograms() {
+		round_trips([[10.0, 20.0], [74.0, 20.0], [74.0, 52.0], [10.0, 52.0]]);
+		// flipped either way round
+		round_trips([[74.0, 20.0], [10.0, 20.0], [10.0, 52.0], [74.0, 52.0]]);
+		round_trips([[10.0, 52.0], [74.0, 52.0], [74.0, 20.0], [10.0, 20.0]]);
+		round_trips([[0.0, 0.0], [4.0, 1.0], [6.0, 5.0], [2.0, 4.0]]);
+	}
+
+	#[test]
+	fn rotated() {
+		for i in 0..16 {
+			let angle = i as f32 / 16.0 * std::f32::consts::TAU;
+			let (sin, cos) = angle.sin_cos();
+			let quad = SQUARE.map(|[x, y]| {
+				let [x, y] = [(x - 0.5) * 40.0, (y - 0.5) * 24.0];
+				[100.0 + x * cos - y * sin, -50.0 + x * sin + y * cos]
+			});
+			round_trips(quad);
+		}
+	}
+
+	#[test]
+	fn trapezoids_and_irregular() {
+		round_trips([[0.0, 0.0], [10.0, 0.0], [8.0, 6.0], [2.0, 6.0]]);
+		round_trips([[0.0, 0.0], [10.0, 2.0], [7.0, 9.0], [-1.0, 5.0]]);
+		round_trips([[3.0, 0.0], [10.0, 3.0], [7.0, 10.0], [0.0, 7.0]]);
+		// one corner pushed in past the diagonal, so it folds
+		lands_on([[0.0, 0.0], [10.0, 0.0], [4.0, 4.0], [0.0, 10.0]]);
+	}
+
+	#[test]
+	fn degenerate() {
+		// a corner on top of another, so one side is a point
+		lands_on([[0.0, 0.0], [0.0, 0.0], [10.0, 10.0], [0.0, 10.0]]);
+		lands_on([[0.0, 0.0], [10.0, 0.0], [10.0, 10.0], [10.0, 10.0]]);
+		// flat along either side
+		lands_on([[0.0, 0.0], [10.0, 0.0], [10.0, 0.0], [0.0, 0.0]]);
+		lands_on([[0.0, 0.0], [0.0, 0.0], [0.0, 10.0], [0.0, 10.0]]);
+		lands_on([[0.0, 0.0], [10.0, 5.0], [20.0, 10.0], [5.0, 2.5]]);
+		// no size at all
+		assert_eq!(inverse_bilinear([[3.0, 4.0]; 4], [3.0, 4.0]), [0.0, 0.0]);
+		assert_eq!(inverse_bilinear([[3.0, 4.0]; 4], [5.0, 1.0]), [0.0, 0.0]);
+	}
+
+	#[test]
+	fn outside_extrapolates() {
+		let quad = [[10.0, 20.0], [74.0, 20.0], [74.0, 52.0], [10.0, 52.0]];
+		for st in [[-0.5, 0.5], [1.5, 0.25], [0.5, -1.0], [2.0, 3.0]] {
+			let p = bilinear(quad, st);
+			assert!(close(inverse_bilinear(quad, p), st, 1e-4));
+		}
+		let trapezoid = [[0.0, 0.0], [10.0, 0.0], [8.0, 6.0], [2.0, 6.0]];
+		for p in [[-100.0, 3.0], [5.0, 1000.0], [1e6, -1e6]] {
+			let res = inverse_bilinear(trapezoid, p);
+			assert!(res[0].is_finite() && res[1].is_finite(), "{:?}", res);
+		}
+	}
+}
@@ -1,99 +1,228 @@
 //! rendering and cropping stuff
 
 use std::mem;
+use std::ops::Range;
 
+use rkpk::common::{FontMeta, SdfMeta, SpriteMeta};
+
+use crate::atlas::DynamicAtlas;
+use crate::clip;
+use crate::math;
 use crate::state::Vertex;
 
 pub type Vert2 = [f32; 2];
 
+/// a run of indices that all draw from the same atlas page
+#[derive(Debug, Clone)]
+pub struct Batch {
+	pub page: usize,
+	pub indices: Range<u32>,
+}
+
+/// how frames from distance field pages get drawn
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SdfStyle {
+	/// how far past the edge the outline goes, 1 is the layer's whole spread
+	pub outline: f32,
+	pub outline_col: [u8; 4],
+	/// how far past the outline the glow fades out, also in spreads
+	pub glow: f32,
+	pub glow_col: [u8; 4],
+}
+
+impl SdfStyle {
+	/// widths as they're passed to the shader, where the edge is at 0.5 and
+	/// the end of the spread at 0
+	fn widths(&self) -> [u8; 4] {
+		let width = |v: f32| (v.clamp(0.0, 1.0) * 0.5 * 255.0).round() as u8;
+		[width(self.outline), width(self.glow), 0, 0]
+	}
+}
+
+/// light for frames that have a normal map, the default lights everything
+/// evenly so frames look the same with or without one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Light {
+	/// direction towards the light, z points out of the screen
+	pub direction: [f32; 3],
+	/// how lit everything is whichever way it faces
+	pub ambient: f32,
+}
+
+impl Default for Light {
+	fn default() -> Self {
+		Self {
+			direction: [0.0; 3],
+			ambient: 1.0,
+		}
+	}
+}
+
+impl From<Light> for [f32; 4] {
+	fn from(light: Light) -> Self {
+		let [x, y, z] = light.direction;
+		[x, y, z, light.ambient]
+	}
+}
+
 #[derive(Debug)]
 pub struct RenderContext<'a> {
-	stack: Vec<Vec<Vert2>>,
+	/// sets of clip polygons that don't overlap, everything drawn gets cut
+	/// down to the last set
+	stack: Vec<Vec<Vec<Vert2>>>,
 	vertex_buffer: &'a mut ResizeBuffer<Vertex>,
 	index_buffer: &'a mut ResizeBuffer<[u16; 3]>,
+	batches: &'a mut Vec<Batch>,
+	dynamic_atlas: &'a mut DynamicAtlas,
+	/// palette page row for everything drawn from palette pages
+	palette: u32,
+	/// style of everything drawn from distance field pages
+	sdf_style: SdfStyle,
+	light: Light,
 }
 
 impl<'a> RenderContext<'a> {
 	pub fn new(
 		vertex_buffer: &'a mut ResizeBuffer<Vertex>,
 		index_buffer: &'a mut ResizeBuffer<[u16; 3]>,
+		batches: &'a mut Vec<Batch>,
+		dynamic_atlas: &'a mut DynamicAtlas,
 	) -> Self {
 		vertex_buffer.reset();
 		index_buffer.reset();
+		batches.clear();
 		Self {
 			stack: Vec::new(),
 			vertex_buffer,
 			index_buffer,
+			batches,
+			dynamic_atlas,
+			palette: 0,
+			sdf_style: SdfStyle::default(),
+			light: Light::default(),
 		}
 	}
-	fn top_clip(&self) -> &[Vert2] {
-		&self.stack[self.stack.len() - 1]
+	/// pick the palette page row used by everything drawn after this, see
+	/// `PaletteMeta::swap`
+	pub fn set_palette(&mut self, row: u32) {
+		self.palette = row;
 	}
-	pub fn push_clip(&mut self, clip: &[Vert2]) {
-		let res = polygon2::intersection(clip, self.top_clip());
-		self.stack.push(match res.len() {
-			// i hope it properly clips empty polygons
-			0 => vec![],
-			1 => res[0].clone(),
-			other => {
-				log::warn!(
-					"polygon split into {} chunks! discarding all but first",
-					other
-				);
-				res[0].clone()
+	/// pick the outline and glow of everything drawn from distance field
+	/// pages after this
+	pub fn set_sdf_style(&mut self, style: SdfStyle) {
+		self.sdf_style = style;
+	}
+	/// light the whole frame with normal maps, the last one set wins
+	pub fn set_light(&mut self, light: Light) {
+		self.light = light;
+	}
+	pub fn light(&self) -> Light {
+		self.light
+	}
+	/// atlas for images made while running, anything inserted gets uploaded
+	/// before this frame is drawn
+	pub fn dynamic_atlas(&mut self) -> &mut DynamicAtlas {
+		self.dynamic_atlas
+	}
+	/// add a triangle, starting a new batch if the page changed
+	fn push_tri(&mut self, tri: [u16; 3], page: usize) {
+		let end = (self.index_buffer.len() as u32 + 1) * 3;
+		match self.batches.last_mut() {
+			Some(batch) if batch.page == page => batch.indices.end = end,
+			_ => self.batches.push(Batch {
+				page,
+				indices: end - 3..end,
+			}),
+		}
+		self.index_buffer.add(tri);
+	}
+	fn vertex(&self, pos: Vert2, uv: Vert2, col: [u8; 4]) -> Vertex {
+		Vertex {
+			pos,
+			uv,
+			col,
+			palette: self.palette,
+			sdf: self.sdf_style.widths(),
+			outline: self.sdf_style.outline_col,
+			glow: self.sdf_style.glow_col,
+		}
+	}
+	/// cut `poly` down to the top clip polygons and draw what's left, points
+	/// get their uvs from where they are on `quad`
+	fn clipped(
+		&mut self,
+		poly: &[Vert2],
+		quad: [Vert2; 4],
+		uv: [Vert2; 4],
+		page: usize,
+		col: [u8; 4],
+	) {
+		let pieces = match self.stack.last() {
+			Some(top) => top.iter().flat_map(|v| clip::clip(poly, v)).collect(),
+			None => clip::convex_pieces(poly),
+		};
+		for piece in pieces {
+			let start_index = self.vertex_buffer.insert_index as u16;
+			for &pos in &piece {
+				self.vertex_buffer.add(self.vertex(
+					pos,
+					math::bilinear(uv, math::inverse_bilinear(quad, pos)),
+					col,
+				));
 			}
-		});
+			// pieces are convex so they can be drawn as fans
+			for i in 1..piece.len() as u16 - 1 {
+				self.push_tri([start_index, start_index + i, start_index + i + 1], page);
+			}
+		}
 	}
-	fn inverse_uv(p: [Vert2; 4], z: Vert2) -> Vert2 {
-		todo!("clipping isn't supported");
-		// doesn't work in some cases
-		// TODO: port MapInv from https://desmos.com/calculator/e1qvwtqsti instead
-
-		// MapInv(P, L):
-		//
-		// δ1.x = (L4.y - L3.y) * (L4.x - L3.x - L1.x + L2.x) - (L4.x - L3.x) * (L4.y - L3.y - L1.y + L2.y)
-		// δ1.y = (L4.y - L1.y) * (L4.x - L3.x - L1.x + L2.x) - (L4.x - L1.x) * (L4.y - L3.y - L1.y + L2.y)
-		// TODO: do these please :(
-		// δ2
-		// δ3
-		//
-		// _s1 = (-δ2 + (δ2 * δ2 - 4 * δ1 * δ3).sqrt()) / 2 * δ1
-		// _s2 = (-δ2 - (δ2 * δ2 - 4 * δ1 * δ3).sqrt()) / 2 * δ1
-		// _s = if 0 <= _s1 <= 1 {_s1} else if 0 <= _s2 <= 1 {_s2} else if (_s2 - 0.5).abs() < (_s1 - 0.5).abs() {_s2} else {_s1}
-		// _pM.x = L3.x * (1 - _s.y) + L2.x * _s.y
-		// _pM.y = L1.y * (1 - _s.x) + L2.y * _s.x
-		// _pm.x = L4.x * (1 - _s.y) + L1.x * _s.y
-		// _pm.y = L3.y * (1 - _s.x) + L4.y * _s.x
-		// _p = (P - _pm) / (_pM - _pm)
-		// Pms = P - L4
-		//
-		// Mix = L3 - L4
-		// Miy = L1 - L4
-		// MiD = 1 / (Mix.x * Miy.y - Miy.x * Mix.y)
-		//
-		// Mia =  Miy.y * MiD
-		// Mib = -Miy.x * MiD
-		// Mic = -Mix.y * MiD
-		// Mid =  Mix.x * MiD
-		// _mi.x = Pms.x * Mia + Pms.y * Mib
-		// _mi.y = Pms.x * Mic + Pms.y * Mid
-		//
-		// if δ1.abs() > 0 {_s} else if δ1.flip().abs() > 0 {_p} else {_mi}
+	/// only draw what's inside of `clip` until `pop_clip`, on top of any
+	/// clips already pushed
+	pub fn push_clip(&mut self, clip: &[Vert2]) {
+		let Some(top) = self.stack.last() else {
+			self.stack.push(vec![clip.to_vec()]);
+			return;
+		};
+		// concave clips can split into several pieces, an empty set means
+		// nothing gets drawn
+		let res = top
+			.iter()
+			.flat_map(|v| clip::intersection(clip, v))
+			.collect();
+		self.stack.push(res);
 	}
 	pub fn pop_clip(&mut self) {
 		self.stack.pop().expect("clip stack empty!");
 	}
-	pub fn polygon(&mut self, poly: &[Vert2], uv: [Vert2; 4], color: [u8; 4]) {
-		todo!("clipping isn't supported");
-		// let clipped = polygon2::intersection(&poly, self.top_clip());
+	/// draw any simple polygon, `uv` are the corners of its bounding box
+	/// clockwise from the top left
+	pub fn polygon(&mut self, poly: &[Vert2], uv: [Vert2; 4], page: usize, col: [u8; 4]) {
+		let Some(&first) = poly.first() else {
+			return;
+		};
+		let (min, max) = poly.iter().fold((first, first), |(min, max), v| {
+			(
+				[min[0].min(v[0]), min[1].min(v[1])],
+				[max[0].max(v[0]), max[1].max(v[1])],
+			)
+		});
+		self.clipped(
+			poly,
+			[min, [max[0], min[1]], max, [min[0], max[1]]],
+			uv,
+			page,
+			col,
+		);
 	}
+	#[allow(clippy::too_many_arguments)]
 	pub fn rect(
 		&mut self,
 		position: Vert2,
 		origin: Vert2,
 		size: Vert2,
-		rotation: f32,
+		_rotation: f32,
 		uv: [Vert2; 4],
+		page: usize,
 		col: [u8; 4],
 	) {
 		let start = [
@@ -101,39 +230,125 @@ impl<'a> RenderContext<'a> {
 			position[1] - size[1] * origin[1],
 		];
 		let end = [start[0] + size[0], start[1] + size[1]];
-		if self.stack.len() > 0 {
-			todo!("clipping isn't supported");
+		if !self.stack.is_empty() {
+			let quad = [start, [end[0], start[1]], end, [start[0], end[1]]];
+			self.clipped(&quad, quad, uv, page, col);
 		} else {
 			let start_index = self.vertex_buffer.insert_index as u16;
-			self.vertex_buffer.add(Vertex {
-				uv: uv[0],
-				col,
-				pos: start,
-			});
-			self.vertex_buffer.add(Vertex {
-				uv: uv[1],
-				col,
-				pos: [end[0], start[1]],
-			});
-			self.vertex_buffer.add(Vertex {
-				uv: uv[2],
-				col,
-				pos: end,
-			});
-			self.vertex_buffer.add(Vertex {
-				uv: uv[3],
-				col,
-				pos: [start[0], end[1]],
-			});
+			for (pos, uv) in [start, [end[0], start[1]], end, [start[0], end[1]]]
+				.into_iter()
+				.zip(uv)
+			{
+				self.vertex_buffer.add(self.vertex(pos, uv, col));
+			}
 			// 0------1
 			// |`. #1 |
 			// |  `.  |
 			// | #2 `.|
 			// 3------2
-			self.index_buffer
-				.add([start_index, start_index + 1, start_index + 2]);
-			self.index_buffer
-				.add([start_index, start_index + 2, start_index + 3]);
+			self.push_tri([start_index, start_index + 1, start_index + 2], page);
+			self.push_tri([start_index, start_index + 2, start_index + 3], page);
+		}
+	}
+	/// draw a frame from the atlas, placed as if it was never trimmed
+	pub fn sprite(
+		&mut self,
+		position: Vert2,
+		origin: Vert2,
+		scale: Vert2,
+		rotation: f32,
+		sprite: &SpriteMeta,
+		col: [u8; 4],
+	) {
+		if sprite.rect.area() == 0 {
+			return;
+		}
+		let (origin, size) = sprite.trimmed_quad(origin, scale);
+		self.rect(
+			position,
+			origin,
+			size,
+			rotation,
+			sprite.uv(),
+			sprite.page,
+			col,
+		);
+	}
+	/// draw a frame stretched to `size`, keeping its nine-slice borders at
+	/// `scale` times their size
+	pub fn nine_slice(
+		&mut self,
+		position: Vert2,
+		origin: Vert2,
+		size: Vert2,
+		scale: f32,
+		sprite: &SpriteMeta,
+		col: [u8; 4],
+	) {
+		if sprite.rect.area() == 0 {
+			return;
+		}
+		let start = [
+			position[0] - size[0] * origin[0],
+			position[1] - size[1] * origin[1],
+		];
+		for quad in sprite.nine_slice_quads(size, scale) {
+			self.rect(
+				[start[0] + quad.position[0], start[1] + quad.position[1]],
+				[0.0, 0.0],
+				quad.size,
+				0.0,
+				quad.uv,
+				sprite.page,
+				col,
+			);
+		}
+	}
+	/// draw a frame from a distance field layer, `origin` is on the frame
+	/// without the padding the field needed
+	#[allow(clippy::too_many_arguments)]
+	pub fn sdf_sprite(
+		&mut self,
+		position: Vert2,
+		origin: Vert2,
+		scale: Vert2,
+		rotation: f32,
+		sprite: &SpriteMeta,
+		sdf: &SdfMeta,
+		col: [u8; 4],
+	) {
+		self.sprite(
+			position,
+			sdf.origin(sprite, origin),
+			scale,
+			rotation,
+			sprite,
+			col,
+		);
+	}
+	/// draw `text` with its top left at `position`, `frames` are the frames
+	/// of the font's image
+	pub fn text(
+		&mut self,
+		position: Vert2,
+		scale: f32,
+		font: &FontMeta,
+		frames: &[SpriteMeta],
+		text: &str,
+		col: [u8; 4],
+	) {
+		let position = [position[0].round(), position[1].round()];
+		for (frame, offset) in font.layout(text, scale) {
+			if let Some(sprite) = frames.get(frame) {
+				self.sprite(
+					[position[0] + offset[0], position[1] + offset[1]],
+					[0.0, 0.0],
+					[scale, scale],
+					0.0,
+					sprite,
+					col,
+				);
+			}
 		}
 	}
 }
@@ -2,23 +2,48 @@
 struct UniIn {
 	offset: vec2<f32>;
 	size: vec2<f32>;
-	atlas_res: vec2<f32>;
+	// direction towards the light and the ambient light
+	light: vec4<f32>;
+	// colour pages are premultiplied and so is the blend state
+	premultiplied: u32;
+};
+struct PageIn {
+	size: vec2<f32>;
+	mask: u32;
+	palette: u32;
+	sdf: u32;
 };
 [[group(0), binding(0)]]
 var t_atlas: texture_2d<f32>;
 [[group(0), binding(1)]]
 var s_atlas: sampler;
+[[group(0), binding(2)]]
+var<uniform> page: PageIn;
 [[group(1), binding(0)]]
 var<uniform> uni: UniIn;
+[[group(2), binding(0)]]
+var t_palette: texture_2d<f32>;
+[[group(3), binding(0)]]
+var t_normal: texture_2d<f32>;
+[[group(3), binding(1)]]
+var t_emissive: texture_2d<f32>;
 struct VertIn {
 	[[location(0)]] pos: vec2<f32>;
 	[[location(1)]] uv:  vec2<f32>;
 	[[location(2)]] col: vec4<f32>;
+	[[location(3)]] palette: u32;
+	[[location(4)]] sdf: vec4<f32>;
+	[[location(5)]] outline: vec4<f32>;
+	[[location(6)]] glow: vec4<f32>;
 };
 struct VertOut {
 	[[builtin(position)]] clip_position: vec4<f32>;
 	[[location(0)]] color: vec4<f32>;
 	[[location(1)]] tex_coords: vec2<f32>;
+	[[location(2), interpolate(flat)]] palette: u32;
+	[[location(3)]] sdf: vec4<f32>;
+	[[location(4)]] outline: vec4<f32>;
+	[[location(5)]] glow: vec4<f32>;
 };
 
 [[stage(vertex)]]
@@ -27,7 +52,11 @@ fn vert(
 ) -> VertOut {
 	var out: VertOut;
 	out.color = vert.col;
-	out.tex_coords = vert.uv / uni.atlas_res;
+	out.tex_coords = vert.uv / page.size;
+	out.palette = vert.palette;
+	out.sdf = vert.sdf;
+	out.outline = vert.outline;
+	out.glow = vert.glow;
 	// f32(pos + offset) / f32(size) - vec2<f32>(1.0, 1.0)
 	let pos2d = (vert.pos + uni.offset) * vec2<f32>(uni.size);
 	out.clip_position = vec4<f32>((2.0 * pos2d - 1.0) * vec2<f32>(1.0, -1.0), 0.5, 1.0);
@@ -35,9 +64,63 @@ fn vert(
 }
 
 // frag shader
+// colours are premultiplied until they're returned
+fn premultiplied(tex: vec4<f32>) -> vec4<f32> {
+	return select(vec4<f32>(tex.rgb * tex.a, tex.a), tex, uni.premultiplied != 0u);
+}
+
+fn tint(col: vec4<f32>, color: vec4<f32>) -> vec4<f32> {
+	return vec4<f32>(col.rgb * color.rgb * color.a, col.a * color.a);
+}
+
+// light a colour with the page's normal and emissive maps, pages without
+// them get a flat normal and no emission
+fn lit(col: vec4<f32>, coords: vec2<f32>) -> vec4<f32> {
+	let normal = textureSample(t_normal, s_atlas, coords).xyz * 2.0 - 1.0;
+	let emissive = textureSample(t_emissive, s_atlas, coords);
+	let light = uni.light.w + max(dot(normal, uni.light.xyz), 0.0);
+	return vec4<f32>(col.rgb * light + emissive.rgb * emissive.a * col.a, col.a);
+}
+
+// back to straight alpha if that's what the blend state wants
+fn output(col: vec4<f32>) -> vec4<f32> {
+	if (uni.premultiplied != 0u) {
+		return col;
+	}
+	return vec4<f32>(col.rgb / max(col.a, 0.0001), col.a);
+}
+
 [[stage(fragment)]]
 fn frag(
 	in: VertOut
 ) -> [[location(0)]] vec4<f32> {
-	return textureSample(t_atlas, s_atlas, in.tex_coords) * in.color;
+	let tex = textureSample(t_atlas, s_atlas, in.tex_coords);
+	// palette pages hold indices into a row of the palette page, filtering
+	// them would mix unrelated colours
+	if (page.palette != 0u) {
+		let index = textureLoad(t_atlas, vec2<i32>(floor(in.tex_coords * page.size)), 0).r;
+		let coords = vec2<i32>(i32(index * 255.0 + 0.5), i32(in.palette));
+		let col = premultiplied(textureLoad(t_palette, coords, 0));
+		return output(lit(tint(col, in.color), in.tex_coords));
+	}
+	// distance field pages have the edge at 0.5, multi-channel ones keep
+	// corners sharp in the median of their channels
+	if (page.sdf != 0u) {
+		let median = max(min(tex.r, tex.g), min(max(tex.r, tex.g), tex.b));
+		let dist = select(median, tex.r, page.mask != 0u);
+		let width = max(fwidth(dist), 0.0001);
+		let edge = 0.5 - in.sdf.x;
+		let fill = clamp((dist - 0.5) / width + 0.5, 0.0, 1.0);
+		let outline = clamp((dist - edge) / width + 0.5, 0.0, 1.0);
+		let glow = smoothStep(edge - in.sdf.y, edge, dist) * in.glow.a;
+		// layered back to front
+		var col = vec4<f32>(in.glow.rgb * glow, glow);
+		col = mix(col, vec4<f32>(in.outline.rgb * in.outline.a, in.outline.a), outline);
+		return output(mix(col, vec4<f32>(in.color.rgb * in.color.a, in.color.a), fill));
+	}
+	// single channel pages are alpha masks
+	if (page.mask != 0u) {
+		return output(lit(tint(vec4<f32>(tex.r, tex.r, tex.r, tex.r), in.color), in.tex_coords));
+	}
+	return output(lit(tint(premultiplied(tex), in.color), in.tex_coords));
 }
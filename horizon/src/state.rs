@@ -1,8 +1,10 @@
 //! core rendering and state logic
+use crate::atlas::{AtlasPage, DynamicAtlas};
 use crate::debugger;
 use crate::ecs::{Component, Entity, UpdateInfo};
 use crate::egui_util::EguiComponent;
-use crate::render::{RenderContext, ResizeBuffer};
+use crate::render::{Batch, Light, RenderContext, ResizeBuffer};
+use rkpk::common::Companion;
 use std::{collections, iter};
 use wgpu::util::DeviceExt;
 use winit::{dpi, event, window};
@@ -31,7 +33,7 @@ impl FakeVertexBufferLayout {
 			array_stride: std::mem::size_of::<T>() as wgpu::BufferAddress,
 		}
 	}
-	fn real(&self) -> wgpu::VertexBufferLayout {
+	fn real(&self) -> wgpu::VertexBufferLayout<'_> {
 		wgpu::VertexBufferLayout {
 			array_stride: self.array_stride,
 			step_mode: self.step_mode,
@@ -46,11 +48,27 @@ trait CanBuffer {
 
 /// all the uniform data that's used, 16-bit values don't work here :(
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 struct WorldUniform {
 	offset: [f32; 2],
 	size: [f32; 2],
-	atlas_size: [f32; 2],
+	/// direction towards the light, then the ambient light
+	light: [f32; 4],
+	/// colour pages are premultiplied, `AtlasMeta::premultiplied`
+	premultiplied: u32,
+	_padding: [u32; 3],
+}
+
+impl Default for WorldUniform {
+	fn default() -> Self {
+		Self {
+			offset: [0.0; 2],
+			size: [0.0; 2],
+			light: Light::default().into(),
+			premultiplied: 0,
+			_padding: [0; 3],
+		}
+	}
 }
 
 impl WorldUniform {
@@ -79,9 +97,6 @@ impl WorldUniform {
 		];
 		log::trace!("WorldUniform = {:?}", self);
 	}
-	fn update_atlas_size(&mut self, width: u32, height: u32) {
-		self.atlas_size = [width as f32, height as f32];
-	}
 }
 
 /// vertex data
@@ -91,6 +106,12 @@ pub struct Vertex {
 	pub pos: [f32; 2],
 	pub uv: [f32; 2],
 	pub col: [u8; 4],
+	/// row of the palette page used by palette pages
+	pub palette: u32,
+	/// outline and glow width for distance field pages, see `SdfStyle`
+	pub sdf: [u8; 4],
+	pub outline: [u8; 4],
+	pub glow: [u8; 4],
 }
 
 impl CanBuffer for Vertex {
@@ -99,6 +120,10 @@ impl CanBuffer for Vertex {
 			0 => Float32x2, // pos
 			1 => Float32x2, // uv
 			2 => Unorm8x4, // col
+			3 => Uint32, // palette
+			4 => Unorm8x4, // sdf
+			5 => Unorm8x4, // outline
+			6 => Unorm8x4, // glow
 		)
 	}
 }
@@ -119,10 +144,18 @@ pub struct State {
 	world_uniform_buffer: wgpu::Buffer,
 	world_uniform_bind_group: wgpu::BindGroup,
 	start_info: crate::StartInfo,
-	atlas_bind_group: wgpu::BindGroup,
+	atlas_pages: Vec<AtlasPage>,
+	atlas_bind_group_layout: wgpu::BindGroupLayout,
+	atlas_sampler: wgpu::Sampler,
+	dynamic_atlas: DynamicAtlas,
+	palette_bind_group: wgpu::BindGroup,
+	default_companions: wgpu::BindGroup,
+	/// keeps the textures of `default_companions` around
+	default_companion_pages: [AtlasPage; 2],
 	pressed_keys: collections::HashSet<u32>,
 	vertex_buffer: ResizeBuffer<Vertex>,
 	index_buffer: ResizeBuffer<[u16; 3]>,
+	batches: Vec<Batch>,
 	root_entity: Entity,
 	dm_screen_offset: [f64; 2],
 }
@@ -130,7 +163,7 @@ pub struct State {
 impl State {
 	// we *might* have to make this as a "init" rather than "new" if some of the
 	// settings in here are worth changing and require reconstruction
-	pub async fn new(window: &window::Window, start_info: crate::StartInfo) -> Self {
+	pub async fn new(window: &window::Window, mut start_info: crate::StartInfo) -> Self {
 		let size = window.inner_size();
 		let instance = wgpu::Instance::new(wgpu::Backends::all());
 		// SAFETY: it is
@@ -146,7 +179,8 @@ impl State {
 		let (device, queue) = adapter
 			.request_device(
 				&wgpu::DeviceDescriptor {
-					features: wgpu::Features::empty(),
+					// compressed atlas pages get decoded if this isn't there
+					features: adapter.features() & wgpu::Features::TEXTURE_COMPRESSION_BC,
 					limits: wgpu::Limits::default(),
 					label: None,
 				},
@@ -169,41 +203,6 @@ impl State {
 			present_mode: wgpu::PresentMode::Fifo,
 		};
 		surface.configure(&device, &config);
-		// let atlas_bytes = include_bytes!("assets/icon.png");
-		let atlas_bytes = &[];
-		let atlas_image = image::load_from_memory(atlas_bytes).expect("failed to load image");
-		let atlas_rgba = atlas_image.to_rgba8();
-		let atlas_size = atlas_rgba.dimensions();
-		let atlas_extent = wgpu::Extent3d {
-			width: atlas_size.0,
-			height: atlas_size.1,
-			depth_or_array_layers: 1,
-		};
-		let atlas_texture = device.create_texture(&wgpu::TextureDescriptor {
-			label: Some("AtlasTexture"),
-			size: atlas_extent,
-			mip_level_count: 1,
-			sample_count: 1,
-			dimension: wgpu::TextureDimension::D2,
-			format: wgpu::TextureFormat::Rgba8UnormSrgb,
-			usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-		});
-		queue.write_texture(
-			wgpu::ImageCopyTexture {
-				texture: &atlas_texture,
-				mip_level: 0,
-				origin: wgpu::Origin3d::ZERO,
-				aspect: wgpu::TextureAspect::All,
-			},
-			&atlas_rgba,
-			wgpu::ImageDataLayout {
-				offset: 0,
-				bytes_per_row: std::num::NonZeroU32::new(4 * atlas_size.0),
-				rows_per_image: std::num::NonZeroU32::new(atlas_size.1),
-			},
-			atlas_extent,
-		);
-		let atlas_view = atlas_texture.create_view(&wgpu::TextureViewDescriptor::default());
 		let atlas_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
 			label: Some("AtlasSampler"),
 			// i want ClampToBorder but no support :(
@@ -216,42 +215,94 @@ impl State {
 			border_color: Some(wgpu::SamplerBorderColor::TransparentBlack),
 			..Default::default()
 		});
-		let atlas_bind_group_layout =
-			device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-				label: Some("AtlasBindGroupLayout"),
-				entries: &[
-					wgpu::BindGroupLayoutEntry {
-						binding: 0,
-						visibility: wgpu::ShaderStages::FRAGMENT,
-						ty: wgpu::BindingType::Texture {
-							multisampled: false,
-							view_dimension: wgpu::TextureViewDimension::D2,
-							sample_type: wgpu::TextureSampleType::Float { filterable: true },
-						},
-						count: None,
-					},
-					wgpu::BindGroupLayoutEntry {
-						binding: 1,
-						visibility: wgpu::ShaderStages::FRAGMENT,
-						ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-						count: None,
-					},
-				],
-			});
-		let atlas_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-			label: Some("AtlasBindGroup"),
-			layout: &atlas_bind_group_layout,
-			entries: &[
-				wgpu::BindGroupEntry {
-					binding: 0,
-					resource: wgpu::BindingResource::TextureView(&atlas_view),
-				},
-				wgpu::BindGroupEntry {
-					binding: 1,
-					resource: wgpu::BindingResource::Sampler(&atlas_sampler),
-				},
-			],
+		let atlas_bind_group_layout = AtlasPage::bind_group_layout(&device);
+		let mut atlas_pages = start_info
+			.atlas_meta
+			.pages
+			.iter()
+			.zip(start_info.atlas_pages.drain(..))
+			.map(|(meta, levels)| {
+				AtlasPage::new(
+					&device,
+					&queue,
+					&atlas_bind_group_layout,
+					&atlas_sampler,
+					meta,
+					&levels,
+				)
+				.expect("failed to load atlas page")
+			})
+			.collect::<Vec<_>>();
+		if atlas_pages.is_empty() {
+			log::warn!("no atlas pages, drawing with a blank one");
+			let page = AtlasPage::blank(
+				&device,
+				&atlas_bind_group_layout,
+				&atlas_sampler,
+				[1, 1],
+				1,
+				wgpu::TextureFormat::Rgba8UnormSrgb,
+			);
+			page.write(&queue, 0, [0, 0], [1, 1], &[0xFF; 4]);
+			atlas_pages.push(page);
+		}
+		// the dynamic atlas goes after the packed pages
+		let dynamic_atlas = DynamicAtlas::new(
+			atlas_pages.len(),
+			rkpk::common::RectWH::new(256, 256),
+			device
+				.limits()
+				.max_texture_dimension_2d
+				.min(rkpk::common::ImagePos::MAX as u32) as rkpk::common::ImagePos,
+			start_info.atlas_meta.premultiplied,
+		);
+		atlas_pages.push(AtlasPage::blank(
+			&device,
+			&atlas_bind_group_layout,
+			&atlas_sampler,
+			[256, 256],
+			1,
+			wgpu::TextureFormat::Rgba8UnormSrgb,
+		));
+		// without any palette pages nothing reads it, so any page will do
+		let palette_bind_group_layout = AtlasPage::palette_bind_group_layout(&device);
+		let palette_bind_group = atlas_pages[start_info
+			.atlas_meta
+			.palette_page
+			.unwrap_or(atlas_pages.len() - 1)]
+		.palette_bind_group(&device, &palette_bind_group_layout);
+		// pages without a normal or emissive page of their own use these
+		let companion_bind_group_layout = AtlasPage::companion_bind_group_layout(&device);
+		let [default_normal, default_emissive] = Companion::ALL.map(|companion| {
+			let page = AtlasPage::blank(
+				&device,
+				&atlas_bind_group_layout,
+				&atlas_sampler,
+				[1, 1],
+				1,
+				wgpu::TextureFormat::Rgba8Unorm,
+			);
+			page.write(&queue, 0, [0, 0], [1, 1], &companion.fill());
+			page
 		});
+		let default_companions = AtlasPage::companion_bind_group(
+			&device,
+			&companion_bind_group_layout,
+			&default_normal,
+			&default_emissive,
+		);
+		for (i, meta) in start_info.atlas_meta.pages.iter().enumerate() {
+			if meta.normal.is_none() && meta.emissive.is_none() {
+				continue;
+			}
+			let bind_group = AtlasPage::companion_bind_group(
+				&device,
+				&companion_bind_group_layout,
+				meta.normal.map_or(&default_normal, |v| &atlas_pages[v]),
+				meta.emissive.map_or(&default_emissive, |v| &atlas_pages[v]),
+			);
+			atlas_pages[i].companion_bind_group = Some(bind_group);
+		}
 		let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
 			label: Some("Shader"),
 			source: wgpu::ShaderSource::Wgsl(include_str!("shaders/shader.wgsl").into()),
@@ -259,8 +310,8 @@ impl State {
 
 		let egui_platform =
 			egui_winit_platform::Platform::new(egui_winit_platform::PlatformDescriptor {
-				physical_width: size.width as u32,
-				physical_height: size.height as u32,
+				physical_width: size.width,
+				physical_height: size.height,
 				scale_factor: window.scale_factor(),
 				font_definitions: egui::FontDefinitions::default(),
 				style: Default::default(),
@@ -276,8 +327,10 @@ impl State {
 		egui_visuals.window_corner_radius = 0.0;
 		egui_context.set_visuals(egui_visuals);
 
-		let mut world_uniform = WorldUniform::default();
-		world_uniform.update_atlas_size(atlas_size.0, atlas_size.1);
+		let world_uniform = WorldUniform {
+			premultiplied: start_info.atlas_meta.premultiplied as u32,
+			..Default::default()
+		};
 		let vertex_buffer = ResizeBuffer::new(vec![], wgpu::BufferUsages::VERTEX, &device);
 		let index_buffer = ResizeBuffer::new(vec![], wgpu::BufferUsages::INDEX, &device);
 		let world_uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -291,7 +344,7 @@ impl State {
 				label: Some("UniformBindGroupLayout"),
 				entries: &[wgpu::BindGroupLayoutEntry {
 					binding: 0,
-					visibility: wgpu::ShaderStages::VERTEX,
+					visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
 					ty: wgpu::BindingType::Buffer {
 						ty: wgpu::BufferBindingType::Uniform,
 						has_dynamic_offset: false,
@@ -311,7 +364,12 @@ impl State {
 		let render_pipeline_layout =
 			device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
 				label: Some("RenderPipelineLayout"),
-				bind_group_layouts: &[&atlas_bind_group_layout, &world_uniform_bind_group_layout],
+				bind_group_layouts: &[
+					&atlas_bind_group_layout,
+					&world_uniform_bind_group_layout,
+					&palette_bind_group_layout,
+					&companion_bind_group_layout,
+				],
 				push_constant_ranges: &[],
 			});
 		let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
@@ -327,7 +385,11 @@ impl State {
 				entry_point: "frag",
 				targets: &[wgpu::ColorTargetState {
 					format: config.format,
-					blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+					blend: Some(if start_info.atlas_meta.premultiplied {
+						wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING
+					} else {
+						wgpu::BlendState::ALPHA_BLENDING
+					}),
 					write_mask: wgpu::ColorWrites::ALL,
 				}],
 			}),
@@ -359,7 +421,13 @@ impl State {
 			size,
 			render_pipeline,
 			world_uniform,
-			atlas_bind_group,
+			atlas_pages,
+			atlas_bind_group_layout,
+			atlas_sampler,
+			dynamic_atlas,
+			palette_bind_group,
+			default_companions,
+			default_companion_pages: [default_normal, default_emissive],
 			world_uniform_buffer,
 			world_uniform_bind_group,
 			start_info,
@@ -367,6 +435,7 @@ impl State {
 			egui_rpass,
 			vertex_buffer,
 			index_buffer,
+			batches: vec![],
 			target_size: size,
 			egui_state: debugger::DebuggerState::new(),
 			pressed_keys: collections::HashSet::new(),
@@ -393,17 +462,24 @@ impl State {
 		// 	rot: 0,
 		// 	flags: 0,
 		// });
-		let mut render_context =
-			RenderContext::new(&mut self.vertex_buffer, &mut self.index_buffer);
+		let mut render_context = RenderContext::new(
+			&mut self.vertex_buffer,
+			&mut self.index_buffer,
+			&mut self.batches,
+			&mut self.dynamic_atlas,
+		);
 		render_context.rect(
 			[0.0, 0.0],
 			[0.0, 0.0],
 			[512.0, 512.0],
 			0.0,
 			[[0.0, 0.0], [512.0, 0.0], [512.0, 512.0], [0.0, 512.0]],
+			0,
 			[255, 255, 255, 255],
 		);
 		self.root_entity.render(&mut render_context);
+		self.world_uniform.light = render_context.light().into();
+		self.send_uniform_buffer();
 	}
 	fn send_uniform_buffer(&mut self) {
 		self.queue.write_buffer(
@@ -448,22 +524,23 @@ impl State {
 	/// runs a resize event if the window has resized
 	pub fn hard_resize(&mut self, force: bool) {
 		let new_size = self.target_size;
-		if new_size.width > 0 && new_size.height > 0 {
-			if force || new_size.width != self.size.width || new_size.height != self.size.height {
-				log::trace!("resize");
-				log::trace!("sizing: {:?} -> {:?}", self.size, new_size);
-				self.size = new_size;
-				self.config.width = new_size.width;
-				self.config.height = new_size.height;
-				self.surface.configure(&self.device, &self.config);
-				self.world_uniform.update_screen_size(
-					new_size.width,
-					new_size.height,
-					self.start_info.integer_mode,
-					self.dm_screen_offset,
-				);
-				self.send_uniform_buffer();
-			}
+		if new_size.width > 0
+			&& new_size.height > 0
+			&& (force || new_size.width != self.size.width || new_size.height != self.size.height)
+		{
+			log::trace!("resize");
+			log::trace!("sizing: {:?} -> {:?}", self.size, new_size);
+			self.size = new_size;
+			self.config.width = new_size.width;
+			self.config.height = new_size.height;
+			self.surface.configure(&self.device, &self.config);
+			self.world_uniform.update_screen_size(
+				new_size.width,
+				new_size.height,
+				self.start_info.integer_mode,
+				self.dm_screen_offset,
+			);
+			self.send_uniform_buffer();
 		}
 	}
 	/// global window event handling
@@ -471,7 +548,7 @@ impl State {
 // where T: std::fmt::Debug {
 	// 	debug!("event {:?}", event);
 	{
-		self.egui_platform.handle_event(&event);
+		self.egui_platform.handle_event(event);
 		false
 	}
 	/// resizes the surface, may not actually cause a resize
@@ -540,6 +617,13 @@ impl State {
 			});
 		self.egui_state.checkpoint("init");
 		self.update_render();
+		self.dynamic_atlas.flush(
+			&self.device,
+			&self.queue,
+			&self.atlas_bind_group_layout,
+			&self.atlas_sampler,
+			&mut self.atlas_pages,
+		);
 		self.egui_state.checkpoint("generate");
 		self.vertex_buffer.write_data(&self.queue, &self.device);
 		self.index_buffer.write_data(&self.queue, &self.device);
@@ -596,9 +680,20 @@ impl State {
 			self.index_buffer.buffer.slice(..),
 			wgpu::IndexFormat::Uint16,
 		);
-		render_pass.set_bind_group(0, &self.atlas_bind_group, &[]);
 		render_pass.set_bind_group(1, &self.world_uniform_bind_group, &[]);
-		render_pass.draw_indexed(0..(self.index_buffer.len() * 3) as u32, 0, 0..1);
+		render_pass.set_bind_group(2, &self.palette_bind_group, &[]);
+		for batch in &self.batches {
+			let page = &self.atlas_pages[batch.page];
+			render_pass.set_bind_group(0, &page.bind_group, &[]);
+			render_pass.set_bind_group(
+				3,
+				page.companion_bind_group
+					.as_ref()
+					.unwrap_or(&self.default_companions),
+				&[],
+			);
+			render_pass.draw_indexed(batch.indices.clone(), 0, 0..1);
+		}
 		self.egui_state.checkpoint("render_pass");
 		drop(render_pass);
 		self.egui_rpass
@@ -0,0 +1,321 @@
+//! pack a graph directory from the command line, without a build script
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use log::LevelFilter;
+use rkpk::build::rectpack2d::max_rects::MaxRectsHeuristic;
+use rkpk::build::rectpack2d::{Algorithm, DiscardStep};
+use rkpk::build::{logger, DirOutput, PackOptions, PackOutput, Packer};
+use rkpk::common::AtlasMeta;
+use rkpk::format::PixelFormat;
+
+const USAGE: &str = "\
+usage: rkpk [options] <graph dir> <out dir>
+
+output:
+  --name <path>              where the metadata and pages go in the output,
+                             <path>.meta and <path>/<page>.png [default: graph]
+  --json                     also write the metadata as <path>.json
+  --previews                 also write pngs of pages that aren't already pngs
+  --report <file>            write a json report of how well layers packed
+  --debug-images <dir>       write pages with every frame outlined and labelled
+  --log <level>              off, error, warn, info, debug or trace [default: warn]
+
+packing:
+  --max-size <n>             biggest a page can get [default: 4096]
+  --discard-step <n>         stop shrinking the bin at steps this small [default: 1]
+  --tries <n>                shrink the bin this many times instead
+  --algorithm <name>         empty-spaces, max-rects-short-side, max-rects-area,
+                             max-rects-contact, skyline or all, can be repeated
+                             [default: empty-spaces]
+  --trim                     trim transparent borders off of frames
+  --no-dedup                 pack every frame even if it's the same as another
+  --dedup-flipped            count mirrored frames as duplicates
+  --format <group/layer=fmt> pixel format of a layer, rgba8, r8, rgba4444,
+                             indexed, palette, bc1, bc3 or bc7, can be repeated
+  --mip-levels <n>           half size copies of every page [default: 0]
+  --previous <meta file>     keep frames where this earlier pack put them
+  --repack-threshold <n>     how much bigger keeping frames in place can make
+                             a layer before it's repacked [default: 0.25]
+  --premultiply              store colour pages with premultiplied alpha";
+
+/// everything from the command line
+struct Args {
+	graph: PathBuf,
+	out: PathBuf,
+	name: String,
+	json: bool,
+	previews: bool,
+	report: Option<PathBuf>,
+	log: LevelFilter,
+	options: PackOptions,
+}
+
+fn algorithms(name: &str) -> Option<Vec<Algorithm>> {
+	Some(match name {
+		"empty-spaces" => vec![Algorithm::EmptySpaces],
+		"max-rects-short-side" => vec![Algorithm::MaxRects(MaxRectsHeuristic::BestShortSideFit)],
+		"max-rects-area" => vec![Algorithm::MaxRects(MaxRectsHeuristic::BestAreaFit)],
+		"max-rects-contact" => vec![Algorithm::MaxRects(MaxRectsHeuristic::ContactPoint)],
+		"skyline" => vec![Algorithm::Skyline],
+		"all" => Algorithm::ALL.to_vec(),
+		_ => return None,
+	})
+}
+
+fn pixel_format(name: &str) -> Option<PixelFormat> {
+	Some(match name {
+		"rgba8" => PixelFormat::Rgba8,
+		"r8" => PixelFormat::R8,
+		"rgba4444" => PixelFormat::Rgba4444,
+		"indexed" => PixelFormat::Indexed,
+		"palette" => PixelFormat::Palette,
+		"bc1" => PixelFormat::Bc1,
+		"bc3" => PixelFormat::Bc3,
+		"bc7" => PixelFormat::Bc7,
+		_ => return None,
+	})
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+	let mut paths = vec![];
+	let mut name = "graph".to_string();
+	let mut json = false;
+	let mut previews = false;
+	let mut report = None;
+	let mut log = LevelFilter::Warn;
+	let mut algorithms_set = vec![];
+	let mut options = PackOptions::default();
+	while let Some(arg) = args.next() {
+		let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+		macro_rules! parse {
+			() => {{
+				let value = value()?;
+				value
+					.parse()
+					.map_err(|_| format!("bad value {:?} for {}", value, arg))?
+			}};
+		}
+		match arg.as_str() {
+			"-h" | "--help" => return Err(String::new()),
+			"--name" => name = value()?,
+			"--json" => json = true,
+			"--previews" => previews = true,
+			"--report" => report = Some(value()?.into()),
+			"--debug-images" => options.debug_images = Some(value()?.into()),
+			"--log" => log = parse!(),
+			"--max-size" => options.max_size = parse!(),
+			"--discard-step" => options.discard_step = DiscardStep::Step(parse!()),
+			"--tries" => options.discard_step = DiscardStep::Tries(parse!()),
+			"--algorithm" => {
+				let value = value()?;
+				algorithms_set.extend(
+					algorithms(&value).ok_or_else(|| format!("unknown algorithm {:?}", value))?,
+				);
+			}
+			"--trim" => options.trim = true,
+			"--no-dedup" => options.dedup = false,
+			"--dedup-flipped" => options.dedup_flipped = true,
+			"--format" => {
+				let value = value()?;
+				let format_error = || format!("bad layer format {:?}", value);
+				let (layer, format) = value.split_once('=').ok_or_else(format_error)?;
+				let (group, layer) = layer.split_once('/').ok_or_else(format_error)?;
+				let format = pixel_format(format).ok_or_else(format_error)?;
+				options
+					.formats
+					.insert((group.to_string(), layer.to_string()), format);
+			}
+			"--mip-levels" => options.mip_levels = parse!(),
+			"--previous" => {
+				let path = value()?;
+				let data = fs::read(&path).map_err(|e| format!("can't read {}: {}", path, e))?;
+				options.previous = Some(
+					AtlasMeta::from_bytes(&data)
+						.map_err(|e| format!("can't read {}: {}", path, e))?,
+				);
+			}
+			"--repack-threshold" => options.repack_threshold = parse!(),
+			"--premultiply" => options.premultiply = true,
+			_ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+			_ => paths.push(PathBuf::from(arg)),
+		}
+	}
+	if !algorithms_set.is_empty() {
+		options.algorithms = algorithms_set;
+	}
+	let [graph, out]: [PathBuf; 2] = paths
+		.try_into()
+		.map_err(|_| "expected a graph dir and an out dir".to_string())?;
+	Ok(Args {
+		graph,
+		out,
+		name,
+		json,
+		previews,
+		report,
+		log,
+		options,
+	})
+}
+
+fn main() -> ExitCode {
+	let args = match parse_args(env::args().skip(1)) {
+		Ok(v) => v,
+		Err(e) if e.is_empty() => {
+			println!("{}", USAGE);
+			return ExitCode::SUCCESS;
+		}
+		Err(e) => {
+			eprintln!("{}\n\n{}", e, USAGE);
+			return ExitCode::from(2);
+		}
+	};
+	logger::init_stderr(args.log);
+	match run(args) {
+		Ok(()) => ExitCode::SUCCESS,
+		Err(e) => {
+			log::error!("{}", e);
+			ExitCode::FAILURE
+		}
+	}
+}
+
+fn run(args: Args) -> rkpk::common::RkPkResult<()> {
+	let mut packer = Packer::with_options(args.options);
+	packer.add_dir(&args.graph)?;
+	let mut output = DirOutput {
+		previews: args.previews,
+		..DirOutput::new(&args.out)
+	};
+	let meta = packer.save_build_info(format!("{}.meta", args.name), &args.name, &mut output)?;
+	if args.json {
+		let json = serde_json::to_string_pretty(&meta)?;
+		output.write(&format!("{}.json", args.name), json.as_bytes())?;
+	}
+	if let Some(path) = args.report {
+		fs::write(path, packer.report().to_json()?)?;
+	}
+	log::info!(
+		"packed {} layers into {} pages",
+		meta.layers.len(),
+		meta.pages.len()
+	);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse(args: &[&str]) -> Result<Args, String> {
+		parse_args(args.iter().map(|v| v.to_string()))
+	}
+
+	fn error(args: &[&str]) -> String {
+		match parse(args) {
+			Ok(_) => panic!("{:?} parsed", args),
+			Err(e) => e,
+		}
+	}
+
+	#[test]
+	fn paths_and_defaults() {
+		let args = parse(&["graph", "out"]).unwrap();
+		assert_eq!(args.graph, PathBuf::from("graph"));
+		assert_eq!(args.out, PathBuf::from("out"));
+		assert_eq!(args.name, "graph");
+		assert_eq!(args.log, LevelFilter::Warn);
+		assert!(!args.json && !args.previews && args.report.is_none());
+		assert_eq!(args.options.algorithms, PackOptions::default().algorithms);
+		assert!(args.options.formats.is_empty());
+
+		assert_eq!(error(&["graph"]), "expected a graph dir and an out dir");
+		assert_eq!(
+			error(&["a", "b", "c"]),
+			"expected a graph dir and an out dir"
+		);
+		// help is an empty error so that usage goes to stdout
+		assert_eq!(error(&["--help"]), "");
+		assert_eq!(error(&["graph", "out", "-h"]), "");
+	}
+
+	#[test]
+	fn unknown_options_are_errors() {
+		assert_eq!(error(&["--fast", "graph", "out"]), "unknown option --fast");
+		assert_eq!(error(&["graph", "out", "-x"]), "unknown option -x");
+		assert_eq!(
+			error(&["--algorithm", "guess", "graph", "out"]),
+			"unknown algorithm \"guess\""
+		);
+	}
+
+	#[test]
+	fn missing_and_bad_values_are_errors() {
+		for option in [
+			"--name",
+			"--report",
+			"--max-size",
+			"--format",
+			"--algorithm",
+			"--previous",
+		] {
+			assert_eq!(
+				error(&["graph", "out", option]),
+				format!("{} needs a value", option)
+			);
+		}
+		assert_eq!(
+			error(&["--max-size", "big", "graph", "out"]),
+			"bad value \"big\" for --max-size"
+		);
+		assert_eq!(
+			error(&["--log", "loud", "graph", "out"]),
+			"bad value \"loud\" for --log"
+		);
+		for format in ["ui=r8", "ui/icons", "ui/icons=jpeg"] {
+			assert_eq!(
+				error(&["--format", format, "graph", "out"]),
+				format!("bad layer format {:?}", format)
+			);
+		}
+	}
+
+	#[test]
+	fn repeated_options_add_up() {
+		let args = parse(&[
+			"--format",
+			"ui/icons=r8",
+			"--format",
+			"ui/text=palette",
+			"--format",
+			"ui/icons=bc7",
+			"--algorithm",
+			"skyline",
+			"--algorithm",
+			"empty-spaces",
+			"--name",
+			"first",
+			"--name",
+			"second",
+			"graph",
+			"out",
+		])
+		.unwrap();
+		let format = |group: &str, layer: &str| {
+			args.options.formats[&(group.to_string(), layer.to_string())]
+		};
+		// a layer given twice keeps the last format
+		assert_eq!(args.options.formats.len(), 2);
+		assert_eq!(format("ui", "icons"), PixelFormat::Bc7);
+		assert_eq!(format("ui", "text"), PixelFormat::Palette);
+		assert_eq!(
+			args.options.algorithms,
+			[Algorithm::Skyline, Algorithm::EmptySpaces]
+		);
+		assert_eq!(args.name, "second");
+	}
+}
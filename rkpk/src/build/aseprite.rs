@@ -0,0 +1,423 @@
+//! reading aseprite files
+//!
+//! every frame gets flattened into one sheet laid out left to right, tags
+//! become clips (or one `default` clip if there aren't any). only the
+//! normal blend mode is supported, other modes get drawn as normal and
+//! tilemap layers are skipped
+
+use std::collections::HashMap;
+use std::io::Read;
+
+use crate::common::{
+	AnimationMeta, CompositeImage, ImagePos, LoopMode, NineSlice, RectWH, RectXYWH, RkPkError,
+	RkPkResult,
+};
+
+const CHUNK_OLD_PALETTE: u16 = 0x0004;
+const CHUNK_LAYER: u16 = 0x2004;
+const CHUNK_CEL: u16 = 0x2005;
+const CHUNK_TAGS: u16 = 0x2018;
+const CHUNK_PALETTE: u16 = 0x2019;
+const CHUNK_SLICE: u16 = 0x2022;
+
+/// everything pulled out of an aseprite file
+#[derive(Debug)]
+pub struct Aseprite {
+	/// size of a single frame
+	pub size: RectWH,
+	/// every frame side by side
+	pub sheet: CompositeImage,
+	/// seconds each frame is shown for
+	pub durations: Vec<f32>,
+	/// tags turned into clips
+	pub animations: HashMap<String, AnimationMeta>,
+	/// insets of the first slice that has nine-patch data
+	pub nine_slice: Option<NineSlice>,
+}
+
+struct Layer {
+	visible: bool,
+	child_level: u16,
+	opacity: u8,
+	/// tilemaps and groups don't have pixels of their own
+	drawn: bool,
+}
+
+#[derive(Clone)]
+struct Cel {
+	layer: usize,
+	x: i32,
+	y: i32,
+	opacity: u8,
+	z_index: i16,
+	w: usize,
+	h: usize,
+	/// rgba pixels
+	pixels: Vec<u8>,
+}
+
+/// little endian cursor over the file
+struct Reader<'a> {
+	data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+	fn bytes(&mut self, len: usize) -> RkPkResult<&'a [u8]> {
+		if self.data.len() < len {
+			return Err(RkPkError::BadAseprite("unexpected end of file"));
+		}
+		let (res, rest) = self.data.split_at(len);
+		self.data = rest;
+		Ok(res)
+	}
+	fn u8(&mut self) -> RkPkResult<u8> {
+		Ok(self.bytes(1)?[0])
+	}
+	fn u16(&mut self) -> RkPkResult<u16> {
+		let v = self.bytes(2)?;
+		Ok(u16::from_le_bytes([v[0], v[1]]))
+	}
+	fn i16(&mut self) -> RkPkResult<i16> {
+		Ok(self.u16()? as i16)
+	}
+	fn u32(&mut self) -> RkPkResult<u32> {
+		let v = self.bytes(4)?;
+		Ok(u32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+	}
+	fn i32(&mut self) -> RkPkResult<i32> {
+		Ok(self.u32()? as i32)
+	}
+	fn string(&mut self) -> RkPkResult<String> {
+		let len = self.u16()? as usize;
+		Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+	}
+}
+
+impl Aseprite {
+	pub fn parse(data: &[u8]) -> RkPkResult<Self> {
+		let mut file = Reader { data };
+		let header = file.bytes(128)?;
+		let mut header = Reader { data: header };
+		header.u32()?;
+		if header.u16()? != 0xA5E0 {
+			return Err(RkPkError::BadAseprite("not an aseprite file"));
+		}
+		let frame_count = header.u16()? as usize;
+		let size = RectWH::new(header.u16()?, header.u16()?);
+		let depth = header.u16()?;
+		let layer_opacity = header.u32()? & 1 != 0;
+		header.bytes(10)?;
+		let transparent = header.u8()?;
+		if ![8, 16, 32].contains(&depth) {
+			return Err(RkPkError::BadAseprite("unknown color depth"));
+		}
+		// every frame goes side by side on one sheet
+		if size.w as usize * frame_count > ImagePos::MAX as usize {
+			return Err(RkPkError::BadAseprite("too many frames to fit on a sheet"));
+		}
+
+		let mut layers = vec![];
+		let mut palette = vec![[0; 4]; 256];
+		let mut cels: Vec<Vec<Cel>> = vec![];
+		let mut durations = vec![];
+		let mut animations = HashMap::new();
+		let mut nine_slice = None;
+		for _ in 0..frame_count {
+			let frame_len = file.u32()? as usize;
+			let mut frame_data = Reader {
+				data: file.bytes(frame_len.saturating_sub(4))?,
+			};
+			if frame_data.u16()? != 0xF1FA {
+				return Err(RkPkError::BadAseprite("bad frame magic"));
+			}
+			let old_chunks = frame_data.u16()? as usize;
+			durations.push(frame_data.u16()? as f32 / 1000.0);
+			frame_data.bytes(2)?;
+			let chunks = match frame_data.u32()? as usize {
+				0 => old_chunks,
+				v => v,
+			};
+			let mut frame_cels = vec![];
+			for _ in 0..chunks {
+				let chunk_len = frame_data.u32()? as usize;
+				let kind = frame_data.u16()?;
+				let mut chunk = Reader {
+					data: frame_data.bytes(chunk_len.saturating_sub(6))?,
+				};
+				match kind {
+					CHUNK_OLD_PALETTE => {
+						let mut index = 0;
+						for _ in 0..chunk.u16()? {
+							index += chunk.u8()? as usize;
+							let count = match chunk.u8()? {
+								0 => 256,
+								v => v as usize,
+							};
+							for _ in 0..count {
+								let c = chunk.bytes(3)?;
+								if let Some(entry) = palette.get_mut(index) {
+									*entry = [c[0], c[1], c[2], 0xFF];
+								}
+								index += 1;
+							}
+						}
+					}
+					CHUNK_PALETTE => {
+						chunk.u32()?;
+						let first = chunk.u32()? as usize;
+						let last = chunk.u32()? as usize;
+						chunk.bytes(8)?;
+						for index in first..=last {
+							let flags = chunk.u16()?;
+							let c = chunk.bytes(4)?;
+							if flags & 1 != 0 {
+								chunk.string()?;
+							}
+							if index >= palette.len() {
+								palette.resize(index + 1, [0; 4]);
+							}
+							palette[index] = [c[0], c[1], c[2], c[3]];
+						}
+					}
+					CHUNK_LAYER => {
+						let flags = chunk.u16()?;
+						let kind = chunk.u16()?;
+						let child_level = chunk.u16()?;
+						chunk.bytes(6)?;
+						let opacity = chunk.u8()?;
+						layers.push(Layer {
+							visible: flags & 1 != 0,
+							child_level,
+							opacity: if layer_opacity { opacity } else { 0xFF },
+							drawn: kind == 0,
+						});
+					}
+					CHUNK_CEL => {
+						let layer = chunk.u16()? as usize;
+						let x = chunk.i16()? as i32;
+						let y = chunk.i16()? as i32;
+						let opacity = chunk.u8()?;
+						let kind = chunk.u16()?;
+						let z_index = chunk.i16()?;
+						chunk.bytes(5)?;
+						let cel = |w: usize, h: usize, pixels: Vec<u8>| Cel {
+							layer,
+							x,
+							y,
+							opacity,
+							z_index,
+							w,
+							h,
+							pixels,
+						};
+						match kind {
+							0 | 2 => {
+								let w = chunk.u16()? as usize;
+								let h = chunk.u16()? as usize;
+								let raw = if kind == 0 {
+									chunk.data.to_vec()
+								} else {
+									let mut raw = vec![];
+									flate2::read::ZlibDecoder::new(chunk.data)
+										.read_to_end(&mut raw)?;
+									raw
+								};
+								let pixels = to_rgba(&raw, w * h, depth, &palette, transparent)?;
+								frame_cels.push(cel(w, h, pixels));
+							}
+							1 => {
+								let linked = chunk.u16()? as usize;
+								let source = cels
+									.get(linked)
+									.and_then(|v| v.iter().find(|v| v.layer == layer))
+									.ok_or(RkPkError::BadAseprite("bad linked cel"))?;
+								frame_cels.push(Cel {
+									x,
+									y,
+									opacity,
+									z_index,
+									..source.clone()
+								});
+							}
+							// tilemaps
+							_ => {}
+						}
+					}
+					CHUNK_TAGS => {
+						let count = chunk.u16()?;
+						chunk.bytes(8)?;
+						for _ in 0..count {
+							let from = chunk.u16()? as usize;
+							let to = chunk.u16()? as usize;
+							let direction = chunk.u8()?;
+							let repeat = chunk.u16()?;
+							chunk.bytes(10)?;
+							let name = chunk.string()?;
+							let mut frames: Vec<usize> = (from..=to).collect();
+							if direction == 1 || direction == 3 {
+								frames.reverse();
+							}
+							animations.insert(
+								name,
+								AnimationMeta {
+									frames,
+									durations: vec![],
+									mode: match (direction, repeat) {
+										(_, 1) => LoopMode::Once,
+										(2 | 3, _) => LoopMode::PingPong,
+										_ => LoopMode::Loop,
+									},
+								},
+							);
+						}
+					}
+					CHUNK_SLICE => {
+						let keys = chunk.u32()?;
+						let flags = chunk.u32()?;
+						chunk.u32()?;
+						chunk.string()?;
+						for _ in 0..keys {
+							chunk.u32()?;
+							let x = chunk.i32()?;
+							let y = chunk.i32()?;
+							chunk.u32()?;
+							chunk.u32()?;
+							if flags & 1 != 0 {
+								let cx = x + chunk.i32()?;
+								let cy = y + chunk.i32()?;
+								let cw = chunk.u32()? as i32;
+								let ch = chunk.u32()? as i32;
+								let clamp =
+									|v: i32, max: ImagePos| v.clamp(0, max as i32) as ImagePos;
+								nine_slice.get_or_insert(NineSlice {
+									left: clamp(cx, size.w),
+									top: clamp(cy, size.h),
+									right: clamp(size.w as i32 - cx - cw, size.w),
+									bottom: clamp(size.h as i32 - cy - ch, size.h),
+								});
+							}
+							if flags & 2 != 0 {
+								chunk.bytes(8)?;
+							}
+						}
+					}
+					_ => {}
+				}
+			}
+			cels.push(frame_cels);
+		}
+
+		// a layer only shows if every group it's in does too
+		let mut shown = Vec::with_capacity(layers.len());
+		let mut parents: Vec<bool> = vec![];
+		for layer in &layers {
+			parents.truncate(layer.child_level as usize);
+			let visible = layer.visible && parents.iter().all(|&v| v);
+			parents.push(visible);
+			shown.push(visible && layer.drawn);
+		}
+
+		let mut sheet = CompositeImage {
+			size: RectWH::new(size.w * frame_count as ImagePos, size.h),
+			data: vec![0; size.w as usize * frame_count * size.h as usize * 4],
+		};
+		for (frame, frame_cels) in cels.iter_mut().enumerate() {
+			frame_cels.sort_by_key(|v| (v.layer as i64 + v.z_index as i64, v.z_index));
+			for cel in frame_cels.iter() {
+				if !shown.get(cel.layer).copied().unwrap_or(false) {
+					continue;
+				}
+				let opacity = cel.opacity as u32 * layers[cel.layer].opacity as u32;
+				draw_cel(&mut sheet, size, frame, cel, opacity)?;
+			}
+		}
+		// untagged files still play with their own timing
+		if animations.is_empty() && frame_count > 1 {
+			animations.insert(
+				"default".to_string(),
+				AnimationMeta {
+					frames: (0..frame_count).collect(),
+					durations: vec![],
+					mode: LoopMode::Loop,
+				},
+			);
+		}
+		for clip in animations.values_mut() {
+			if clip.frames.iter().any(|&v| v >= frame_count) {
+				return Err(RkPkError::BadAseprite("tag outside of the frames"));
+			}
+			clip.durations = clip.frames.iter().map(|&v| durations[v]).collect();
+		}
+		Ok(Self {
+			size,
+			sheet,
+			durations,
+			animations,
+			nine_slice,
+		})
+	}
+}
+
+/// convert `count` pixels of any color depth to rgba
+fn to_rgba(
+	raw: &[u8],
+	count: usize,
+	depth: u16,
+	palette: &[[u8; 4]],
+	transparent: u8,
+) -> RkPkResult<Vec<u8>> {
+	let bytes = depth as usize / 8;
+	if raw.len() < count * bytes {
+		return Err(RkPkError::BadAseprite("cel is missing pixels"));
+	}
+	let raw = raw[..count * bytes].chunks_exact(bytes);
+	Ok(match depth {
+		32 => raw.flatten().copied().collect(),
+		16 => raw.flat_map(|v| [v[0], v[0], v[0], v[1]]).collect(),
+		_ => raw
+			.flat_map(|v| match v[0] {
+				i if i == transparent => [0; 4],
+				i => palette.get(i as usize).copied().unwrap_or_default(),
+			})
+			.collect(),
+	})
+}
+
+/// alpha-over the part of a cel inside its frame onto the sheet, `opacity`
+/// is out of 255²
+fn draw_cel(
+	sheet: &mut CompositeImage,
+	size: RectWH,
+	frame: usize,
+	cel: &Cel,
+	opacity: u32,
+) -> RkPkResult<()> {
+	let x0 = cel.x.max(0);
+	let y0 = cel.y.max(0);
+	let x1 = (cel.x + cel.w as i32).min(size.w as i32);
+	let y1 = (cel.y + cel.h as i32).min(size.h as i32);
+	if x0 >= x1 || y0 >= y1 {
+		return Ok(());
+	}
+	let mut data = cel.pixels.clone();
+	for v in data.chunks_exact_mut(4) {
+		v[3] = ((v[3] as u32 * opacity + 255 * 255 / 2) / (255 * 255)) as u8;
+	}
+	let image = CompositeImage {
+		size: RectWH::new(cel.w as ImagePos, cel.h as ImagePos),
+		data,
+	};
+	sheet.blend_from(
+		&image,
+		RectWH::new(
+			(frame * size.w as usize) as ImagePos + x0 as ImagePos,
+			y0 as ImagePos,
+		),
+		RectXYWH::new(
+			(x0 - cel.x) as ImagePos,
+			(y0 - cel.y) as ImagePos,
+			(x1 - x0) as ImagePos,
+			(y1 - y0) as ImagePos,
+		),
+	)
+}
@@ -0,0 +1,430 @@
+//! collision shapes traced from the alpha of frames
+//!
+//! outlines follow pixel edges, so a frame made of whole opaque pixels gets
+//! exactly its shape back before it's simplified
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::common::{CollisionMeta, CompositeImage, RectWH, RectXYWH};
+
+/// contents of a `[layer].collision` sidecar
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct CollisionSidecar {
+	/// pixels with at least this much alpha are solid
+	pub threshold: u8,
+	/// how far in pixels a simplified outline can stray from the traced one,
+	/// 0 keeps every corner
+	pub tolerance: f32,
+}
+
+impl Default for CollisionSidecar {
+	fn default() -> Self {
+		Self {
+			threshold: 128,
+			tolerance: 1.0,
+		}
+	}
+}
+
+type Point = [i32; 2];
+
+fn add(a: Point, b: Point) -> Point {
+	[a[0] + b[0], a[1] + b[1]]
+}
+
+/// clockwise on screen, y goes down
+fn turn_right(d: Point) -> Point {
+	[-d[1], d[0]]
+}
+
+fn turn_left(d: Point) -> Point {
+	[d[1], -d[0]]
+}
+
+/// which pixels of `uv` are solid, row by row
+struct Mask {
+	size: RectWH,
+	solid: Vec<bool>,
+}
+
+impl Mask {
+	fn new(source: &CompositeImage, uv: RectXYWH, threshold: u8) -> Self {
+		let mut solid = Vec::with_capacity(uv.area() as usize);
+		for y in uv.y..uv.y + uv.h {
+			let start = (y as usize * source.size.w as usize + uv.x as usize) * 4;
+			let row = &source.data[start..start + uv.w as usize * 4];
+			solid.extend(row.chunks_exact(4).map(|v| v[3] >= threshold));
+		}
+		Self {
+			size: uv.to_wh(),
+			solid,
+		}
+	}
+	fn get(&self, x: i32, y: i32) -> bool {
+		x >= 0
+			&& y >= 0 && x < self.size.w as i32
+			&& y < self.size.h as i32
+			&& self.solid[y as usize * self.size.w as usize + x as usize]
+	}
+	/// smallest rect holding every solid pixel
+	fn bounds(&self) -> Option<RectXYWH> {
+		let mut min = [i32::MAX; 2];
+		let mut max = [i32::MIN; 2];
+		for y in 0..self.size.h as i32 {
+			for x in 0..self.size.w as i32 {
+				if self.get(x, y) {
+					min = [min[0].min(x), min[1].min(y)];
+					max = [max[0].max(x), max[1].max(y)];
+				}
+			}
+		}
+		(min[0] <= max[0]).then(|| {
+			RectXYWH::new(
+				min[0] as _,
+				min[1] as _,
+				(max[0] - min[0] + 1) as _,
+				(max[1] - min[1] + 1) as _,
+			)
+		})
+	}
+	/// closed loops along the edges between solid and empty pixels, with the
+	/// solid side on the right. outer edges go clockwise on screen and holes
+	/// counter clockwise, diagonal neighbours aren't joined
+	fn outlines(&self) -> Vec<Vec<Point>> {
+		let mut edges = vec![];
+		for y in 0..self.size.h as i32 {
+			for x in 0..self.size.w as i32 {
+				if !self.get(x, y) {
+					continue;
+				}
+				if !self.get(x, y - 1) {
+					edges.push(([x, y], [1, 0]));
+				}
+				if !self.get(x + 1, y) {
+					edges.push(([x + 1, y], [0, 1]));
+				}
+				if !self.get(x, y + 1) {
+					edges.push(([x + 1, y + 1], [-1, 0]));
+				}
+				if !self.get(x - 1, y) {
+					edges.push(([x, y + 1], [0, -1]));
+				}
+			}
+		}
+		let mut outgoing: HashMap<Point, Vec<Point>> = HashMap::new();
+		for &(start, dir) in &edges {
+			outgoing.entry(start).or_default().push(dir);
+		}
+		let mut take = |point: Point, dir: Point| {
+			let dirs = outgoing.get_mut(&point)?;
+			let i = dirs.iter().position(|&v| v == dir)?;
+			Some(dirs.swap_remove(i))
+		};
+		let mut res = vec![];
+		'outlines: for (start, dir) in edges {
+			if take(start, dir).is_none() {
+				continue;
+			}
+			let mut outline = vec![start];
+			let mut dir = dir;
+			let mut point = add(start, dir);
+			while point != start {
+				outline.push(point);
+				// turning right first keeps pixels that only touch at a
+				// corner apart
+				dir = match [turn_right(dir), dir, turn_left(dir)]
+					.into_iter()
+					.find_map(|v| take(point, v))
+				{
+					Some(v) => v,
+					// every point has as many edges in as out, so this can't
+					// happen, but an open outline is no use to anyone
+					None => continue 'outlines,
+				};
+				point = add(point, dir);
+			}
+			res.push(corners(&outline));
+		}
+		res
+	}
+}
+
+/// drop points that are in a straight line with their neighbours
+fn corners(outline: &[Point]) -> Vec<Point> {
+	let n = outline.len();
+	(0..n)
+		.filter(|&i| {
+			let prev = outline[(i + n - 1) % n];
+			let next = outline[(i + 1) % n];
+			let a = [outline[i][0] - prev[0], outline[i][1] - prev[1]];
+			let b = [next[0] - outline[i][0], next[1] - outline[i][1]];
+			a[0] * b[1] - a[1] * b[0] != 0
+		})
+		.map(|i| outline[i])
+		.collect()
+}
+
+/// distance from `p` to the line through `a` and `b`
+fn distance(p: Point, a: Point, b: Point) -> f32 {
+	let d = [(b[0] - a[0]) as f32, (b[1] - a[1]) as f32];
+	let v = [(p[0] - a[0]) as f32, (p[1] - a[1]) as f32];
+	let len = d[0].hypot(d[1]);
+	if len == 0.0 {
+		v[0].hypot(v[1])
+	} else {
+		(v[0] * d[1] - v[1] * d[0]).abs() / len
+	}
+}
+
+/// ramer-douglas-peucker between `points[first]` and `points[last]`
+fn simplify_span(points: &[Point], first: usize, last: usize, tolerance: f32, keep: &mut [bool]) {
+	let far = (first + 1..last)
+		.map(|i| (i, distance(points[i], points[first], points[last])))
+		.max_by(|a, b| a.1.total_cmp(&b.1));
+	if let Some((i, d)) = far {
+		if d > tolerance {
+			keep[i] = true;
+			simplify_span(points, first, i, tolerance, keep);
+			simplify_span(points, i, last, tolerance, keep);
+		}
+	}
+}
+
+/// simplify a closed outline, split at its first point and the point
+/// furthest from it. outlines that would collapse are kept as they are
+fn simplify(outline: &[Point], tolerance: f32) -> Vec<Point> {
+	if tolerance <= 0.0 || outline.len() <= 4 {
+		return outline.to_vec();
+	}
+	let dist = |p: Point| {
+		let v = [(p[0] - outline[0][0]) as f32, (p[1] - outline[0][1]) as f32];
+		v[0].hypot(v[1])
+	};
+	let far = (1..outline.len())
+		.max_by(|&a, &b| dist(outline[a]).total_cmp(&dist(outline[b])))
+		.unwrap_or(0);
+	let mut closed = outline.to_vec();
+	closed.push(outline[0]);
+	let mut keep = vec![false; closed.len()];
+	keep[0] = true;
+	keep[far] = true;
+	simplify_span(&closed, 0, far, tolerance, &mut keep);
+	simplify_span(&closed, far, outline.len(), tolerance, &mut keep);
+	let res = outline
+		.iter()
+		.zip(keep)
+		.filter(|v| v.1)
+		.map(|v| *v.0)
+		.collect::<Vec<_>>();
+	if res.len() < 3 {
+		outline.to_vec()
+	} else {
+		res
+	}
+}
+
+/// convex hull of `points` clockwise on screen, monotone chain
+fn hull(mut points: Vec<Point>) -> Vec<Point> {
+	points.sort_unstable();
+	points.dedup();
+	if points.len() < 3 {
+		return points;
+	}
+	let cross = |o: Point, a: Point, b: Point| {
+		(a[0] - o[0]) as i64 * (b[1] - o[1]) as i64 - (a[1] - o[1]) as i64 * (b[0] - o[0]) as i64
+	};
+	let mut res: Vec<Point> = vec![];
+	for pass in [
+		&points[..],
+		&points.iter().rev().copied().collect::<Vec<_>>(),
+	] {
+		let start = res.len();
+		for &p in pass {
+			while res.len() >= start + 2 && cross(res[res.len() - 2], res[res.len() - 1], p) <= 0 {
+				res.pop();
+			}
+			res.push(p);
+		}
+		// the last point starts the other half
+		res.pop();
+	}
+	res
+}
+
+/// collision shape of the part of `source` in `uv`, relative to the top left
+/// of the untrimmed frame which is `offset` away from it
+pub fn shape(
+	source: &CompositeImage,
+	uv: Option<RectXYWH>,
+	offset: RectWH,
+	sidecar: &CollisionSidecar,
+) -> CollisionMeta {
+	let Some(uv) = uv else {
+		return CollisionMeta::default();
+	};
+	let mask = Mask::new(source, uv, sidecar.threshold);
+	let Some(mut aabb) = mask.bounds() else {
+		return CollisionMeta::default();
+	};
+	aabb.x += offset.w;
+	aabb.y += offset.h;
+	let outlines = mask.outlines();
+	let place = |points: Vec<Point>| {
+		points
+			.into_iter()
+			.map(|v| {
+				[
+					(v[0] + offset.w as i32) as f32,
+					(v[1] + offset.h as i32) as f32,
+				]
+			})
+			.collect::<Vec<_>>()
+	};
+	CollisionMeta {
+		aabb,
+		hull: place(hull(outlines.concat())),
+		polygons: outlines
+			.iter()
+			.map(|v| place(simplify(v, sidecar.tolerance)))
+			.collect(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// `#` is solid
+	fn mask(rows: &[&str]) -> Mask {
+		Mask {
+			size: RectWH::new(rows[0].len() as _, rows.len() as _),
+			solid: rows
+				.iter()
+				.flat_map(|v| v.chars())
+				.map(|v| v == '#')
+				.collect(),
+		}
+	}
+
+	/// twice the area, positive if the points go clockwise on screen
+	fn signed_area(points: &[Point]) -> i32 {
+		(0..points.len())
+			.map(|i| {
+				let [a, b] = [points[i], points[(i + 1) % points.len()]];
+				a[0] * b[1] - b[0] * a[1]
+			})
+			.sum()
+	}
+
+	/// distance from `p` to the closest point between `a` and `b`
+	fn segment_distance(p: Point, a: Point, b: Point) -> f32 {
+		let d = [(b[0] - a[0]) as f32, (b[1] - a[1]) as f32];
+		let v = [(p[0] - a[0]) as f32, (p[1] - a[1]) as f32];
+		let t = ((v[0] * d[0] + v[1] * d[1]) / (d[0] * d[0] + d[1] * d[1])).clamp(0.0, 1.0);
+		(v[0] - d[0] * t).hypot(v[1] - d[1] * t)
+	}
+
+	#[test]
+	fn filled_rect() {
+		let outlines = mask(&["###", "###"]).outlines();
+		assert_eq!(outlines, [vec![[0, 0], [3, 0], [3, 2], [0, 2]]]);
+		assert_eq!(signed_area(&outlines[0]), 12);
+	}
+
+	#[test]
+	fn holes_go_the_other_way() {
+		let outlines = mask(&["####", "#..#", "####"]).outlines();
+		let mut areas = outlines.iter().map(|v| signed_area(v)).collect::<Vec<_>>();
+		areas.sort();
+		assert_eq!(areas, [-4, 24]);
+		let hole = outlines.iter().find(|v| signed_area(v) < 0).unwrap();
+		let mut corners = hole.clone();
+		corners.sort();
+		assert_eq!(corners, [[1, 1], [1, 2], [3, 1], [3, 2]]);
+	}
+
+	#[test]
+	fn diagonal_pixels_stay_apart() {
+		for rows in [["#.", ".#"], [".#", "#."]] {
+			let outlines = mask(&rows).outlines();
+			assert_eq!(outlines.len(), 2, "{:?}", rows);
+			for outline in outlines {
+				assert_eq!(outline.len(), 4);
+				assert_eq!(signed_area(&outline), 2);
+			}
+		}
+		// an x of five pixels is five shapes
+		let outlines = mask(&["#.#", ".#.", "#.#"]).outlines();
+		assert_eq!(outlines.len(), 5);
+	}
+
+	#[test]
+	fn l_shape() {
+		let mut image = CompositeImage {
+			size: RectWH::new(5, 5),
+			data: vec![0; 5 * 5 * 4],
+		};
+		for (x, y) in [(1, 1), (1, 2), (1, 3), (2, 3), (3, 3)] {
+			image.data[(y * 5 + x) * 4 + 3] = 0xFF;
+		}
+		// keeps the inside corner, which is only a pixel away from the hull
+		let sidecar = CollisionSidecar {
+			tolerance: 0.0,
+			..Default::default()
+		};
+		let uv = RectXYWH::new(1, 0, 4, 4);
+		let l = shape(&image, Some(uv), RectWH::new(2, 1), &sidecar);
+		assert_eq!(l.aabb, RectXYWH::new(2, 2, 3, 3));
+		let hull = [[2.0, 2.0], [3.0, 2.0], [5.0, 4.0], [5.0, 5.0], [2.0, 5.0]];
+		assert_eq!(l.hull.len(), hull.len());
+		let start = l.hull.iter().position(|v| *v == hull[0]).unwrap();
+		for (i, point) in hull.iter().enumerate() {
+			assert_eq!(l.hull[(start + i) % hull.len()], *point);
+		}
+		assert_eq!(l.polygons.len(), 1);
+		assert_eq!(l.polygons[0].len(), 6);
+		// nothing solid, nothing to collide with
+		let empty = shape(
+			&image,
+			Some(RectXYWH::new(0, 0, 1, 5)),
+			RectWH::default(),
+			&sidecar,
+		);
+		assert_eq!(empty, CollisionMeta::default());
+		assert_eq!(
+			shape(&image, None, RectWH::default(), &sidecar),
+			CollisionMeta::default()
+		);
+	}
+
+	#[test]
+	fn simplify_within_tolerance() {
+		let outlines = mask(&[
+			"#.......", "##......", "###.....", "####....", "#####...", "######..", "#######.",
+			"########",
+		])
+		.outlines();
+		let outline = &outlines[0];
+		assert_eq!(outline.len(), 2 + 8 * 2);
+		assert_eq!(simplify(outline, 0.0), *outline);
+		let simple = simplify(outline, 1.0);
+		assert!(simple.len() >= 3 && simple.len() < outline.len());
+		assert!(signed_area(&simple) > 0);
+		// every point it kept is an original corner, in the same order
+		let mut kept = outline.iter();
+		assert!(simple.iter().all(|v| kept.any(|w| w == v)));
+		// and nothing it dropped is further away than the tolerance
+		for &point in outline {
+			let closest = (0..simple.len())
+				.map(|i| segment_distance(point, simple[i], simple[(i + 1) % simple.len()]))
+				.fold(f32::MAX, f32::min);
+			assert!(closest <= 1.0, "{:?} is {} away", point, closest);
+		}
+		// tiny outlines are left alone
+		let pixel = &mask(&["#"]).outlines()[0];
+		assert_eq!(simplify(pixel, 1.0), *pixel);
+	}
+}
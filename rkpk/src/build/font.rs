@@ -0,0 +1,523 @@
+//! turning fonts into frames
+//!
+//! every glyph becomes a frame of a sheet that gets packed like an image
+//! with an `.atlas` sidecar, the metrics end up in `LayerMeta::fonts`
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use ab_glyph::{Font, FontRef, OutlineCurve, PxScale, ScaleFont};
+use serde::Deserialize;
+
+use super::sdf::{self, Segment};
+use super::{transparent, SheetBuilder};
+use crate::common::{
+	CompositeImage, FontMeta, GlyphMeta, ImagePos, RectWH, RectXYWH, RkPkError, RkPkResult,
+	SdfMeta, WithPath,
+};
+
+/// contents of a `.font` sidecar
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct FontSidecar {
+	/// pixel sizes of an em to rasterize a ttf/otf at
+	pub sizes: Vec<u16>,
+	/// characters to include, for grid fonts in the same order as the cells
+	pub chars: String,
+	/// snap coverage to fully opaque or fully transparent, for pixel fonts
+	pub mono: bool,
+	/// cell size of a png grid font
+	pub grid: Option<RectWH>,
+	/// row of a grid cell the baseline is on, the bottom of the cell if
+	/// it's not set
+	pub baseline: Option<ImagePos>,
+	/// advance grid glyphs by how wide they actually are instead of the
+	/// cell width
+	pub proportional: bool,
+	/// extra pixels after every grid glyph
+	pub spacing: f32,
+}
+
+impl Default for FontSidecar {
+	fn default() -> Self {
+		Self {
+			sizes: vec![16],
+			chars: (' '..='~').collect(),
+			mono: false,
+			grid: None,
+			baseline: None,
+			proportional: false,
+			spacing: 0.0,
+		}
+	}
+}
+
+/// glyphs laid out on a sheet, ready to be packed
+#[derive(Debug)]
+pub struct FontSheet {
+	pub sheet: CompositeImage,
+	/// where each frame is on the sheet
+	pub rects: Vec<RectXYWH>,
+	pub meta: FontMeta,
+	/// the glyphs are already distance fields padded by the layer's spread
+	pub distance_field: bool,
+}
+
+fn bad_font(path: &Path) -> RkPkError {
+	RkPkError::BadFont(path.to_string_lossy().into_owned())
+}
+
+/// rasterize a ttf/otf at every size in `settings`, glyphs of distance field
+/// layers are made straight from the outlines
+pub fn rasterize(
+	path: &Path,
+	settings: &FontSidecar,
+	sdf: Option<SdfMeta>,
+) -> RkPkResult<Vec<(u16, FontSheet)>> {
+	let data = fs::read(path).with_path(path)?;
+	let font = FontRef::try_from_slice(&data).map_err(|_| bad_font(path))?;
+	let units_per_em = font.units_per_em().ok_or_else(|| bad_font(path))?;
+	let chars = settings
+		.chars
+		.chars()
+		.filter(|&c| c == ' ' || font.glyph_id(c).0 != 0)
+		.collect::<Vec<_>>();
+	let mut res = vec![];
+	for &size in &settings.sizes {
+		// sizes are em sizes, `PxScale` is the height of ascent to descent
+		let scale = PxScale::from(size as f32 * font.height_unscaled() / units_per_em);
+		let scaled = font.as_scaled(scale);
+		let mut meta = FontMeta {
+			line_height: (scaled.height() + scaled.line_gap()).round(),
+			ascent: scaled.ascent().round(),
+			..Default::default()
+		};
+		let mut glyphs = SheetBuilder::default();
+		for &c in &chars {
+			let id = font.glyph_id(c);
+			let (image, bearing) = match font.outline_glyph(id.with_scale(scale)) {
+				Some(outlined) => {
+					let bounds = outlined.px_bounds();
+					let size = RectWH::new(bounds.width() as ImagePos, bounds.height() as ImagePos);
+					let bearing = [bounds.min.x, bounds.min.y];
+					if let Some(sdf) = sdf {
+						let outline = font.outline(id).ok_or_else(|| bad_font(path))?;
+						let scale = [scaled.h_scale_factor(), scaled.v_scale_factor()];
+						let segments = outline
+							.curves
+							.iter()
+							.map(|v| segment(v, scale))
+							.collect::<Vec<_>>();
+						let spread = sdf.spread as f32;
+						let image = sdf::from_outline(
+							&segments,
+							[bearing[0] - spread, bearing[1] - spread],
+							RectWH::new(size.w + sdf.spread * 2, size.h + sdf.spread * 2),
+							sdf.spread,
+							sdf.multi,
+						);
+						(image, bearing)
+					} else {
+						(coverage(&outlined, size, settings.mono), bearing)
+					}
+				}
+				None => (transparent(RectWH::default()), [0.0, 0.0]),
+			};
+			meta.glyphs.insert(
+				c,
+				GlyphMeta {
+					frame: glyphs.push(image),
+					bearing,
+					advance: scaled.h_advance(id),
+				},
+			);
+		}
+		for &a in &chars {
+			for &b in &chars {
+				let kern = scaled.kern(font.glyph_id(a), font.glyph_id(b));
+				if kern != 0.0 {
+					meta.kerning.insert((a, b), kern);
+				}
+			}
+		}
+		let (sheet, rects) = glyphs.finish()?;
+		res.push((
+			size,
+			FontSheet {
+				sheet,
+				rects,
+				meta,
+				distance_field: sdf.is_some(),
+			},
+		));
+	}
+	Ok(res)
+}
+
+/// glyph with its coverage as alpha
+fn coverage(outlined: &ab_glyph::OutlinedGlyph, size: RectWH, mono: bool) -> CompositeImage {
+	let mut image = transparent(size);
+	outlined.draw(|x, y, coverage| {
+		let alpha = if mono {
+			if coverage >= 0.5 {
+				0xFF
+			} else {
+				0
+			}
+		} else {
+			(coverage.clamp(0.0, 1.0) * 255.0).round() as u8
+		};
+		let i = (y as usize * size.w as usize + x as usize) * 4;
+		if let Some(pixel) = image.data.get_mut(i..i + 4) {
+			pixel.copy_from_slice(&[0xFF, 0xFF, 0xFF, alpha]);
+		}
+	});
+	image
+}
+
+/// outline curve in pixels with y going down, outlines are in font units
+/// with y going up
+fn segment(curve: &OutlineCurve, scale: [f32; 2]) -> Segment {
+	let p = |v: ab_glyph::Point| [v.x * scale[0], -v.y * scale[1]];
+	match *curve {
+		OutlineCurve::Line(a, b) => Segment::Line(p(a), p(b)),
+		OutlineCurve::Quad(a, b, c) => Segment::Quad(p(a), p(b), p(c)),
+		OutlineCurve::Cubic(a, b, c, d) => Segment::Cubic(p(a), p(b), p(c), p(d)),
+	}
+}
+
+/// cut a png up into same size cells, one per character
+pub fn grid(path: &Path, image: &CompositeImage, settings: &FontSidecar) -> RkPkResult<FontSheet> {
+	let cell = settings.grid.ok_or_else(|| bad_font(path))?;
+	let columns = image.size.w.checked_div(cell.w).unwrap_or(0);
+	let baseline = settings.baseline.unwrap_or(cell.h) as f32;
+	let mut meta = FontMeta {
+		line_height: cell.h as f32,
+		ascent: baseline,
+		..Default::default()
+	};
+	let mut glyphs = SheetBuilder::default();
+	for (i, c) in settings.chars.chars().enumerate() {
+		let (column, row) = (i % columns.max(1) as usize, i / columns.max(1) as usize);
+		let uv = RectXYWH::new(
+			(column * cell.w as usize) as ImagePos,
+			(row * cell.h as usize) as ImagePos,
+			cell.w,
+			cell.h,
+		);
+		if columns == 0 || uv.y as usize + cell.h as usize > image.size.h as usize {
+			return Err(bad_font(path));
+		}
+		let mut glyph = transparent(cell);
+		glyph.copy_from(image, RectWH::default(), uv)?;
+		let advance = if settings.proportional {
+			// blank glyphs like spaces keep half a cell
+			image
+				.opaque_bounds(uv)
+				.map_or(cell.w as f32 / 2.0, |v| (v.x + v.w - uv.x) as f32)
+		} else {
+			cell.w as f32
+		};
+		meta.glyphs.insert(
+			c,
+			GlyphMeta {
+				frame: glyphs.push(glyph),
+				bearing: [0.0, -baseline],
+				advance: advance + settings.spacing,
+			},
+		);
+	}
+	let (sheet, rects) = glyphs.finish()?;
+	Ok(FontSheet {
+		sheet,
+		rects,
+		meta,
+		distance_field: false,
+	})
+}
+
+/// split a bmfont line into its tag and `key=value` pairs, values can be
+/// quoted
+fn bmfont_line(line: &str) -> (&str, HashMap<&str, &str>) {
+	let line = line.trim();
+	let (tag, mut rest) = line.split_once(' ').unwrap_or((line, ""));
+	let mut values = HashMap::new();
+	loop {
+		rest = rest.trim_start();
+		let (key, after) = match rest.split_once('=') {
+			Some(v) => v,
+			None => break,
+		};
+		let (value, after) = match after.strip_prefix('"') {
+			Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+			None => after.split_once(' ').unwrap_or((after, "")),
+		};
+		values.insert(key.trim(), value);
+		rest = after;
+	}
+	(tag, values)
+}
+
+/// read a text bmfont and the pages next to it
+pub fn bmfont(path: &Path) -> RkPkResult<FontSheet> {
+	let text = fs::read_to_string(path).with_path(path)?;
+	let dir = path.parent().unwrap_or_else(|| Path::new("."));
+	let mut pages = HashMap::new();
+	let mut meta = FontMeta::default();
+	let mut glyphs = SheetBuilder::default();
+	let mut chars = vec![];
+	for line in text.lines() {
+		let (tag, values) = bmfont_line(line);
+		let int = |key: &str| -> RkPkResult<i32> {
+			values
+				.get(key)
+				.and_then(|v| v.parse().ok())
+				.ok_or_else(|| bad_font(path))
+		};
+		match tag {
+			"common" => {
+				meta.line_height = int("lineHeight")? as f32;
+				meta.ascent = int("base")? as f32;
+			}
+			"page" => {
+				let file = values.get("file").ok_or_else(|| bad_font(path))?;
+				pages.insert(int("id")?, CompositeImage::open(dir.join(file))?);
+			}
+			"char" => {
+				let mut char = [0; 9];
+				let keys = [
+					"id", "x", "y", "width", "height", "xoffset", "yoffset", "xadvance", "page",
+				];
+				for (value, key) in char.iter_mut().zip(keys) {
+					*value = int(key)?;
+				}
+				chars.push(char);
+			}
+			"kerning" => {
+				let char = |v: i32| char::from_u32(v as u32).ok_or_else(|| bad_font(path));
+				meta.kerning.insert(
+					(char(int("first")?)?, char(int("second")?)?),
+					int("amount")? as f32,
+				);
+			}
+			_ => {}
+		}
+	}
+	for [id, x, y, w, h, x_offset, y_offset, advance, page] in chars {
+		let c = char::from_u32(id as u32).ok_or_else(|| bad_font(path))?;
+		let page = pages.get(&page).ok_or_else(|| bad_font(path))?;
+		if x < 0 || y < 0 || w < 0 || h < 0 {
+			return Err(bad_font(path));
+		}
+		let uv = RectXYWH::new(x as ImagePos, y as ImagePos, w as ImagePos, h as ImagePos);
+		if x + w > page.size.w as i32 || y + h > page.size.h as i32 {
+			return Err(bad_font(path));
+		}
+		let mut glyph = transparent(uv.to_wh());
+		glyph.copy_from(page, RectWH::default(), uv)?;
+		meta.glyphs.insert(
+			c,
+			GlyphMeta {
+				frame: glyphs.push(glyph),
+				bearing: [x_offset as f32, (y_offset - meta.ascent as i32) as f32],
+				advance: advance as f32,
+			},
+		);
+	}
+	let (sheet, rects) = glyphs.finish()?;
+	Ok(FontSheet {
+		sheet,
+		rects,
+		meta,
+		distance_field: false,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// a 12x8 sheet of 4x4 cells, cell `i` is opaque for its first `i % 4`
+	/// columns
+	fn cells() -> CompositeImage {
+		let mut image = transparent(RectWH::new(12, 8));
+		for (i, v) in image.data.chunks_exact_mut(4).enumerate() {
+			let (x, y) = (i % 12, i / 12);
+			let cell = x / 4 + y / 4 * 3;
+			if x % 4 < cell % 4 {
+				v.copy_from_slice(&[0xFF; 4]);
+			}
+		}
+		image
+	}
+
+	/// write a bmfont and a page of `cells` into a new folder
+	fn write_bmfont(name: &str, text: &str) -> std::path::PathBuf {
+		let dir = std::env::temp_dir().join(format!("rkpk-{}-{}", name, std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		let page = cells();
+		image::save_buffer(
+			dir.join("page 0.png"),
+			&page.data,
+			12,
+			8,
+			image::ColorType::Rgba8,
+		)
+		.unwrap();
+		let path = dir.join("font.fnt");
+		fs::write(&path, text).unwrap();
+		path
+	}
+
+	#[test]
+	fn bmfont_lines_keep_quoted_values_whole() {
+		let (tag, values) =
+			bmfont_line(r#"info face="Some Font" size=16 charset="" padding=0,0,0,0"#);
+		assert_eq!(tag, "info");
+		assert_eq!(values["face"], "Some Font");
+		assert_eq!(values["size"], "16");
+		assert_eq!(values["charset"], "");
+		assert_eq!(values["padding"], "0,0,0,0");
+		let (tag, values) = bmfont_line("  page id=0   file=\"page 0.png\"");
+		assert_eq!(tag, "page");
+		assert_eq!(values["id"], "0");
+		assert_eq!(values["file"], "page 0.png");
+		assert_eq!(bmfont_line("chars").0, "chars");
+	}
+
+	#[test]
+	fn bmfont_reads_chars_and_kerning() {
+		let path = write_bmfont(
+			"bmfont",
+			"info face=\"Test\" size=8\n\
+			 common lineHeight=10 base=7 scaleW=12 scaleH=8 pages=1\n\
+			 page id=0 file=\"page 0.png\"\n\
+			 chars count=2\n\
+			 char id=65 x=4 y=0 width=4 height=4 xoffset=1 yoffset=3 xadvance=5 page=0\n\
+			 char id=66 x=8 y=4 width=4 height=4 xoffset=-1 yoffset=0 xadvance=6 page=0\n\
+			 kernings count=1\n\
+			 kerning first=65 second=66 amount=-2\n",
+		);
+		let font = bmfont(&path).unwrap();
+		fs::remove_dir_all(path.parent().unwrap()).unwrap();
+		assert_eq!(font.meta.line_height, 10.0);
+		assert_eq!(font.meta.ascent, 7.0);
+		let a = font.meta.glyphs[&'A'];
+		assert_eq!(a.bearing, [1.0, -4.0]);
+		assert_eq!(a.advance, 5.0);
+		let b = font.meta.glyphs[&'B'];
+		assert_eq!(b.bearing, [-1.0, -7.0]);
+		assert_eq!(b.advance, 6.0);
+		assert_eq!(font.meta.kerning[&('A', 'B')], -2.0);
+		// glyphs are copied out of the page as they are
+		let page = cells();
+		for (glyph, uv) in [
+			(a, RectXYWH::new(4, 0, 4, 4)),
+			(b, RectXYWH::new(8, 4, 4, 4)),
+		] {
+			let rect = font.rects[glyph.frame];
+			assert_eq!(rect.to_wh(), uv.to_wh());
+			for y in 0..4 {
+				for x in 0..4 {
+					let at = |image: &CompositeImage, x: ImagePos, y: ImagePos| {
+						let i = (y as usize * image.size.w as usize + x as usize) * 4;
+						image.data[i..i + 4].to_vec()
+					};
+					assert_eq!(
+						at(&font.sheet, rect.x + x, rect.y + y),
+						at(&page, uv.x + x, uv.y + y)
+					);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn bmfont_rejects_bad_char_fields() {
+		let char = "char id=65 x=0 y=0 width=4 height=4 xoffset=0 yoffset=0 xadvance=4 page=0";
+		for (i, line) in [
+			char.replace("xoffset=0", "xoffset=left"),
+			char.replace(" yoffset=0", ""),
+			char.replace("xadvance=4", "xadvance="),
+			char.replace("page=0", "page=1"),
+			"kerning first=65 second=66 amount=much".to_string(),
+		]
+		.iter()
+		.enumerate()
+		{
+			let path = write_bmfont(
+				&format!("bad-bmfont-{}", i),
+				&format!(
+					"common lineHeight=8 base=8\npage id=0 file=\"page 0.png\"\n{}\n",
+					line
+				),
+			);
+			let res = bmfont(&path);
+			fs::remove_dir_all(path.parent().unwrap()).unwrap();
+			assert!(
+				matches!(&res, Err(RkPkError::BadFont(v)) if *v == path.to_string_lossy()),
+				"{}: {:?}",
+				line,
+				res
+			);
+		}
+	}
+
+	#[test]
+	fn grid_advances_by_cell_or_by_opaque_width() {
+		let path = Path::new("grid.png");
+		let mut settings = FontSidecar {
+			chars: "abcdef".into(),
+			grid: Some(RectWH::new(4, 4)),
+			baseline: Some(3),
+			..Default::default()
+		};
+		let font = grid(path, &cells(), &settings).unwrap();
+		assert_eq!(font.rects.len(), 6);
+		assert_eq!(font.meta.line_height, 4.0);
+		assert_eq!(font.meta.ascent, 3.0);
+		for c in settings.chars.chars() {
+			assert_eq!(font.meta.glyphs[&c].advance, 4.0, "{}", c);
+			assert_eq!(font.meta.glyphs[&c].bearing, [0.0, -3.0], "{}", c);
+		}
+
+		settings.proportional = true;
+		settings.spacing = 1.0;
+		let font = grid(path, &cells(), &settings).unwrap();
+		// blank cells keep half a cell, the rest go as far as their last
+		// opaque column
+		let advances = settings
+			.chars
+			.chars()
+			.map(|c| font.meta.glyphs[&c].advance)
+			.collect::<Vec<_>>();
+		assert_eq!(advances, [3.0, 2.0, 3.0, 4.0, 3.0, 2.0]);
+	}
+
+	#[test]
+	fn grid_rejects_characters_past_the_last_row() {
+		let path = Path::new("grid.png");
+		let mut settings = FontSidecar {
+			chars: "abcdefg".into(),
+			grid: Some(RectWH::new(4, 4)),
+			..Default::default()
+		};
+		assert!(matches!(
+			grid(path, &cells(), &settings),
+			Err(RkPkError::BadFont(v)) if v == "grid.png"
+		));
+		// cells wider than the image leave no columns at all
+		settings.grid = Some(RectWH::new(16, 4));
+		settings.chars = "a".into();
+		assert!(matches!(
+			grid(path, &cells(), &settings),
+			Err(RkPkError::BadFont(_))
+		));
+		settings.grid = None;
+		assert!(matches!(
+			grid(path, &cells(), &settings),
+			Err(RkPkError::BadFont(_))
+		));
+	}
+}
@@ -0,0 +1,58 @@
+//! showing rkpk's log output from a build script or the command line
+
+use std::env;
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+enum Logger {
+	/// sends log records to cargo as warnings, the only thing a build script
+	/// can print that actually shows up
+	Cargo,
+	Stderr,
+}
+
+impl Log for Logger {
+	fn enabled(&self, metadata: &Metadata) -> bool {
+		metadata.level() <= log::max_level()
+	}
+	fn log(&self, record: &Record) {
+		if self.enabled(record.metadata()) {
+			for line in record.args().to_string().lines() {
+				match self {
+					Self::Cargo => println!("cargo:warning=[{}] {}", record.level(), line),
+					Self::Stderr => eprintln!("[{}] {}", record.level(), line),
+				}
+			}
+		}
+	}
+	fn flush(&self) {}
+}
+
+fn set_logger(logger: &'static Logger, level: LevelFilter) {
+	if log::set_logger(logger).is_ok() {
+		log::set_max_level(level);
+	}
+}
+
+/// install the cargo logger, does nothing if a logger is already set
+pub fn init(level: LevelFilter) {
+	set_logger(&Logger::Cargo, level);
+}
+
+/// install a logger that prints to stderr, for running outside of a build
+/// script
+pub fn init_stderr(level: LevelFilter) {
+	set_logger(&Logger::Stderr, level);
+}
+
+/// install the cargo logger with the level from `RKPK_LOG`, warnings and
+/// errors only if it isn't set
+pub fn init_from_env() {
+	println!("cargo:rerun-if-env-changed=RKPK_LOG");
+	init(
+		env::var("RKPK_LOG")
+			.ok()
+			.and_then(|v| v.parse().ok())
+			.unwrap_or(LevelFilter::Warn),
+	);
+}
@@ -1,10 +1,35 @@
-use std::collections::HashMap;
+use std::cmp;
+use std::collections::{hash_map, HashMap, HashSet};
+use std::ffi::OsStr;
 use std::fs;
 use std::path::{Path, PathBuf};
 
-use crate::common::{CompositeImage, RectWH, RectXYWH, RkPkResult};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
 
-pub mod rectpack2d;
+use crate::common::{
+	AliasMeta, AnimationMeta, AtlasMeta, CollisionMeta, Companion, CompositeImage, Flip, FontMeta,
+	FrameRef, ImageArea, ImagePos, LayerMeta, LoopMode, NineSlice, PageMeta, PaletteMeta, RectWH,
+	RectXYWH, RkPkError, RkPkResult, SdfMeta, SpriteMeta, WithPath,
+};
+use crate::format::PixelFormat;
+pub use crate::rectpack2d;
+use crate::rectpack2d::max_rects::{MaxRects, MaxRectsHeuristic};
+use crate::rectpack2d::PackingRoot;
+use aseprite::Aseprite;
+use collision::CollisionSidecar;
+use font::{FontSheet, FontSidecar};
+pub use output::{DirOutput, PackOutput};
+use report::{LayerReport, PackReport, PageReport, SpriteReport};
+use sdf::SdfSidecar;
+
+pub mod aseprite;
+pub mod collision;
+pub mod font;
+pub mod logger;
+pub mod output;
+pub mod report;
+pub mod sdf;
 
 #[derive(Debug)]
 pub enum ImageSource {
@@ -15,7 +40,7 @@ pub enum ImageSource {
 impl ImageSource {
 	fn load(&self) -> RkPkResult<CompositeImage> {
 		Ok(match self {
-			ImageSource::Path(v) => image::io::Reader::open(v)?.decode()?.to_rgba8().into(),
+			ImageSource::Path(v) => CompositeImage::open(v)?,
 			ImageSource::Raw(v) => CompositeImage {
 				size: v.size,
 				data: v.data.clone(),
@@ -37,36 +62,301 @@ pub enum ImageLoad {
 }
 
 impl ImageLoad {
-	fn into_rects(self, source: &ImageSource) -> RkPkResult<Vec<RectXYWH>> {
-		match self {
+	/// every frame's rect, `path` is only for the error if one goes past the
+	/// edge of the image
+	fn rects(&self, source: &CompositeImage, path: &Path) -> RkPkResult<Vec<RectXYWH>> {
+		let rects = match self {
 			ImageLoad::Whole => {
-				let RectWH {w, h} = source.load()?.size;
-				Ok(vec![RectXYWH::new(0, 0, w, h)])
-			}
-			ImageLoad::Tiled { init, gap, count } => Ok((0..count.w)
-				.flat_map(|x| {
-					(0..count.h).map(|y| {
-						RectXYWH::new(
-							init.x + x * (init.w + gap.w),
-							init.y + y * (init.h + gap.h),
-							init.w,
-							init.h,
-						)
+				let RectWH { w, h } = source.size;
+				vec![RectXYWH::new(0, 0, w, h)]
+			}
+			&ImageLoad::Tiled { init, gap, count } => {
+				// tiles too far out to even be an `ImagePos` are still past the edge
+				let pos = |start: ImagePos, i: ImagePos, size: ImagePos, gap: ImagePos| {
+					(start as u32 + i as u32 * (size as u32 + gap as u32)).min(ImagePos::MAX as u32)
+						as ImagePos
+				};
+				(0..count.w)
+					.flat_map(|x| {
+						(0..count.h).map(move |y| {
+							RectXYWH::new(
+								pos(init.x, x, init.w, gap.w),
+								pos(init.y, y, init.h, gap.h),
+								init.w,
+								init.h,
+							)
+						})
 					})
-				})
-				.collect()),
-			ImageLoad::Atlas(v) => Ok(v),
+					.collect()
+			}
+			ImageLoad::Atlas(v) => v.clone(),
+		};
+		let size = source.size;
+		let outside = |v: &&RectXYWH| {
+			v.x as u32 + v.w as u32 > size.w as u32 || v.y as u32 + v.h as u32 > size.h as u32
+		};
+		match rects.iter().find(outside) {
+			Some(&rect) => Err(RkPkError::OutOfBounds {
+				path: path.to_path_buf(),
+				rect,
+				size,
+			}),
+			None => Ok(rects),
+		}
+	}
+}
+
+/// options for packing layers
+#[derive(Debug, Clone)]
+pub struct PackOptions {
+	/// maximum width and height of a page, layers that don't fit spill over
+	/// into more pages
+	pub max_size: ImagePos,
+	/// when to stop shrinking the bin
+	pub discard_step: rectpack2d::DiscardStep,
+	/// every algorithm gets tried on each layer, the one that needs the least
+	/// pages (and then the least area) wins
+	pub algorithms: Vec<rectpack2d::Algorithm>,
+	/// trim fully transparent borders off of every frame before packing
+	pub trim: bool,
+	/// only pack one copy of frames with the same pixels, across every layer
+	/// in a group
+	pub dedup: bool,
+	/// also count mirrored frames as duplicates, needs `dedup`
+	pub dedup_flipped: bool,
+	/// metadata from the last build, frames that are still the same size stay
+	/// where they were and only new or resized frames get placed
+	pub previous: Option<AtlasMeta>,
+	/// pixel format of each `(group, layer)`, anything not in here is `Rgba8`
+	pub formats: HashMap<(String, String), PixelFormat>,
+	/// how many half size copies of every page to generate, frames are padded
+	/// out to a multiple of `2^mip_levels` so no pixel in any level mixes two
+	/// frames
+	pub mip_levels: u32,
+	/// how much more page area an incremental layout can take up than a full
+	/// repack before the layer gets repacked anyway, `0.25` is 25% more
+	pub repack_threshold: f32,
+	/// directory to write a copy of every page to with the frames outlined
+	/// and labelled
+	pub debug_images: Option<PathBuf>,
+	/// store colour pages premultiplied, see `AtlasMeta::premultiplied`
+	pub premultiply: bool,
+}
+
+impl Default for PackOptions {
+	fn default() -> Self {
+		Self {
+			max_size: 4096,
+			discard_step: rectpack2d::DiscardStep::Step(1),
+			algorithms: vec![rectpack2d::Algorithm::EmptySpaces],
+			trim: false,
+			dedup: true,
+			dedup_flipped: false,
+			formats: HashMap::new(),
+			mip_levels: 0,
+			previous: None,
+			repack_threshold: 0.25,
+			debug_images: None,
+			premultiply: false,
 		}
 	}
 }
 
+/// an image waiting to be packed
+#[derive(Debug)]
+struct ImageEntry {
+	/// file the image came from
+	path: PathBuf,
+	source: ImageSource,
+	load: ImageLoad,
+	nine_slice: Option<NineSlice>,
+	animations: HashMap<String, AnimationMeta>,
+	font: Option<FontMeta>,
+	/// the frames are already distance fields, don't make new ones out of them
+	distance_field: bool,
+	/// maps packed at the same places as the frames
+	companions: Vec<(Companion, PathBuf)>,
+}
+
+/// companion maps of a loaded source, by `Companion::ALL`
+type Companions = [Option<CompositeImage>; 2];
+
+impl ImageEntry {
+	fn font(font: FontSheet, path: &Path) -> Self {
+		Self {
+			path: path.to_path_buf(),
+			source: ImageSource::Raw(font.sheet),
+			load: ImageLoad::Atlas(font.rects),
+			nine_slice: None,
+			animations: HashMap::new(),
+			font: Some(font.meta),
+			distance_field: font.distance_field,
+			companions: vec![],
+		}
+	}
+}
+
+/// widest a sheet of generated frames gets before they wrap onto another row
+const SHEET_WIDTH: usize = 2048;
+
+/// fully transparent image
+pub(crate) fn transparent(size: RectWH) -> CompositeImage {
+	CompositeImage {
+		size,
+		data: vec![0; size.area() as usize * 4],
+	}
+}
+
+/// frames waiting to be put on a sheet
+#[derive(Default)]
+pub(crate) struct SheetBuilder {
+	frames: Vec<CompositeImage>,
+}
+
+impl SheetBuilder {
+	/// add a frame and return its index, empty frames still get a
+	/// transparent pixel so they keep their place
+	pub(crate) fn push(&mut self, frame: CompositeImage) -> usize {
+		self.frames.push(if frame.size.area() == 0 {
+			transparent(RectWH::new(1, 1))
+		} else {
+			frame
+		});
+		self.frames.len() - 1
+	}
+	/// lay the frames out in rows
+	pub(crate) fn finish(self) -> RkPkResult<(CompositeImage, Vec<RectXYWH>)> {
+		let mut rects = Vec::with_capacity(self.frames.len());
+		let (mut x, mut y, mut row_h, mut w) = (0, 0, 0, 0);
+		for frame in &self.frames {
+			let size = frame.size;
+			if x != 0 && x + size.w as usize > SHEET_WIDTH {
+				x = 0;
+				y += row_h;
+				row_h = 0;
+			}
+			rects.push(RectXYWH::new(x as ImagePos, y as ImagePos, size.w, size.h));
+			x += size.w as usize;
+			w = w.max(x);
+			row_h = row_h.max(size.h as usize);
+		}
+		let mut sheet = transparent(RectWH::new(w as ImagePos, (y + row_h) as ImagePos));
+		for (frame, rect) in self.frames.iter().zip(&rects) {
+			sheet.copy_from(
+				frame,
+				RectWH::new(rect.x, rect.y),
+				RectXYWH::new(0, 0, rect.w, rect.h),
+			)?;
+		}
+		Ok((sheet, rects))
+	}
+}
+
+/// contents of a `.tiled` sidecar
+#[derive(Debug, Deserialize)]
+struct TiledSidecar {
+	/// the first tile
+	init: RectXYWH,
+	/// space between tiles
+	#[serde(default)]
+	gap: RectWH,
+	/// number of tiles across and down
+	count: RectWH,
+	#[serde(default)]
+	animations: HashMap<String, ClipSidecar>,
+}
+
+/// contents of an `.anim` sidecar
+#[derive(Debug, Default, Deserialize)]
+struct AnimSidecar {
+	#[serde(default)]
+	animations: HashMap<String, ClipSidecar>,
+}
+
+/// a clip as it's written in a sidecar
+#[derive(Debug, Deserialize)]
+struct ClipSidecar {
+	/// first frame
+	from: usize,
+	/// last frame, included, plays backwards if it's before `from`
+	to: usize,
+	/// seconds each frame is shown for
+	#[serde(default = "ClipSidecar::default_duration")]
+	duration: f32,
+	/// per-frame overrides for `duration`
+	#[serde(default)]
+	durations: Vec<f32>,
+	#[serde(default)]
+	mode: LoopMode,
+}
+
+impl ClipSidecar {
+	fn default_duration() -> f32 {
+		0.1
+	}
+	fn compile(self) -> AnimationMeta {
+		let frames: Vec<usize> = if self.from <= self.to {
+			(self.from..=self.to).collect()
+		} else {
+			(self.to..=self.from).rev().collect()
+		};
+		let durations = (0..frames.len())
+			.map(|i| self.durations.get(i).copied().unwrap_or(self.duration))
+			.collect();
+		AnimationMeta {
+			frames,
+			durations,
+			mode: self.mode,
+		}
+	}
+}
+
+/// read `[image].[ext].[sidecar]` next to an image if it exists
+fn read_sidecar<T: DeserializeOwned>(
+	layer_path: &Path,
+	image_name: &OsStr,
+	sidecar: &str,
+) -> RkPkResult<Option<T>> {
+	let mut name = image_name.to_os_string();
+	name.push(".");
+	name.push(sidecar);
+	let path = layer_path.join(name);
+	if path.exists() {
+		Ok(Some(read_toml(&path)?))
+	} else {
+		Ok(None)
+	}
+}
+
+fn read_toml<T: DeserializeOwned>(path: &Path) -> RkPkResult<T> {
+	toml::from_str(&fs::read_to_string(path).with_path(path)?).map_err(|source| {
+		RkPkError::BadSidecar {
+			path: path.to_path_buf(),
+			source,
+		}
+	})
+}
+
 /// packing things
 #[derive(Debug)]
 pub struct Packer {
-	images: HashMap<(String, String), HashMap<String, (ImageSource, ImageLoad)>>,
+	images: HashMap<(String, String), HashMap<String, ImageEntry>>,
+	options: PackOptions,
+	report: PackReport,
+	/// palette swap images of each `(group, layer)`
+	palettes: HashMap<(String, String), PathBuf>,
+	/// layers stored as distance fields
+	sdf: HashMap<(String, String), SdfMeta>,
+	/// layers that get collision shapes traced from their frames
+	collision: HashMap<(String, String), CollisionSidecar>,
 }
 
-const VALID_EXTENSIONS: &[&str] = &["png"];
+/// palettes of a layer, the first one is its own colours
+type Palettes = Vec<Vec<[u8; 4]>>;
+
+const VALID_EXTENSIONS: &[&str] = &["png", "aseprite", "ase", "ttf", "otf", "fnt"];
+const ASEPRITE_EXTENSIONS: &[&str] = &["aseprite", "ase"];
+const FONT_EXTENSIONS: &[&str] = &["ttf", "otf"];
 
 fn valid_ext(v: &str) -> bool {
 	let ext: String = v.chars().rev().take_while(|v| *v != '.').collect();
@@ -81,26 +371,42 @@ fn valid_ext(v: &str) -> bool {
 
 impl Packer {
 	pub fn new() -> Self {
+		Self::with_options(PackOptions::default())
+	}
+	pub fn with_options(options: PackOptions) -> Self {
 		Self {
 			images: HashMap::new(),
+			options,
+			report: PackReport::default(),
+			palettes: HashMap::new(),
+			sdf: HashMap::new(),
+			collision: HashMap::new(),
 		}
 	}
+	/// what the last `save_build_info` did
+	pub fn report(&self) -> &PackReport {
+		&self.report
+	}
+	/// add an image to a layer, two files can't have the same name
 	fn set_images_ent(
 		&mut self,
 		group: String,
 		layer: String,
 		image: String,
-		path: PathBuf,
-		load: ImageLoad,
-	) {
-		println!(
-			"cargo:warning=set_images_ent({:?}, {:?}, {:?}, {:?}, {:?})",
-			group, layer, image, path, load
-		);
-		self.images
-			.entry((group, layer))
-			.or_insert_with(HashMap::new)
-			.insert(image, (ImageSource::Path(path), load));
+		entry: ImageEntry,
+	) -> RkPkResult<()> {
+		log::debug!("{}/{}/{}: {:?}", group, layer, image, entry);
+		let name = format!("{}/{}/{}", group, layer, image);
+		match self.images.entry((group, layer)).or_default().entry(image) {
+			hash_map::Entry::Occupied(v) => Err(RkPkError::NameCollision {
+				name,
+				paths: [v.get().path.clone(), entry.path],
+			}),
+			hash_map::Entry::Vacant(v) => {
+				v.insert(entry);
+				Ok(())
+			}
+		}
 	}
 	pub fn add_dir(&mut self, path: impl AsRef<Path>) -> RkPkResult<()> {
 		// structure:
@@ -108,85 +414,268 @@ impl Packer {
 		//  [group]/
 		//   [layer].[ext]
 		//   [layer].[ext].atlas
+		//   [layer].palette.png
+		//   [layer].sdf
+		//   [layer].collision
 		//   [layer]/
 		//    [image].[ext]
 		//    [image].[ext].tiled
 		//    [image].[ext].atlas
-		println!("cargo:warning=graph:{:?}", path.as_ref());
-		for group_ent in fs::read_dir(path.as_ref())? {
+		//    [image].[ext].nine
+		//    [image].[ext].anim
+		//    [image].aseprite
+		//    [image].normal.png
+		//    [image].emissive.png
+		//    [font].ttf
+		//    [font].ttf.font
+		//    [font].fnt (pages are relative to it, keep them in a subfolder)
+		//    [font].png
+		//    [font].png.font (with a grid)
+		log::trace!("graph: {:?}", path.as_ref());
+		// images might come after their companions
+		let mut companions = vec![];
+		for group_ent in fs::read_dir(path.as_ref()).with_path(&path)? {
 			let group_ent = group_ent?;
-			println!("cargo:warning=group:{:?}", group_ent.path());
+			log::trace!("group: {:?}", group_ent.path());
 			if group_ent.file_type()?.is_dir() {
 				let group_name = group_ent.file_name().to_string_lossy().into_owned();
-				for layer_ent in fs::read_dir(group_ent.path())? {
+				for layer_ent in fs::read_dir(group_ent.path()).with_path(group_ent.path())? {
 					let layer_ent = layer_ent?;
-					println!("cargo:warning=layer:{:?}", layer_ent.path());
+					log::trace!("layer: {:?}", layer_ent.path());
 					let layer_type = layer_ent.file_type()?;
 					let layer_name = layer_ent.file_name().to_string_lossy().into_owned();
 					if layer_type.is_dir() {
 						let layer_path = layer_ent.path();
-						for image_ent in fs::read_dir(layer_path.clone())? {
+						// fonts need to know this before they're rasterized
+						let sdf = group_ent.path().join(format!("{}.sdf", layer_name));
+						if sdf.exists() {
+							let sidecar: SdfSidecar = read_toml(&sdf)?;
+							self.sdf
+								.insert((group_name.clone(), layer_name.clone()), sidecar.meta());
+						}
+						let collision = group_ent.path().join(format!("{}.collision", layer_name));
+						if collision.exists() {
+							self.collision.insert(
+								(group_name.clone(), layer_name.clone()),
+								read_toml(&collision)?,
+							);
+						}
+						for image_ent in fs::read_dir(&layer_path).with_path(&layer_path)? {
 							let image_ent = image_ent?;
-							println!("cargo:warning=image:{:?}", image_ent.path());
+							log::trace!("image: {:?}", image_ent.path());
 							if image_ent.file_type()?.is_file() {
 								let image_name_os = image_ent.file_name();
 								let image_name = image_name_os.to_string_lossy().into_owned();
-								if valid_ext(&image_name) {
-									// check for .tiled or .atlas
-									let mut alt_path = layer_path.clone();
-									{
-										let mut image_name_tiled = image_name_os.clone();
-										image_name_tiled.push(".tiled");
-										alt_path.push(image_name_tiled);
+								let companion = Companion::ALL.into_iter().find_map(|v| {
+									let stem = image_name
+										.strip_suffix(".png")?
+										.strip_suffix(v.suffix())?
+										.strip_suffix('.')?;
+									Some((stem.to_string(), v))
+								});
+								if let Some((image, companion)) = companion {
+									companions.push((
+										(group_name.clone(), layer_name.clone(), image),
+										companion,
+										image_ent.path(),
+									));
+								} else if valid_ext(&image_name) {
+									if self.add_font(
+										&group_name,
+										&layer_name,
+										&layer_path,
+										&image_ent.path(),
+									)? {
+										continue;
 									}
-									if alt_path.exists() {
-										// image is tiled
-										todo!("image tiled support");
-									} else {
-										alt_path.pop();
-										{
-											let mut image_name_atlas = image_name_os.clone();
-											image_name_atlas.push(".atlas");
-											alt_path.push(image_name_atlas);
+									let nine_slice =
+										read_sidecar(&layer_path, &image_name_os, "nine")?;
+									let mut animations = read_sidecar::<AnimSidecar>(
+										&layer_path,
+										&image_name_os,
+										"anim",
+									)?
+									.unwrap_or_default()
+									.animations
+									.into_iter()
+									.map(|(name, clip)| (name, clip.compile()))
+									.collect::<HashMap<_, _>>();
+									let image_path = image_ent.path();
+									let aseprite = image_path.extension().is_some_and(|v| {
+										ASEPRITE_EXTENSIONS.iter().any(|e| v == *e)
+									});
+									// check for .aseprite, .tiled or .atlas
+									let (source, load, nine_slice) = if aseprite {
+										let file = fs::read(&image_path)
+											.map_err(RkPkError::from)
+											.and_then(|v| Aseprite::parse(&v))
+											.with_path(&image_path)?;
+										for (name, clip) in file.animations {
+											animations.entry(name).or_insert(clip);
 										}
-										if alt_path.exists() {
-											// image is atlas
-											todo!("image atlas support");
-										} else {
-											// image is whole
-											let image_name = image_ent
-												.path()
-												.file_stem()
-												.unwrap()
-												.to_string_lossy()
-												.into_owned();
-											self.set_images_ent(
-												group_name.clone(),
-												layer_name.clone(),
-												image_name,
-												image_ent.path(),
-												ImageLoad::Whole,
-											);
+										(
+											ImageSource::Raw(file.sheet),
+											ImageLoad::Tiled {
+												init: RectXYWH::new(0, 0, file.size.w, file.size.h),
+												gap: RectWH::default(),
+												count: RectWH::new(
+													file.durations.len() as ImagePos,
+													1,
+												),
+											},
+											nine_slice.or(file.nine_slice),
+										)
+									} else if let Some(tiled) = read_sidecar::<TiledSidecar>(
+										&layer_path,
+										&image_name_os,
+										"tiled",
+									)? {
+										// clips in a separate file win
+										for (name, clip) in tiled.animations {
+											animations
+												.entry(name)
+												.or_insert_with(|| clip.compile());
 										}
-									}
+										(
+											ImageSource::Path(image_path),
+											ImageLoad::Tiled {
+												init: tiled.init,
+												gap: tiled.gap,
+												count: tiled.count,
+											},
+											nine_slice,
+										)
+									} else {
+										let mut image_name_atlas = image_name_os.clone();
+										image_name_atlas.push(".atlas");
+										let atlas_path = layer_path.join(image_name_atlas);
+										if atlas_path.exists() {
+											return Err(RkPkError::Unsupported {
+												path: atlas_path,
+												what: "atlas sidecars",
+											});
+										}
+										(
+											ImageSource::Path(image_path),
+											ImageLoad::Whole,
+											nine_slice,
+										)
+									};
+									let image_name = image_ent
+										.path()
+										.file_stem()
+										.unwrap_or_default()
+										.to_string_lossy()
+										.into_owned();
+									self.set_images_ent(
+										group_name.clone(),
+										layer_name.clone(),
+										image_name,
+										ImageEntry {
+											path: image_ent.path(),
+											source,
+											load,
+											nine_slice,
+											animations,
+											font: None,
+											distance_field: false,
+											companions: vec![],
+										},
+									)?;
 								}
 							}
 						}
 					} else if layer_type.is_file() {
-						todo!("layer atlas support");
+						if let Some(layer) = layer_name.strip_suffix(".palette.png") {
+							self.palettes
+								.insert((group_name.clone(), layer.to_string()), layer_ent.path());
+						} else if layer_name.ends_with(".sdf") || layer_name.ends_with(".collision")
+						{
+							// read along with the layer's folder
+						} else if valid_ext(&layer_name) {
+							return Err(RkPkError::Unsupported {
+								path: layer_ent.path(),
+								what: "layer atlases",
+							});
+						}
 					}
 				}
 			}
 		}
+		for ((group, layer, image), companion, path) in companions {
+			match self
+				.images
+				.get_mut(&(group, layer))
+				.and_then(|v| v.get_mut(&image))
+			{
+				Some(entry) => entry.companions.push((companion, path)),
+				None => log::warn!("{:?} doesn't have an image to go with", path),
+			}
+		}
 		Ok(())
 	}
+	/// add a font if `path` is one, a ttf/otf gets an image per size named
+	/// `[font].[size]`
+	fn add_font(
+		&mut self,
+		group: &str,
+		layer: &str,
+		layer_path: &Path,
+		path: &Path,
+	) -> RkPkResult<bool> {
+		let name_os = path.file_name().unwrap_or_default();
+		let stem = path
+			.file_stem()
+			.unwrap_or_default()
+			.to_string_lossy()
+			.into_owned();
+		let ext = path.extension().and_then(OsStr::to_str).unwrap_or("");
+		let settings = read_sidecar::<FontSidecar>(layer_path, name_os, "font")?;
+		if FONT_EXTENSIONS.contains(&ext) {
+			let sdf = self
+				.sdf
+				.get(&(group.to_string(), layer.to_string()))
+				.copied();
+			for (size, font) in font::rasterize(path, &settings.unwrap_or_default(), sdf)? {
+				self.set_images_ent(
+					group.to_string(),
+					layer.to_string(),
+					format!("{}.{}", stem, size),
+					ImageEntry::font(font, path),
+				)?;
+			}
+		} else if ext == "fnt" {
+			let font = font::bmfont(path)?;
+			self.set_images_ent(
+				group.to_string(),
+				layer.to_string(),
+				stem,
+				ImageEntry::font(font, path),
+			)?;
+		} else if let Some(settings) = settings.filter(|v| v.grid.is_some()) {
+			let image = CompositeImage::open(path)?;
+			let font = font::grid(path, &image, &settings)?;
+			self.set_images_ent(
+				group.to_string(),
+				layer.to_string(),
+				stem,
+				ImageEntry::font(font, path),
+			)?;
+		} else {
+			return Ok(false);
+		}
+		Ok(true)
+	}
+	/// pack everything and write the pages and metadata, the metadata is also
+	/// returned so it can be kept around for `PackOptions::previous`
 	pub fn save_build_info(
 		&mut self,
 		meta_path: impl Into<String>,
 		data_path: impl AsRef<Path>,
-		asset_manager: &mut asset::build::Builder,
-	) -> RkPkResult<()> {
-		println!("cargo:warning={:?}", self);
+		output: &mut dyn PackOutput,
+	) -> RkPkResult<AtlasMeta> {
+		log::trace!("{:?}", self);
+		let meta_path = meta_path.into();
 
 		// // create a global vec<rect> + vec<assoc>
 		// // for each group
@@ -200,19 +689,856 @@ impl Packer {
 		// // generate and save metadata code
 
 		// pack each layer (with deduplication)
-		for ((group, layer), images) in self.images.iter_mut() {
-			let mut rects = vec![];
-			let mut rects_associated = vec![];
-			for (image_name, (image_src, image_load)) in images.iter_mut() {
-				for image_rect in image_load.into_rects(image_src).unwrap() {
-					rects.push(image_rect);
+		let data_path = data_path.as_ref().to_string_lossy();
+		let mut meta = AtlasMeta {
+			mip_levels: self.options.mip_levels,
+			premultiplied: self.options.premultiply,
+			..Default::default()
+		};
+		let mut keys = self.images.keys().collect::<Vec<_>>();
+		keys.sort();
+		let mut reports = vec![];
+		let mut palette_rows = vec![];
+		for group_keys in keys.chunk_by(|a, b| a.0 == b.0) {
+			let first_report = reports.len();
+			let mut sources = vec![];
+			let mut companions = vec![];
+			let mut frames = vec![];
+			// frames only alias frames on pages stored the same way
+			let mut unique: HashMap<PageSettings, HashMap<_, _>> = HashMap::new();
+			for &key in group_keys {
+				let first_frame = frames.len();
+				let first_source = sources.len();
+				self.load_frames(
+					&key.1,
+					&self.images[key],
+					self.sdf.get(key).copied(),
+					&mut sources,
+					&mut companions,
+					&mut frames,
+				)?;
+				// aliases keep the shape of their own pixels, flipped ones differ
+				if let Some(sidecar) = self.collision.get(key) {
+					for frame in &mut frames[first_frame..] {
+						frame.collision = Some(collision::shape(
+							&sources[frame.source],
+							frame.uv,
+							frame.sprite.offset,
+							sidecar,
+						));
+					}
+				}
+				if self.options.dedup {
+					let unique = unique
+						.entry(self.page_settings(key, &companions[first_source..]))
+						.or_default();
+					self.deduplicate(&sources, &companions, &mut frames, first_frame, unique);
+				}
+				reports.push(self.pack_layer(
+					&key.0,
+					&key.1,
+					&sources,
+					&companions,
+					&mut frames[first_frame..],
+					&data_path,
+					&mut meta,
+					output,
+				)?);
+			}
+			// aliases point at frames that are already packed by now
+			for i in 0..frames.len() {
+				if let Some((of, flip)) = frames[i].alias {
+					let packed = frames[of].sprite;
+					let sprite = &mut frames[i].sprite;
+					sprite.page = packed.page;
+					sprite.rect = packed.rect;
+					sprite.flip = flip;
+				}
+			}
+			for (&key, (report, palettes)) in group_keys.iter().zip(&mut reports[first_report..]) {
+				let (group, layer) = key;
+				let mut layer_meta = LayerMeta {
+					group: group.clone(),
+					layer: layer.clone(),
+					..Default::default()
+				};
+				for frame in frames.iter().filter(|v| v.layer == layer) {
+					let sprites = layer_meta
+						.images
+						.entry(frame.image.to_string())
+						.or_default();
+					if let Some((of, flip)) = frame.alias {
+						let of = &frames[of];
+						layer_meta.aliases.push(AliasMeta {
+							image: frame.image.to_string(),
+							frame: sprites.len(),
+							of: FrameRef {
+								layer: of.layer.to_string(),
+								image: of.image.to_string(),
+								frame: of.index,
+							},
+							flip,
+						});
+					}
+					sprites.push(frame.sprite);
+					if let Some(collision) = &frame.collision {
+						layer_meta
+							.collision
+							.entry(frame.image.to_string())
+							.or_default()
+							.push(collision.clone());
+					}
+				}
+				for (image, entry) in &self.images[key] {
+					if entry.animations.is_empty() {
+						continue;
+					}
+					let count = layer_meta.images.get(image).map_or(0, |v| v.len());
+					for (name, clip) in &entry.animations {
+						if clip.frames.iter().any(|&v| v >= count) {
+							return Err(RkPkError::BadAnimation(format!("{}/{}", image, name)));
+						}
+					}
+					layer_meta
+						.animations
+						.insert(image.clone(), entry.animations.clone());
+				}
+				for (image, entry) in &self.images[key] {
+					if let Some(font) = &entry.font {
+						let mut font = font.clone();
+						// glyphs got padded along with every other frame
+						if let Some(sdf) = self.sdf.get(key) {
+							for glyph in font.glyphs.values_mut() {
+								glyph.bearing[0] -= sdf.spread as f32;
+								glyph.bearing[1] -= sdf.spread as f32;
+							}
+						}
+						layer_meta.fonts.insert(image.clone(), font);
+					}
+				}
+				layer_meta.sdf = self.sdf.get(key).copied();
+				if let Some(palettes) = palettes.take() {
+					layer_meta.palette = Some(PaletteMeta {
+						row: palette_rows.len() as u32,
+						rows: palettes.len() as u32,
+					});
+					palette_rows.extend(palettes);
 				}
-				// rects.push(rectpack2d::RectXYWH::new());
+				report.duplicates = layer_meta.aliases.clone();
+				report.finish();
+				log::info!(
+					"{}/{}: {} pages, {:.1}% used, {} duplicates",
+					group,
+					layer,
+					report.pages.len(),
+					report.occupancy,
+					report.duplicates.len()
+				);
+				meta.layers.push(layer_meta);
 			}
 		}
-		// pack the output image
-		// some stupid shit here
-		todo!("save_build_info");
+		if !palette_rows.is_empty() {
+			let mut strip = CompositeImage {
+				size: RectWH::new(256, palette_rows.len() as ImagePos),
+				data: vec![0; 256 * palette_rows.len() * 4],
+			};
+			for (row, colors) in strip.data.chunks_exact_mut(256 * 4).zip(palette_rows) {
+				for (pixel, color) in row.chunks_exact_mut(4).zip(colors) {
+					pixel.copy_from_slice(&color);
+				}
+			}
+			meta.palette_page = Some(meta.pages.len());
+			let page = PageMeta {
+				size: strip.size,
+				format: PixelFormat::Rgba8,
+				..Default::default()
+			};
+			self.save_page(strip, page, &data_path, &mut meta, output)?;
+		}
+		output.write(&meta_path, &meta.to_bytes()?)?;
+		self.report = PackReport {
+			layers: reports.into_iter().map(|v| v.0).collect(),
+		};
+		Ok(meta)
+	}
+	/// load every image in a layer and cut it up into frames
+	fn load_frames<'a>(
+		&self,
+		layer: &'a str,
+		images: &'a HashMap<String, ImageEntry>,
+		sdf: Option<SdfMeta>,
+		sources: &mut Vec<CompositeImage>,
+		companions: &mut Vec<Companions>,
+		frames: &mut Vec<Frame<'a>>,
+	) -> RkPkResult<()> {
+		let mut names = images.keys().collect::<Vec<_>>();
+		names.sort();
+		for image_name in names {
+			let entry = &images[image_name];
+			let mut source = entry.source.load()?;
+			let mut rects = entry.load.rects(&source, &entry.path)?;
+			let mut nine_slice = entry.nine_slice;
+			let mut maps = Companions::default();
+			for (companion, path) in &entry.companions {
+				let map = CompositeImage::open(path)?;
+				if map.size != source.size {
+					return Err(RkPkError::BadCompanion(path.to_string_lossy().into_owned()));
+				}
+				maps[*companion as usize] = Some(map);
+			}
+			if sdf.is_some() && !entry.companions.is_empty() {
+				log::warn!(
+					"{}/{} is a distance field, ignoring its companions",
+					layer,
+					image_name
+				);
+				maps = Companions::default();
+			}
+			if let Some(sdf) = sdf {
+				if !entry.distance_field {
+					(source, rects) = sdf::frames(&source, &rects, sdf.spread)?;
+				}
+				// borders are measured from the padded edges now
+				nine_slice = nine_slice.map(|v| NineSlice {
+					left: v.left + sdf.spread,
+					top: v.top + sdf.spread,
+					right: v.right + sdf.spread,
+					bottom: v.bottom + sdf.spread,
+				});
+			}
+			for (index, image_rect) in rects.into_iter().enumerate() {
+				let uv = if self.options.trim {
+					source.opaque_bounds(image_rect)
+				} else {
+					Some(image_rect)
+				};
+				frames.push(Frame {
+					layer,
+					image: image_name,
+					index,
+					source: sources.len(),
+					uv,
+					alias: None,
+					collision: None,
+					sprite: SpriteMeta {
+						size: image_rect.to_wh(),
+						offset: match uv {
+							Some(uv) => RectWH::new(uv.x - image_rect.x, uv.y - image_rect.y),
+							None => RectWH::default(),
+						},
+						nine_slice,
+						..Default::default()
+					},
+				});
+			}
+			sources.push(source);
+			companions.push(maps);
+		}
+		Ok(())
+	}
+	/// how a layer's pages get stored, before palettes are checked
+	fn page_settings<'a>(
+		&self,
+		key: &'a (String, String),
+		companions: &[Companions],
+	) -> PageSettings<'a> {
+		let format = self.options.formats.get(key).copied().unwrap_or_default();
+		let palette = format == PixelFormat::Palette || self.palettes.contains_key(key);
+		PageSettings {
+			format,
+			sdf: self.sdf.get(key).copied(),
+			// every palette layer has colours of its own
+			palette: palette.then_some(key.1.as_str()),
+			companions: Companion::ALL
+				.map(|companion| companions.iter().any(|v| v[companion as usize].is_some())),
+		}
+	}
+	/// find frames starting at `first_frame` that have the same pixels as
+	/// another frame in `unique`
+	fn deduplicate(
+		&self,
+		sources: &[CompositeImage],
+		companions: &[Companions],
+		frames: &mut [Frame],
+		first_frame: usize,
+		unique: &mut HashMap<(RectWH, Vec<u8>), usize>,
+	) {
+		let flips: &[Flip] = if self.options.dedup_flipped {
+			&[
+				Flip { x: false, y: false },
+				Flip { x: true, y: false },
+				Flip { x: false, y: true },
+				Flip { x: true, y: true },
+			]
+		} else {
+			&[Flip { x: false, y: false }]
+		};
+		for i in first_frame..frames.len() {
+			let uv = match frames[i].uv {
+				Some(uv) => uv,
+				None => continue,
+			};
+			let source = &sources[frames[i].source];
+			let maps = &companions[frames[i].source];
+			// flipping a normal map would point its normals the wrong way
+			let flips = if maps.iter().any(Option::is_some) {
+				&flips[..1]
+			} else {
+				flips
+			};
+			let pixels = |flip| {
+				let mut res = source.flipped(uv, flip).data;
+				for map in maps {
+					res.push(map.is_some() as u8);
+					if let Some(map) = map {
+						res.extend(map.flipped(uv, flip).data);
+					}
+				}
+				res
+			};
+			frames[i].alias = flips.iter().find_map(|&flip| {
+				unique
+					.get(&(uv.to_wh(), pixels(flip)))
+					.map(|&of| (of, flip))
+			});
+			if frames[i].alias.is_none() {
+				unique.insert((uv.to_wh(), pixels(Flip::default())), i);
+			}
+		}
+	}
+	/// pack a layer's frames into pages and save them, the report is missing
+	/// its totals and duplicates until the whole group is done
+	#[allow(clippy::too_many_arguments)]
+	fn pack_layer(
+		&self,
+		group: &str,
+		layer: &str,
+		sources: &[CompositeImage],
+		companions: &[Companions],
+		frames: &mut [Frame],
+		data_path: &str,
+		meta: &mut AtlasMeta,
+		output: &mut dyn PackOutput,
+	) -> RkPkResult<(LayerReport, Option<Palettes>)> {
+		let key = (group.to_string(), layer.to_string());
+		let mut format = self.options.formats.get(&key).copied().unwrap_or_default();
+		let palette_path = self.palettes.get(&key);
+		let mut palettes = None;
+		if format == PixelFormat::Palette || palette_path.is_some() {
+			palettes = self.layer_palettes(group, layer, palette_path, sources, frames)?;
+			format = if palettes.is_some() {
+				PixelFormat::Palette
+			} else {
+				log::warn!(
+					"{}/{} has more than 256 colours, storing it as rgba",
+					group,
+					layer
+				);
+				PixelFormat::Rgba8
+			};
+		}
+		let mut rects = vec![];
+		let mut rects_associated = vec![];
+		for (i, frame) in frames.iter().enumerate() {
+			// fully transparent frames and duplicates don't take up any space
+			if let (Some(uv), None) = (frame.uv, frame.alias) {
+				let cell = self.cell(uv.to_wh(), format);
+				rects.push(RectXYWH::new(0, 0, cell.w, cell.h));
+				rects_associated.push(i);
+			}
+		}
+		let fresh = self.pack_fresh(&rects).ok_or_else(|| {
+			let max_size = self.options.max_size;
+			RkPkError::PackingFailed {
+				layer: format!("{}/{}", group, layer),
+				frames: rects
+					.iter()
+					.zip(&rects_associated)
+					.filter(|(v, _)| v.w > max_size || v.h > max_size)
+					.map(|(v, &i)| {
+						let frame = &frames[i];
+						(format!("{} frame {}", frame.image, frame.index), v.to_wh())
+					})
+					.collect(),
+			}
+		})?;
+		let mut report = LayerReport {
+			group: group.to_string(),
+			layer: layer.to_string(),
+			empty_frames: frames.iter().filter(|v| v.uv.is_none()).count(),
+			..Default::default()
+		};
+		let Packing {
+			rects,
+			pages,
+			sizes,
+		} = match self.pack_incremental(group, layer, format, frames, &rects, &rects_associated) {
+			Some(v)
+				if v.area() as f32
+					<= fresh.area() as f32 * (1.0 + self.options.repack_threshold) =>
+			{
+				report.incremental = true;
+				v
+			}
+			_ => fresh,
+		};
+		let first_page = meta.pages.len();
+		report.pages = sizes
+			.iter()
+			.enumerate()
+			.map(|(i, &size)| PageReport {
+				page: first_page + i,
+				size,
+				..Default::default()
+			})
+			.collect();
+		for ((rect, page), &frame) in rects.iter().zip(pages).zip(rects_associated.iter()) {
+			let frame = &mut frames[frame];
+			let uv = frame.uv.unwrap();
+			frame.sprite.page = first_page + page;
+			frame.sprite.rect = RectXYWH::new(rect.x, rect.y, uv.w, uv.h);
+			report.pages[page].used_area += uv.area();
+			report.largest.push(SpriteReport {
+				image: frame.image.to_string(),
+				frame: frame.index,
+				size: uv.to_wh(),
+			});
+		}
+		// composite the layer's pages, everything around the frames stays
+		// transparent so it doesn't bleed into the smaller levels
+		let mut images = sizes
+			.iter()
+			.map(|&size| transparent(size))
+			.collect::<Vec<_>>();
+		for &frame in &rects_associated {
+			let Frame {
+				source, uv, sprite, ..
+			} = &frames[frame];
+			images[sprite.page - first_page].copy_from(
+				&sources[*source],
+				RectWH::new(sprite.rect.x, sprite.rect.y),
+				uv.unwrap(),
+			)?;
+		}
+		if let Some(dir) = &self.options.debug_images {
+			fs::create_dir_all(dir)?;
+			for (i, image) in images.iter().enumerate() {
+				let cells = rects_associated
+					.iter()
+					.zip(&rects)
+					.filter(|(&frame, _)| frames[frame].sprite.page == first_page + i)
+					.map(|(&frame, &rect)| {
+						let frame = &frames[frame];
+						(rect, format!("{}:{}", frame.image, frame.index))
+					})
+					.collect::<Vec<_>>();
+				let debug = report::debug_image(image, &cells);
+				image::save_buffer(
+					dir.join(format!("{}.{}.{}.png", group, layer, i)),
+					&debug.data,
+					debug.size.w as u32,
+					debug.size.h as u32,
+					image::ColorType::Rgba8,
+				)?;
+			}
+		}
+		// companions get pages of their own with the same placements
+		let mut companion_pages = vec![];
+		for companion in Companion::ALL {
+			let map = |frame: usize| companions[frames[frame].source][companion as usize].as_ref();
+			if rects_associated.iter().all(|&frame| map(frame).is_none()) {
+				continue;
+			}
+			let mut pages = sizes
+				.iter()
+				.map(|&size| transparent(size))
+				.collect::<Vec<_>>();
+			for &frame in &rects_associated {
+				let Frame { uv, sprite, .. } = &frames[frame];
+				let page = &mut pages[sprite.page - first_page];
+				match map(frame) {
+					Some(map) => {
+						page.copy_from(map, RectWH::new(sprite.rect.x, sprite.rect.y), uv.unwrap())?
+					}
+					None => fill(page, sprite.rect, companion.fill()),
+				}
+			}
+			companion_pages.push((companion, pages));
+		}
+		let distance_field = self.sdf.contains_key(&key);
+		for mut image in images {
+			if let Some(palettes) = &palettes {
+				to_indices(&mut image, &palettes[0]);
+			}
+			let page = PageMeta {
+				size: image.size,
+				format,
+				distance_field,
+				..Default::default()
+			};
+			self.save_page(image, page, data_path, meta, output)?;
+		}
+		// indices and single channels can't hold a map
+		let companion_format = match format {
+			PixelFormat::Palette | PixelFormat::R8 => PixelFormat::Rgba8,
+			v => v,
+		};
+		for (companion, pages) in companion_pages {
+			for (i, image) in pages.into_iter().enumerate() {
+				let page = PageMeta {
+					size: image.size,
+					format: companion_format,
+					companion: Some(companion),
+					..Default::default()
+				};
+				let page = Some(self.save_page(image, page, data_path, meta, output)?);
+				let owner = &mut meta.pages[first_page + i];
+				match companion {
+					Companion::Normal => owner.normal = page,
+					Companion::Emissive => owner.emissive = page,
+				}
+			}
+		}
+		Ok((report, palettes))
+	}
+	/// bundle a page and its mip levels, returns its index
+	fn save_page(
+		&self,
+		image: CompositeImage,
+		page_meta: PageMeta,
+		data_path: &str,
+		meta: &mut AtlasMeta,
+		output: &mut dyn PackOutput,
+	) -> RkPkResult<usize> {
+		let PageMeta {
+			format,
+			distance_field,
+			companion,
+			..
+		} = page_meta;
+		let page = meta.pages.len();
+		meta.pages.push(page_meta);
+		let mut level = image;
+		// masks are tinted in the shader and the rest aren't colours
+		let premultiply = self.options.premultiply
+			&& !distance_field
+			&& companion.is_none()
+			&& ![PixelFormat::Palette, PixelFormat::R8].contains(&format);
+		if premultiply {
+			level.premultiply_srgb();
+		}
+		for i in 0..=self.options.mip_levels {
+			if i != 0 {
+				// averaging palette indices doesn't mean anything
+				level = if format == PixelFormat::Palette {
+					level.downsample_nearest()
+				} else if distance_field {
+					level.downsample_average()
+				} else if premultiply {
+					level.downsample_srgb()
+				} else {
+					level.downsample()
+				};
+			}
+			output.write_level(
+				&meta.level_path(data_path, page, i),
+				&level,
+				format,
+				&format.encode(&level)?,
+			)?;
+		}
+		Ok(page)
+	}
+	/// palettes for a layer, read from its palette image or made out of the
+	/// colours in its frames. `None` if there's no palette image and the
+	/// frames have too many colours to fit in one
+	fn layer_palettes(
+		&self,
+		group: &str,
+		layer: &str,
+		path: Option<&PathBuf>,
+		sources: &[CompositeImage],
+		frames: &[Frame],
+	) -> RkPkResult<Option<Palettes>> {
+		let mut colors = HashSet::new();
+		for frame in frames.iter().filter(|v| v.alias.is_none()) {
+			let uv = match frame.uv {
+				Some(v) => v,
+				None => continue,
+			};
+			let source = &sources[frame.source];
+			for y in uv.y..uv.y + uv.h {
+				let row = (y as usize * source.size.w as usize + uv.x as usize) * 4;
+				for v in source.data[row..row + uv.w as usize * 4].chunks_exact(4) {
+					colors.insert(opaque_color([v[0], v[1], v[2], v[3]]));
+				}
+			}
+		}
+		let path = match path {
+			Some(v) => v,
+			None => {
+				colors.insert([0; 4]);
+				if colors.len() > 256 {
+					return Ok(None);
+				}
+				let mut palette = colors.into_iter().collect::<Vec<_>>();
+				palette.sort();
+				return Ok(Some(vec![palette]));
+			}
+		};
+		let image = CompositeImage::open(path)?;
+		let bad = || RkPkError::BadPalette(format!("{}/{}", group, layer));
+		if image.size.w > 256 || image.size.area() == 0 {
+			return Err(bad());
+		}
+		let palettes: Palettes = image
+			.data
+			.chunks_exact(image.size.w as usize * 4)
+			.map(|row| {
+				row.chunks_exact(4)
+					.map(|v| [v[0], v[1], v[2], v[3]])
+					.collect()
+			})
+			.collect();
+		let known = palettes[0]
+			.iter()
+			.map(|&v| opaque_color(v))
+			.collect::<HashSet<_>>();
+		if !colors.is_subset(&known) {
+			return Err(bad());
+		}
+		Ok(Some(palettes))
+	}
+	/// size a frame takes up in a page, rounded up so that it stays aligned
+	/// in every mip level and never shares a compressed block
+	fn cell(&self, size: RectWH, format: PixelFormat) -> RectWH {
+		let align = (1u32 << self.options.mip_levels) * format.block_size() as u32;
+		let round = |v: ImagePos| ((v as u32).div_ceil(align) * align).min(ImagePos::MAX as u32);
+		RectWH::new(round(size.w) as ImagePos, round(size.h) as ImagePos)
+	}
+	/// pack rects from scratch with every algorithm and keep the best result,
+	/// `None` if some of them are too big for a page
+	fn pack_fresh(&self, rects: &[RectXYWH]) -> Option<Packing> {
+		let mut best: Option<((usize, ImageArea), Packing)> = None;
+		for algorithm in &self.options.algorithms {
+			let mut packing = Packing {
+				rects: rects.to_vec(),
+				pages: vec![0; rects.len()],
+				sizes: vec![],
+			};
+			packing.sizes = match rectpack2d::find_best_packing_bins(
+				algorithm.root().as_mut(),
+				&mut packing.rects,
+				&mut packing.pages,
+				self.options.max_size,
+				self.options.discard_step,
+				rectpack2d::DEFAULT_COMPARATORS,
+			) {
+				Some(v) => v,
+				None => continue,
+			};
+			let score = (packing.sizes.len(), packing.area());
+			let better = match &best {
+				Some((best_score, _)) => score < *best_score,
+				None => true,
+			};
+			if better {
+				best = Some((score, packing));
+			}
+		}
+		best.map(|v| v.1)
+	}
+	/// keep frames that didn't change size where they were in the previous
+	/// build and fit everything else into the gaps, `None` if there's nothing
+	/// to reuse
+	#[allow(clippy::too_many_arguments)]
+	fn pack_incremental(
+		&self,
+		group: &str,
+		layer: &str,
+		format: PixelFormat,
+		frames: &[Frame],
+		rects: &[RectXYWH],
+		rects_associated: &[usize],
+	) -> Option<Packing> {
+		let previous = self.options.previous.as_ref()?;
+		if previous.mip_levels != self.options.mip_levels {
+			return None;
+		}
+		let previous_layer = previous.layer(group, layer)?;
+		// aliases share their rect with another frame, so they can't keep it
+		let aliased = previous_layer
+			.aliases
+			.iter()
+			.map(|v| (v.image.as_str(), v.frame))
+			.collect::<HashSet<_>>();
+		// and they can be on another layer's pages
+		let previous_pages = previous_layer
+			.images
+			.iter()
+			.flat_map(|(image, sprites)| {
+				let aliased = &aliased;
+				sprites
+					.iter()
+					.enumerate()
+					.filter(move |(i, _)| !aliased.contains(&(image.as_str(), *i)))
+			})
+			.filter(|(_, v)| v.rect.area() != 0)
+			.map(|(_, v)| v.page);
+		let first_page = previous_pages.clone().min()?;
+		let last_page = previous_pages.max()?;
+		let pages = previous.pages.get(first_page..=last_page)?;
+		if pages.iter().any(|v| {
+			v.format != format || v.companion.is_some() || v.size.max_size() > self.options.max_size
+		}) {
+			return None;
+		}
+		let sizes = pages.iter().map(|v| v.size).collect::<Vec<_>>();
+		let mut roots = sizes
+			.iter()
+			.map(|&size| {
+				let mut root = MaxRects::new(MaxRectsHeuristic::BestShortSideFit);
+				root.reset(size);
+				root
+			})
+			.collect::<Vec<_>>();
+		let mut packing = Packing {
+			rects: rects.to_vec(),
+			pages: vec![0; rects.len()],
+			sizes,
+		};
+		let mut placed = vec![false; rects.len()];
+		for (i, &frame) in rects_associated.iter().enumerate() {
+			let frame = &frames[frame];
+			if aliased.contains(&(frame.image, frame.index)) {
+				continue;
+			}
+			let old = match previous_layer
+				.images
+				.get(frame.image)
+				.and_then(|v| v.get(frame.index))
+			{
+				Some(v) => v,
+				None => continue,
+			};
+			let page = old.page - first_page;
+			let cell = RectXYWH::new(old.rect.x, old.rect.y, rects[i].w, rects[i].h);
+			if old.rect.area() != 0
+				&& Some(old.rect.to_wh()) == frame.uv.map(|v| v.to_wh())
+				&& roots[page].occupy(cell)
+			{
+				packing.rects[i] = cell;
+				packing.pages[i] = page;
+				placed[i] = true;
+			}
+		}
+		// new and resized frames go into the gaps, biggest first
+		let mut order = (0..rects.len()).filter(|&i| !placed[i]).collect::<Vec<_>>();
+		order.sort_by_key(|&i| cmp::Reverse((rects[i].w.max(rects[i].h), rects[i].area())));
+		let mut rest = vec![];
+		for i in order {
+			let found = roots
+				.iter_mut()
+				.enumerate()
+				.find_map(|(page, root)| Some((page, root.insert(rects[i])?)));
+			match found {
+				Some((page, rect)) => {
+					packing.rects[i] = rect;
+					packing.pages[i] = page;
+				}
+				None => rest.push(i),
+			}
+		}
+		// whatever doesn't fit anymore gets new pages
+		if !rest.is_empty() {
+			let extra = self.pack_fresh(&rest.iter().map(|&i| rects[i]).collect::<Vec<_>>())?;
+			for (j, &i) in rest.iter().enumerate() {
+				packing.rects[i] = extra.rects[j];
+				packing.pages[i] = roots.len() + extra.pages[j];
+			}
+			packing.sizes.extend(extra.sizes);
+		}
+		Some(packing)
+	}
+}
+
+/// where a layer's rects ended up
+struct Packing {
+	rects: Vec<RectXYWH>,
+	/// page of each rect, counted from the layer's first page
+	pages: Vec<usize>,
+	sizes: Vec<RectWH>,
+}
+
+impl Packing {
+	/// total area of every page
+	fn area(&self) -> ImageArea {
+		self.sizes.iter().map(|v| v.area()).sum()
+	}
+}
+
+/// everything about a layer that decides what its pages look like
+#[derive(PartialEq, Eq, Hash)]
+struct PageSettings<'a> {
+	format: PixelFormat,
+	sdf: Option<SdfMeta>,
+	/// the layer, if it's stored as indices into its own palette
+	palette: Option<&'a str>,
+	/// which companion pages the layer has
+	companions: [bool; 2],
+}
+
+/// a single frame cut out of a source image
+struct Frame<'a> {
+	layer: &'a str,
+	image: &'a str,
+	/// frame number in the image
+	index: usize,
+	/// index into the group's loaded sources
+	source: usize,
+	/// region of the source that gets packed, `None` if it's fully transparent
+	uv: Option<RectXYWH>,
+	/// the frame in the group this is a duplicate of
+	alias: Option<(usize, Flip)>,
+	sprite: SpriteMeta,
+	/// set if the layer has a `[layer].collision` sidecar
+	collision: Option<CollisionMeta>,
+}
+
+/// set every pixel inside `rect` to `color`
+fn fill(image: &mut CompositeImage, rect: RectXYWH, color: [u8; 4]) {
+	for y in rect.y..rect.y + rect.h {
+		let start = (y as usize * image.size.w as usize + rect.x as usize) * 4;
+		for pixel in image.data[start..start + rect.w as usize * 4].chunks_exact_mut(4) {
+			pixel.copy_from_slice(&color);
+		}
+	}
+}
+
+/// colour with every fully transparent pixel counted as the same one
+fn opaque_color(v: [u8; 4]) -> [u8; 4] {
+	if v[3] == 0 {
+		[0; 4]
+	} else {
+		v
+	}
+}
+
+/// replace every pixel with its index in `palette`, kept in the red channel.
+/// anything that isn't in it (like the empty parts of a page) becomes the
+/// first transparent entry
+fn to_indices(image: &mut CompositeImage, palette: &[[u8; 4]]) {
+	let mut lookup = HashMap::new();
+	for (i, &color) in palette.iter().enumerate().rev() {
+		lookup.insert(opaque_color(color), i as u8);
+	}
+	let transparent = lookup.get(&[0; 4]).copied().unwrap_or(0);
+	for v in image.data.chunks_exact_mut(4) {
+		let index = lookup
+			.get(&opaque_color([v[0], v[1], v[2], v[3]]))
+			.copied()
+			.unwrap_or(transparent);
+		v.copy_from_slice(&[index, 0, 0, 0xFF]);
 	}
 }
 
@@ -222,14 +1548,493 @@ impl Default for Packer {
 	}
 }
 
-fn recurse_dir(dir: &Path) -> RkPkResult<Vec<PathBuf>> {
-	let mut res = vec![];
-	if dir.is_dir() {
-		for entry in fs::read_dir(dir)? {
-			res.append(&mut recurse_dir(&entry?.path())?);
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// every file a pack wrote, by path
+	#[derive(Default)]
+	struct MemoryOutput(HashMap<String, Vec<u8>>);
+
+	impl PackOutput for MemoryOutput {
+		fn write(&mut self, path: &str, data: &[u8]) -> RkPkResult<()> {
+			self.0.insert(path.to_string(), data.to_vec());
+			Ok(())
 		}
-	} else {
-		res.push(dir.to_path_buf());
 	}
-	Ok(res)
+
+	/// a 4x4 image with a different colour in every pixel
+	fn pattern(seed: u8) -> CompositeImage {
+		CompositeImage {
+			size: RectWH::new(4, 4),
+			data: (0..16u8)
+				.flat_map(|i| [i * 16, seed, 255 - i * 8, 0xFF])
+				.collect(),
+		}
+	}
+
+	fn add(packer: &mut Packer, layer: &str, image: &str, source: CompositeImage) {
+		let entry = ImageEntry {
+			path: PathBuf::from(format!("group/{}/{}.png", layer, image)),
+			source: ImageSource::Raw(source),
+			load: ImageLoad::Whole,
+			nine_slice: None,
+			animations: HashMap::new(),
+			font: None,
+			distance_field: false,
+			companions: vec![],
+		};
+		packer
+			.set_images_ent("group".into(), layer.into(), image.into(), entry)
+			.unwrap();
+	}
+
+	fn pack(packer: &mut Packer) -> AtlasMeta {
+		packer
+			.save_build_info("meta", "data", &mut MemoryOutput::default())
+			.unwrap()
+	}
+
+	fn layer<'a>(meta: &'a AtlasMeta, name: &str) -> &'a LayerMeta {
+		meta.layers.iter().find(|v| v.layer == name).unwrap()
+	}
+
+	#[test]
+	fn dedup_only_across_matching_formats() {
+		let formats = [
+			("a", PixelFormat::Rgba8),
+			("b", PixelFormat::Rgba8),
+			("c", PixelFormat::R8),
+			("d", PixelFormat::Bc1),
+		];
+		let mut packer = Packer::with_options(PackOptions {
+			formats: formats
+				.iter()
+				.map(|&(layer, format)| (("group".to_string(), layer.to_string()), format))
+				.collect(),
+			..Default::default()
+		});
+		for (layer, _) in formats {
+			add(&mut packer, layer, "same", pattern(7));
+		}
+		let meta = pack(&mut packer);
+		// the only layer sharing a format with another is the one that aliases
+		assert_eq!(layer(&meta, "b").aliases.len(), 1);
+		assert_eq!(layer(&meta, "b").aliases[0].of.layer, "a");
+		for (name, format) in formats {
+			let layer = layer(&meta, name);
+			let sprite = layer.images["same"][0];
+			assert_eq!(meta.pages[sprite.page].format, format, "{}", name);
+			if name != "b" {
+				assert!(layer.aliases.is_empty(), "{}", name);
+			}
+		}
+		let pages = formats.map(|(name, _)| layer(&meta, name).images["same"][0].page);
+		assert_eq!(pages[0], pages[1]);
+		assert!(pages[0] != pages[2] && pages[0] != pages[3] && pages[2] != pages[3]);
+	}
+
+	#[test]
+	fn palette_layers_keep_their_own_frames() {
+		let mut packer = Packer::with_options(PackOptions {
+			formats: ["a", "b"]
+				.iter()
+				.map(|&layer| {
+					(
+						("group".to_string(), layer.to_string()),
+						PixelFormat::Palette,
+					)
+				})
+				.collect(),
+			..Default::default()
+		});
+		for name in ["a", "b", "c"] {
+			add(&mut packer, name, "same", pattern(7));
+		}
+		let meta = pack(&mut packer);
+		// every palette layer indexes into colours of its own
+		for name in ["a", "b", "c"] {
+			assert!(layer(&meta, name).aliases.is_empty(), "{}", name);
+		}
+	}
+
+	#[test]
+	fn palette_pages_index_the_original_colours() {
+		let mut packer = Packer::with_options(PackOptions {
+			formats: ["small", "large"]
+				.iter()
+				.map(|&layer| {
+					(
+						("group".to_string(), layer.to_string()),
+						PixelFormat::Palette,
+					)
+				})
+				.collect(),
+			..Default::default()
+		});
+		add(&mut packer, "small", "pattern", pattern(3));
+		// 17x17 colours, too many for one palette
+		let colours = || CompositeImage {
+			size: RectWH::new(17, 17),
+			data: (0..17 * 17u32)
+				.flat_map(|i| [(i % 17 * 15) as u8, (i / 17 * 15) as u8, 0x80, 0xFF])
+				.collect(),
+		};
+		add(&mut packer, "large", "colours", colours());
+		let mut output = MemoryOutput::default();
+		let meta = packer.save_build_info("meta", "data", &mut output).unwrap();
+		let load = |page: usize| {
+			let info = &meta.pages[page];
+			let data = &output.0[&meta.level_path("data", page, 0)];
+			info.format.decode(info.size, data).unwrap()
+		};
+		let strip = load(meta.palette_page.unwrap());
+		let small = layer(&meta, "small");
+		let sprite = small.images["pattern"][0];
+		assert_eq!(meta.pages[sprite.page].format, PixelFormat::Palette);
+		let page = load(sprite.page);
+		let row = small.palette.unwrap().row as usize;
+		let source = pattern(3);
+		for y in 0..4 {
+			for x in 0..4 {
+				let at = ((sprite.rect.y + y) * page.size.w + sprite.rect.x + x) as usize * 4;
+				let color = (row * 256 + page.data[at] as usize) * 4;
+				let original = (y * 4 + x) as usize * 4;
+				assert_eq!(
+					strip.data[color..color + 4],
+					source.data[original..original + 4],
+					"{} {}",
+					x,
+					y
+				);
+			}
+		}
+		// the large layer falls back to rgba and keeps its colours as they are
+		let large_layer = layer(&meta, "large");
+		assert!(large_layer.palette.is_none());
+		let sprite = large_layer.images["colours"][0];
+		assert_eq!(meta.pages[sprite.page].format, PixelFormat::Rgba8);
+		let page = load(sprite.page);
+		let large = colours();
+		for y in 0..17 {
+			let at = ((sprite.rect.y + y) * page.size.w + sprite.rect.x) as usize * 4;
+			let original = (y * 17) as usize * 4;
+			assert_eq!(
+				page.data[at..at + 17 * 4],
+				large.data[original..original + 17 * 4]
+			);
+		}
+	}
+
+	#[test]
+	fn unknown_colours_index_transparent() {
+		let palette = [[9, 9, 9, 0xFF], [0; 4], [1, 2, 3, 0xFF]];
+		let mut image = CompositeImage {
+			size: RectWH::new(4, 1),
+			data: [
+				[1, 2, 3, 0xFF],
+				[9, 9, 9, 0xFF],
+				[5, 5, 5, 0],
+				[7, 7, 7, 0xFF],
+			]
+			.concat(),
+		};
+		to_indices(&mut image, &palette);
+		// fully transparent pixels and colours missing from the palette both
+		// become the transparent entry
+		assert_eq!(
+			image.data,
+			[
+				[2, 0, 0, 0xFF],
+				[0, 0, 0, 0xFF],
+				[1, 0, 0, 0xFF],
+				[1, 0, 0, 0xFF]
+			]
+			.concat()
+		);
+	}
+
+	#[test]
+	fn distance_fields_only_alias_the_same_field() {
+		let mut packer = Packer::new();
+		let fields = [
+			("a", Some(false)),
+			("b", Some(false)),
+			("c", Some(true)),
+			("d", None),
+		];
+		for (name, multi) in fields {
+			if let Some(multi) = multi {
+				packer.sdf.insert(
+					("group".to_string(), name.to_string()),
+					SdfMeta { spread: 2, multi },
+				);
+			}
+			add(&mut packer, name, "same", pattern(7));
+		}
+		let meta = pack(&mut packer);
+		assert_eq!(layer(&meta, "b").aliases.len(), 1);
+		assert_eq!(layer(&meta, "b").aliases[0].of.layer, "a");
+		for name in ["a", "c", "d"] {
+			assert!(layer(&meta, name).aliases.is_empty(), "{}", name);
+		}
+	}
+
+	#[test]
+	fn companion_layers_only_alias_each_other() {
+		let normal =
+			std::env::temp_dir().join(format!("rkpk-dedup-normal-{}.png", std::process::id()));
+		let map = pattern(1);
+		image::save_buffer(&normal, &map.data, 4, 4, image::ColorType::Rgba8).unwrap();
+		let mut packer = Packer::new();
+		add(&mut packer, "a", "same", pattern(7));
+		add(&mut packer, "b", "same", pattern(7));
+		// "lit" gives b normal pages, which a's frames don't have
+		let entry = ImageEntry {
+			path: PathBuf::from("group/b/lit.png"),
+			source: ImageSource::Raw(pattern(9)),
+			load: ImageLoad::Whole,
+			nine_slice: None,
+			animations: HashMap::new(),
+			font: None,
+			distance_field: false,
+			companions: vec![(Companion::Normal, normal.clone())],
+		};
+		packer
+			.set_images_ent("group".into(), "b".into(), "lit".into(), entry)
+			.unwrap();
+		let meta = pack(&mut packer);
+		std::fs::remove_file(normal).unwrap();
+		assert!(layer(&meta, "a").aliases.is_empty());
+		assert!(layer(&meta, "b").aliases.is_empty());
+		let page = layer(&meta, "b").images["same"][0].page;
+		assert!(meta.pages[page].normal.is_some());
+	}
+
+	#[test]
+	fn companion_pages_line_up_with_colour_pages() {
+		let dir = std::env::temp_dir().join(format!("rkpk-companions-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let save = |name: &str, image: &CompositeImage| {
+			let path = dir.join(name);
+			image::save_buffer(&path, &image.data, 4, 4, image::ColorType::Rgba8).unwrap();
+			path
+		};
+		let maps = [
+			vec![
+				(Companion::Normal, save("both.normal.png", &pattern(2))),
+				(Companion::Emissive, save("both.emissive.png", &pattern(3))),
+			],
+			vec![(Companion::Normal, save("normal.normal.png", &pattern(5)))],
+			vec![],
+		];
+		let mut packer = Packer::new();
+		for ((name, companions), seed) in ["both", "normal", "plain"]
+			.into_iter()
+			.zip(maps)
+			.zip([1, 4, 6])
+		{
+			let entry = ImageEntry {
+				path: PathBuf::from(format!("group/lit/{}.png", name)),
+				source: ImageSource::Raw(pattern(seed)),
+				load: ImageLoad::Whole,
+				nine_slice: None,
+				animations: HashMap::new(),
+				font: None,
+				distance_field: false,
+				companions,
+			};
+			packer
+				.set_images_ent("group".into(), "lit".into(), name.into(), entry)
+				.unwrap();
+		}
+		let mut output = MemoryOutput::default();
+		let meta = packer.save_build_info("meta", "data", &mut output).unwrap();
+		std::fs::remove_dir_all(dir).unwrap();
+		let load = |page: usize| {
+			image::load_from_memory(&output.0[&meta.level_path("data", page, 0)])
+				.unwrap()
+				.to_rgba8()
+		};
+		let region = |page: &image::RgbaImage, rect: RectXYWH| {
+			(rect.y..rect.y + rect.h)
+				.flat_map(|y| (rect.x..rect.x + rect.w).map(move |x| (x, y)))
+				.flat_map(|(x, y)| page.get_pixel(x as u32, y as u32).0)
+				.collect::<Vec<_>>()
+		};
+		let lit = layer(&meta, "lit");
+		for (name, colour, normal, emissive) in [
+			("both", pattern(1).data, pattern(2).data, pattern(3).data),
+			("normal", pattern(4).data, pattern(5).data, vec![0; 16 * 4]),
+			(
+				"plain",
+				pattern(6).data,
+				Companion::Normal.fill().repeat(16),
+				vec![0; 16 * 4],
+			),
+		] {
+			let sprite = lit.images[name][0];
+			let page = &meta.pages[sprite.page];
+			assert_eq!(region(&load(sprite.page), sprite.rect), colour, "{}", name);
+			// the same rect on the parallel pages has the frame's own maps
+			let normal_page = page.normal.unwrap();
+			let emissive_page = page.emissive.unwrap();
+			assert_eq!(meta.pages[normal_page].size, page.size);
+			assert_eq!(meta.pages[emissive_page].size, page.size);
+			assert_eq!(region(&load(normal_page), sprite.rect), normal, "{}", name);
+			assert_eq!(
+				region(&load(emissive_page), sprite.rect),
+				emissive,
+				"{}",
+				name
+			);
+		}
+	}
+
+	#[test]
+	fn incremental_skips_pages_of_aliased_layers() {
+		let mut packer = Packer::with_options(PackOptions {
+			repack_threshold: f32::INFINITY,
+			..Default::default()
+		});
+		add(&mut packer, "a", "first", pattern(1));
+		add(&mut packer, "a", "second", pattern(2));
+		add(&mut packer, "b", "copy", pattern(1));
+		add(&mut packer, "b", "own", pattern(3));
+		let before = pack(&mut packer);
+		let copy = layer(&before, "b").images["copy"][0];
+		let own = layer(&before, "b").images["own"][0];
+		assert_eq!(layer(&before, "b").aliases.len(), 1);
+		assert_ne!(copy.page, own.page);
+
+		packer.options.previous = Some(before.clone());
+		let after = pack(&mut packer);
+		let report = &packer.report().layers;
+		assert!(report.iter().all(|v| v.incremental));
+		// b only keeps its own page, a's page isn't pulled into it
+		assert_eq!(
+			report.iter().find(|v| v.layer == "b").unwrap().pages.len(),
+			1
+		);
+		assert_eq!(after.pages.len(), before.pages.len());
+		for name in ["a", "b"] {
+			assert_eq!(layer(&after, name).images, layer(&before, name).images);
+		}
+	}
+
+	#[test]
+	fn pages_are_transparent_around_frames() {
+		let mut packer = Packer::with_options(PackOptions {
+			mip_levels: 2,
+			..Default::default()
+		});
+		add(&mut packer, "a", "square", pattern(1));
+		let wide = CompositeImage {
+			size: RectWH::new(9, 3),
+			data: vec![0x40; 9 * 3 * 4],
+		};
+		add(&mut packer, "a", "wide", wide);
+		let mut output = MemoryOutput::default();
+		let meta = packer.save_build_info("meta", "data", &mut output).unwrap();
+		let page = image::load_from_memory(&output.0[&meta.level_path("data", 0, 0)])
+			.unwrap()
+			.to_rgba8();
+		let sprites = layer(&meta, "a")
+			.images
+			.values()
+			.flatten()
+			.collect::<Vec<_>>();
+		let mut outside = 0;
+		for (x, y, pixel) in page.enumerate_pixels() {
+			let inside = sprites.iter().any(|v| {
+				let r = v.rect;
+				(r.x as u32..(r.x + r.w) as u32).contains(&x)
+					&& (r.y as u32..(r.y + r.h) as u32).contains(&y)
+			});
+			if !inside {
+				assert_eq!(pixel.0, [0; 4], "{} {}", x, y);
+				outside += 1;
+			}
+		}
+		assert!(outside > 0);
+	}
+
+	#[test]
+	fn report_matches_the_pack() {
+		let dir = std::env::temp_dir().join(format!("rkpk-debug-{}", std::process::id()));
+		let mut packer = Packer::with_options(PackOptions {
+			trim: true,
+			repack_threshold: f32::INFINITY,
+			debug_images: Some(dir.clone()),
+			..Default::default()
+		});
+		add(&mut packer, "a", "first", pattern(1));
+		add(&mut packer, "a", "second", pattern(2));
+		add(&mut packer, "a", "copy", pattern(1));
+		add(&mut packer, "a", "empty", transparent(RectWH::new(3, 3)));
+		add(&mut packer, "b", "own", pattern(3));
+		let check = |meta: &AtlasMeta, report: &PackReport, incremental: bool| {
+			assert_eq!(report.layers.len(), 2);
+			for report in &report.layers {
+				let layer = layer(meta, &report.layer);
+				assert_eq!(report.incremental, incremental);
+				let mut pages = layer
+					.images
+					.values()
+					.flatten()
+					.filter(|v| v.rect.area() != 0)
+					.map(|v| v.page)
+					.collect::<Vec<_>>();
+				pages.sort();
+				pages.dedup();
+				assert_eq!(
+					report.pages.iter().map(|v| v.page).collect::<Vec<_>>(),
+					pages
+				);
+				for page in &report.pages {
+					assert_eq!(page.size, meta.pages[page.page].size);
+				}
+				// aliases point at another frame's rect
+				let used = layer
+					.images
+					.iter()
+					.flat_map(|(image, v)| v.iter().enumerate().map(move |v| (image, v)))
+					.filter(|&(image, (frame, _))| {
+						!layer
+							.aliases
+							.iter()
+							.any(|v| &v.image == image && v.frame == frame)
+					})
+					.map(|(_, (_, v))| v.rect.area());
+				assert_eq!(report.used_area, used.sum::<ImageArea>());
+				assert_eq!(report.bin_area, report.used_area + report.wasted_area);
+				assert_eq!(report.duplicates, layer.aliases);
+			}
+		};
+		let before = pack(&mut packer);
+		check(&before, packer.report(), false);
+		let a = &packer.report().layers[0];
+		assert_eq!((a.layer.as_str(), a.empty_frames), ("a", 1));
+		assert_eq!(a.duplicates.len(), 1);
+		assert_eq!(a.largest.len(), 2);
+		// a debug image of every page
+		for report in &packer.report().layers {
+			for i in 0..report.pages.len() {
+				let path = dir.join(format!("group.{}.{}.png", report.layer, i));
+				let image = CompositeImage::open(&path).unwrap();
+				assert_eq!(image.size, report.pages[i].size);
+			}
+		}
+		std::fs::remove_dir_all(&dir).unwrap();
+
+		packer.options.debug_images = None;
+		packer.options.previous = Some(before);
+		let after = pack(&mut packer);
+		check(&after, packer.report(), true);
+		assert!(packer
+			.report()
+			.to_json()
+			.unwrap()
+			.contains("\"incremental\": true"));
+	}
 }
@@ -0,0 +1,71 @@
+//! where packed pages and metadata get written to
+
+use std::fs;
+use std::path::PathBuf;
+
+use crate::common::{CompositeImage, RkPkResult};
+use crate::format::PixelFormat;
+
+/// somewhere to put the files a pack makes, paths are `/` separated and
+/// relative to wherever the output is
+pub trait PackOutput {
+	fn write(&mut self, path: &str, data: &[u8]) -> RkPkResult<()>;
+	/// a mip level of a page, `data` is `image` encoded as `format`
+	fn write_level(
+		&mut self,
+		path: &str,
+		_image: &CompositeImage,
+		_format: PixelFormat,
+		data: &[u8],
+	) -> RkPkResult<()> {
+		self.write(path, data)
+	}
+}
+
+impl PackOutput for asset::build::Builder {
+	fn write(&mut self, path: &str, data: &[u8]) -> RkPkResult<()> {
+		Ok(self.bundle_data(path, data)?)
+	}
+}
+
+/// plain files in a directory, for looking at or loading without the asset
+/// bundle
+#[derive(Debug, Clone)]
+pub struct DirOutput {
+	pub dir: PathBuf,
+	/// also write a png of every level that isn't already one
+	pub previews: bool,
+}
+
+impl DirOutput {
+	pub fn new(dir: impl Into<PathBuf>) -> Self {
+		Self {
+			dir: dir.into(),
+			previews: false,
+		}
+	}
+}
+
+impl PackOutput for DirOutput {
+	fn write(&mut self, path: &str, data: &[u8]) -> RkPkResult<()> {
+		let path = self.dir.join(path);
+		if let Some(parent) = path.parent() {
+			fs::create_dir_all(parent)?;
+		}
+		Ok(fs::write(path, data)?)
+	}
+	fn write_level(
+		&mut self,
+		path: &str,
+		image: &CompositeImage,
+		format: PixelFormat,
+		data: &[u8],
+	) -> RkPkResult<()> {
+		self.write(path, data)?;
+		if self.previews && format != PixelFormat::Rgba8 {
+			let preview = format!("{}.png", path);
+			self.write(&preview, &PixelFormat::Rgba8.encode(image)?)?;
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,190 @@
+//! what packing did, for figuring out why an atlas came out badly
+
+use serde::Serialize;
+
+use crate::common::{AliasMeta, CompositeImage, ImageArea, RectWH, RectXYWH, RkPkResult};
+
+/// how many of the biggest frames each layer lists
+const LARGEST_COUNT: usize = 8;
+
+/// report for every layer packed by `Packer::save_build_info`
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct PackReport {
+	pub layers: Vec<LayerReport>,
+}
+
+impl PackReport {
+	pub fn to_json(&self) -> RkPkResult<String> {
+		Ok(serde_json::to_string_pretty(self)?)
+	}
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct LayerReport {
+	pub group: String,
+	pub layer: String,
+	pub pages: Vec<PageReport>,
+	/// area of every page
+	pub bin_area: ImageArea,
+	/// area of the packed frames, without padding
+	pub used_area: ImageArea,
+	/// `used_area` out of `bin_area` as a percentage
+	pub occupancy: f32,
+	/// area that's padding or empty
+	pub wasted_area: ImageArea,
+	/// whether the previous placements were kept
+	pub incremental: bool,
+	/// frames that didn't take up any space because they were fully
+	/// transparent
+	pub empty_frames: usize,
+	/// biggest frames first
+	pub largest: Vec<SpriteReport>,
+	pub duplicates: Vec<AliasMeta>,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct PageReport {
+	/// index into `AtlasMeta::pages`
+	pub page: usize,
+	pub size: RectWH,
+	pub used_area: ImageArea,
+	pub occupancy: f32,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SpriteReport {
+	pub image: String,
+	pub frame: usize,
+	/// trimmed size
+	pub size: RectWH,
+}
+
+impl LayerReport {
+	/// fill in the totals from `pages` and keep only the biggest frames
+	pub(crate) fn finish(&mut self) {
+		self.bin_area = self.pages.iter().map(|v| v.size.area()).sum();
+		self.used_area = self.pages.iter().map(|v| v.used_area).sum();
+		self.wasted_area = self.bin_area - self.used_area;
+		self.occupancy = percent(self.used_area, self.bin_area);
+		for page in &mut self.pages {
+			page.occupancy = percent(page.used_area, page.size.area());
+		}
+		self.largest
+			.sort_by_key(|v| std::cmp::Reverse(v.size.area()));
+		self.largest.truncate(LARGEST_COUNT);
+	}
+}
+
+fn percent(part: ImageArea, whole: ImageArea) -> f32 {
+	if whole == 0 {
+		0.0
+	} else {
+		part as f32 * 100.0 / whole as f32
+	}
+}
+
+/// copy of a page with every cell outlined and labelled
+pub(crate) fn debug_image(page: &CompositeImage, cells: &[(RectXYWH, String)]) -> CompositeImage {
+	let mut res = CompositeImage {
+		size: page.size,
+		data: page.data.clone(),
+	};
+	for (cell, label) in cells {
+		outline(&mut res, *cell, [0x00, 0xFF, 0x00, 0xFF]);
+		let mut x = cell.x as usize + 2;
+		for c in label.chars() {
+			if x + 3 > (cell.x + cell.w) as usize || cell.h < 9 {
+				break;
+			}
+			draw_glyph(&mut res, x, cell.y as usize + 2, c);
+			x += 4;
+		}
+	}
+	res
+}
+
+fn set_pixel(image: &mut CompositeImage, x: usize, y: usize, color: [u8; 4]) {
+	if x < image.size.w as usize && y < image.size.h as usize {
+		let i = (y * image.size.w as usize + x) * 4;
+		image.data[i..i + 4].copy_from_slice(&color);
+	}
+}
+
+fn outline(image: &mut CompositeImage, rect: RectXYWH, color: [u8; 4]) {
+	if rect.w == 0 || rect.h == 0 {
+		return;
+	}
+	let (x0, y0) = (rect.x as usize, rect.y as usize);
+	let (x1, y1) = (x0 + rect.w as usize - 1, y0 + rect.h as usize - 1);
+	for x in x0..=x1 {
+		set_pixel(image, x, y0, color);
+		set_pixel(image, x, y1, color);
+	}
+	for y in y0..=y1 {
+		set_pixel(image, x0, y, color);
+		set_pixel(image, x1, y, color);
+	}
+}
+
+/// 3x5 glyph with a dark outline so it reads on anything
+fn draw_glyph(image: &mut CompositeImage, x: usize, y: usize, c: char) {
+	let rows = match glyph(c.to_ascii_uppercase()) {
+		Some(v) => v,
+		None => return,
+	};
+	for (dy, row) in rows.iter().enumerate() {
+		for dx in 0..3 {
+			if row & (0b100 >> dx) != 0 {
+				set_pixel(image, x + dx + 1, y + dy + 1, [0x00, 0x00, 0x00, 0xFF]);
+				set_pixel(image, x + dx, y + dy, [0xFF, 0xFF, 0xFF, 0xFF]);
+			}
+		}
+	}
+}
+
+fn glyph(c: char) -> Option<[u8; 5]> {
+	Some(match c {
+		'0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+		'1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+		'2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+		'3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+		'4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+		'5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+		'6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+		'7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+		'8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+		'9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+		'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+		'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+		'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+		'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+		'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+		'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+		'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+		'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+		'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+		'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+		'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+		'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+		'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+		'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+		'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+		'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+		'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+		'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+		'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+		'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+		'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+		'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+		'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+		'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+		'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+		'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+		'_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+		'-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+		'.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+		':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+		'#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+		_ => return None,
+	})
+}
@@ -0,0 +1,621 @@
+//! distance fields, so frames can be drawn at any scale with sharp edges
+//!
+//! frames of bitmaps get a field made from their alpha, glyphs of ttf/otf
+//! fonts get one made from their outlines which can also be multi-channel
+
+use serde::Deserialize;
+
+use super::{transparent, SheetBuilder};
+use crate::common::{CompositeImage, ImagePos, RectWH, RectXYWH, RkPkResult, SdfMeta};
+
+/// distance used for pixels that can't reach the thing they're looking for
+const FAR: f32 = 1e20;
+
+/// bits of the channels an edge belongs to
+const RED: u8 = 1;
+const GREEN: u8 = 2;
+const BLUE: u8 = 4;
+const WHITE: u8 = RED | GREEN | BLUE;
+const CYAN: u8 = GREEN | BLUE;
+const MAGENTA: u8 = RED | BLUE;
+const YELLOW: u8 = RED | GREEN;
+
+/// contents of a `[layer].sdf` sidecar
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct SdfSidecar {
+	pub spread: ImagePos,
+	pub multi: bool,
+}
+
+impl Default for SdfSidecar {
+	fn default() -> Self {
+		Self {
+			spread: 4,
+			multi: false,
+		}
+	}
+}
+
+impl SdfSidecar {
+	pub fn meta(&self) -> SdfMeta {
+		SdfMeta {
+			spread: self.spread,
+			multi: self.multi,
+		}
+	}
+}
+
+/// turn a distance in pixels, positive inside, into a channel value
+fn encode(distance: f32, spread: ImagePos) -> u8 {
+	((0.5 + distance / (2.0 * spread.max(1) as f32)).clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// replace every frame of `source` with a field of its alpha, returns the new
+/// sheet and where the frames are on it
+pub fn frames(
+	source: &CompositeImage,
+	rects: &[RectXYWH],
+	spread: ImagePos,
+) -> RkPkResult<(CompositeImage, Vec<RectXYWH>)> {
+	let mut sheet = SheetBuilder::default();
+	for &rect in rects {
+		sheet.push(from_alpha(source, rect, spread));
+	}
+	sheet.finish()
+}
+
+/// field of the alpha of `rect`, padded by `spread` on every side. the
+/// distance is in every channel so it can be drawn like a multi-channel one
+pub fn from_alpha(image: &CompositeImage, rect: RectXYWH, spread: ImagePos) -> CompositeImage {
+	let pad = spread as usize;
+	let (w, h) = (rect.w as usize + pad * 2, rect.h as usize + pad * 2);
+	let mut inside = vec![false; w * h];
+	for y in 0..rect.h as usize {
+		for x in 0..rect.w as usize {
+			let i = ((rect.y as usize + y) * image.size.w as usize + rect.x as usize + x) * 4;
+			inside[(y + pad) * w + x + pad] = image.data[i + 3] >= 0x80;
+		}
+	}
+	let to_inside = squared_distances(&inside, w, h, true);
+	let to_outside = squared_distances(&inside, w, h, false);
+	let mut res = transparent(RectWH::new(w as ImagePos, h as ImagePos));
+	for (i, pixel) in res.data.chunks_exact_mut(4).enumerate() {
+		// distances are between pixel centres, the edge is half a pixel off
+		let distance = if inside[i] {
+			to_outside[i].sqrt() - 0.5
+		} else {
+			0.5 - to_inside[i].sqrt()
+		};
+		pixel.fill(encode(distance, spread));
+	}
+	res
+}
+
+/// squared distance from every pixel to the closest one where `mask` is
+/// `target`, by felzenszwalb and huttenlocher's transform
+fn squared_distances(mask: &[bool], w: usize, h: usize, target: bool) -> Vec<f32> {
+	let mut grid = mask
+		.iter()
+		.map(|&v| if v == target { 0.0 } else { FAR })
+		.collect::<Vec<_>>();
+	let mut line = vec![0.0; w.max(h)];
+	let mut out = vec![0.0; w.max(h)];
+	for x in 0..w {
+		for y in 0..h {
+			line[y] = grid[y * w + x];
+		}
+		transform_line(&line[..h], &mut out[..h]);
+		for y in 0..h {
+			grid[y * w + x] = out[y];
+		}
+	}
+	for y in 0..h {
+		line[..w].copy_from_slice(&grid[y * w..(y + 1) * w]);
+		transform_line(&line[..w], &mut out[..w]);
+		grid[y * w..(y + 1) * w].copy_from_slice(&out[..w]);
+	}
+	grid
+}
+
+/// one dimensional distance transform, the lower envelope of parabolas
+/// rooted at every sample
+fn transform_line(f: &[f32], d: &mut [f32]) {
+	let n = f.len();
+	if n == 0 {
+		return;
+	}
+	let mut v = vec![0usize; n];
+	let mut z = vec![0.0f32; n + 1];
+	let mut k = 0;
+	z[0] = -f32::INFINITY;
+	z[1] = f32::INFINITY;
+	let intersect = |q: usize, p: usize| {
+		let (qf, pf) = (q as f32, p as f32);
+		((f[q] + qf * qf) - (f[p] + pf * pf)) / (2.0 * qf - 2.0 * pf)
+	};
+	for q in 1..n {
+		let mut s = intersect(q, v[k]);
+		while s <= z[k] {
+			k -= 1;
+			s = intersect(q, v[k]);
+		}
+		k += 1;
+		v[k] = q;
+		z[k] = s;
+		z[k + 1] = f32::INFINITY;
+	}
+	k = 0;
+	for (q, d) in d.iter_mut().enumerate() {
+		while z[k + 1] < q as f32 {
+			k += 1;
+		}
+		let dq = q as f32 - v[k] as f32;
+		*d = dq * dq + f[v[k]];
+	}
+}
+
+/// a piece of an outline in pixels, with y going down
+#[derive(Debug, Clone, Copy)]
+pub enum Segment {
+	Line([f32; 2], [f32; 2]),
+	Quad([f32; 2], [f32; 2], [f32; 2]),
+	Cubic([f32; 2], [f32; 2], [f32; 2], [f32; 2]),
+}
+
+impl Segment {
+	fn start(&self) -> [f32; 2] {
+		match *self {
+			Segment::Line(v, _) | Segment::Quad(v, _, _) | Segment::Cubic(v, _, _, _) => v,
+		}
+	}
+	fn end(&self) -> [f32; 2] {
+		match *self {
+			Segment::Line(_, v) | Segment::Quad(_, _, v) | Segment::Cubic(_, _, _, v) => v,
+		}
+	}
+	fn controls(&self) -> Vec<[f32; 2]> {
+		match *self {
+			Segment::Line(a, b) => vec![a, b],
+			Segment::Quad(a, b, c) => vec![a, b, c],
+			Segment::Cubic(a, b, c, d) => vec![a, b, c, d],
+		}
+	}
+	/// which way the segment leaves its start and arrives at its end,
+	/// control points on top of an end don't count
+	fn tangents(&self) -> [[f32; 2]; 2] {
+		let points = self.controls();
+		let (start, end) = (self.start(), self.end());
+		[
+			points
+				.iter()
+				.find(|&&v| !same_point(v, start))
+				.map_or([0.0; 2], |&v| sub(v, start)),
+			points
+				.iter()
+				.rev()
+				.find(|&&v| !same_point(v, end))
+				.map_or([0.0; 2], |&v| sub(end, v)),
+		]
+	}
+	/// points along the segment, curves get split into lines
+	fn flatten(&self) -> Vec<[f32; 2]> {
+		let lerp =
+			|a: [f32; 2], b: [f32; 2], t: f32| [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t];
+		match *self {
+			Segment::Line(a, b) => vec![a, b],
+			Segment::Quad(a, b, c) => (0..=8)
+				.map(|i| {
+					let t = i as f32 / 8.0;
+					lerp(lerp(a, b, t), lerp(b, c, t), t)
+				})
+				.collect(),
+			Segment::Cubic(a, b, c, d) => (0..=12)
+				.map(|i| {
+					let t = i as f32 / 12.0;
+					let (ab, bc, cd) = (lerp(a, b, t), lerp(b, c, t), lerp(c, d, t));
+					lerp(lerp(ab, bc, t), lerp(bc, cd, t), t)
+				})
+				.collect(),
+		}
+	}
+}
+
+/// a flattened segment and the channels it counts towards
+struct Edge {
+	points: Vec<[f32; 2]>,
+	color: u8,
+	/// of the segment and not the flattened lines, which turn a little at
+	/// every point even on smooth curves
+	tangents: [[f32; 2]; 2],
+}
+
+fn sub(a: [f32; 2], b: [f32; 2]) -> [f32; 2] {
+	[a[0] - b[0], a[1] - b[1]]
+}
+
+fn dot(a: [f32; 2], b: [f32; 2]) -> f32 {
+	a[0] * b[0] + a[1] * b[1]
+}
+
+fn cross(a: [f32; 2], b: [f32; 2]) -> f32 {
+	a[0] * b[1] - a[1] * b[0]
+}
+
+fn normalize(a: [f32; 2]) -> [f32; 2] {
+	let len = dot(a, a).sqrt();
+	if len == 0.0 {
+		a
+	} else {
+		[a[0] / len, a[1] / len]
+	}
+}
+
+/// split closed paths of segments into contours, a new one starts wherever a
+/// segment doesn't start where the last one ended
+pub fn contours(segments: &[Segment]) -> Vec<Vec<Segment>> {
+	let mut res: Vec<Vec<Segment>> = vec![];
+	for &segment in segments {
+		match res.last_mut() {
+			Some(contour)
+				if contour
+					.last()
+					.is_some_and(|last| same_point(last.end(), segment.start())) =>
+			{
+				contour.push(segment)
+			}
+			_ => res.push(vec![segment]),
+		}
+	}
+	res
+}
+
+fn same_point(a: [f32; 2], b: [f32; 2]) -> bool {
+	(a[0] - b[0]).abs() < 1e-3 && (a[1] - b[1]).abs() < 1e-3
+}
+
+/// give the edges of a contour colours so that the edges meeting at a corner
+/// never share more than one channel
+fn color_contour(contour: &[Segment]) -> Vec<Edge> {
+	let mut edges = contour
+		.iter()
+		.map(|v| Edge {
+			points: v.flatten(),
+			color: WHITE,
+			tangents: v.tangents(),
+		})
+		.filter(|v| v.points.windows(2).any(|w| !same_point(w[0], w[1])))
+		.collect::<Vec<_>>();
+	let n = edges.len();
+	// about 3 degrees
+	let corners = (0..n)
+		.filter(|&i| {
+			let a = normalize(edges[(i + n - 1) % n].tangents[1]);
+			let b = normalize(edges[i].tangents[0]);
+			dot(a, b) <= 0.0 || cross(a, b).abs() > 0.05
+		})
+		.collect::<Vec<_>>();
+	match corners.len() {
+		0 => {}
+		// a teardrop, split what's after the corner into three
+		1 if n >= 3 => {
+			for i in 0..n {
+				edges[(corners[0] + i) % n].color = [MAGENTA, WHITE, YELLOW][i * 3 / n];
+			}
+		}
+		1 => {}
+		count => {
+			for (i, &corner) in corners.iter().enumerate() {
+				// the last run wraps around to the first one, so it can't
+				// have its colour too
+				let color = if i == count - 1 && count % 2 == 1 {
+					YELLOW
+				} else {
+					[CYAN, MAGENTA][i % 2]
+				};
+				let end = corners.get(i + 1).copied().unwrap_or(corners[0] + n);
+				for j in corner..end {
+					edges[j % n].color = color;
+				}
+			}
+		}
+	}
+	edges
+}
+
+/// how far `p` is from a polyline and which side it's on, as the distance,
+/// the cross product of the closest piece and how head on it was hit
+fn polyline_distance(points: &[[f32; 2]], p: [f32; 2]) -> (f32, f32, f32) {
+	let mut best = (FAR, 0.0, 0.0);
+	for w in points.windows(2) {
+		let ab = sub(w[1], w[0]);
+		let len = dot(ab, ab);
+		if len == 0.0 {
+			continue;
+		}
+		let t = (dot(sub(p, w[0]), ab) / len).clamp(0.0, 1.0);
+		let to = sub(p, [w[0][0] + ab[0] * t, w[0][1] + ab[1] * t]);
+		let distance = dot(to, to).sqrt();
+		let side = cross(ab, sub(p, w[0]));
+		// at a shared point the piece that's hit more head on knows the side
+		let orthogonality = cross(normalize(ab), normalize(to)).abs();
+		if distance < best.0 - 1e-4 || (distance < best.0 + 1e-4 && orthogonality > best.2) {
+			best = (distance, side, orthogonality);
+		}
+	}
+	best
+}
+
+/// whether `p` is inside by the non-zero winding rule
+fn winding(edges: &[Edge], p: [f32; 2]) -> bool {
+	let mut winding = 0;
+	for edge in edges {
+		for w in edge.points.windows(2) {
+			let (a, b) = (w[0], w[1]);
+			if a[1] <= p[1] {
+				if b[1] > p[1] && cross(sub(b, a), sub(p, a)) > 0.0 {
+					winding += 1;
+				}
+			} else if b[1] <= p[1] && cross(sub(b, a), sub(p, a)) < 0.0 {
+				winding -= 1;
+			}
+		}
+	}
+	winding != 0
+}
+
+/// field of an outline. pixel `(x, y)` of the result is centred on
+/// `origin + (x + 0.5, y + 0.5)`. with `multi` the colour channels each hold
+/// the distance to a subset of the edges, alpha always has the real distance
+pub fn from_outline(
+	segments: &[Segment],
+	origin: [f32; 2],
+	size: RectWH,
+	spread: ImagePos,
+	multi: bool,
+) -> CompositeImage {
+	let edges = contours(segments)
+		.iter()
+		.flat_map(|v| color_contour(v))
+		.collect::<Vec<_>>();
+	// which side of an edge is inside depends on which way the outlines go
+	let area: f32 = edges
+		.iter()
+		.flat_map(|v| v.points.windows(2))
+		.map(|w| cross(w[0], w[1]))
+		.sum();
+	let orientation = if area < 0.0 { -1.0 } else { 1.0 };
+	let mut res = transparent(size);
+	for (i, pixel) in res.data.chunks_exact_mut(4).enumerate() {
+		let p = [
+			origin[0] + (i % size.w as usize) as f32 + 0.5,
+			origin[1] + (i / size.w as usize) as f32 + 0.5,
+		];
+		let mut channels = [(FAR, 0.0, 0.0); 3];
+		let mut nearest = FAR;
+		for edge in &edges {
+			let hit = polyline_distance(&edge.points, p);
+			nearest = nearest.min(hit.0);
+			for (c, best) in channels.iter_mut().enumerate() {
+				let closer = hit.0 < best.0 - 1e-4 || (hit.0 < best.0 + 1e-4 && hit.2 > best.2);
+				if edge.color & (1 << c) != 0 && closer {
+					*best = hit;
+				}
+			}
+		}
+		let inside = winding(&edges, p);
+		let real = encode(if inside { nearest } else { -nearest }, spread);
+		let rgb = channels.map(|(distance, side, _)| {
+			let sign = if side * orientation > 0.0 { 1.0 } else { -1.0 };
+			encode(distance.min(FAR) * sign, spread)
+		});
+		let median = rgb[0].max(rgb[1]).min(rgb[0].min(rgb[1]).max(rgb[2]));
+		// channels that disagree with the real distance on which side of the
+		// edge a pixel is on would poke holes into the shape
+		if !multi || (median >= 0x80) != inside {
+			pixel.copy_from_slice(&[real; 4]);
+		} else {
+			pixel.copy_from_slice(&[rgb[0], rgb[1], rgb[2], real]);
+		}
+	}
+	res
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// `#` is inside
+	fn mask(rows: &[&str]) -> (Vec<bool>, usize, usize) {
+		let mask = rows
+			.iter()
+			.flat_map(|v| v.chars())
+			.map(|v| v == '#')
+			.collect();
+		(mask, rows[0].len(), rows.len())
+	}
+
+	/// solid where `inside` says so, transparent everywhere else
+	fn image(w: usize, h: usize, inside: impl Fn(f32, f32) -> bool) -> CompositeImage {
+		let mut res = transparent(RectWH::new(w as ImagePos, h as ImagePos));
+		for (i, pixel) in res.data.chunks_exact_mut(4).enumerate() {
+			let (x, y) = ((i % w) as f32 + 0.5, (i / w) as f32 + 0.5);
+			if inside(x, y) {
+				pixel.copy_from_slice(&[0xFF; 4]);
+			}
+		}
+		res
+	}
+
+	fn value(image: &CompositeImage, x: usize, y: usize, channel: usize) -> u8 {
+		image.data[(y * image.size.w as usize + x) * 4 + channel]
+	}
+
+	fn median(v: [u8; 3]) -> u8 {
+		v[0].max(v[1]).min(v[0].min(v[1]).max(v[2]))
+	}
+
+	fn square(x0: f32, y0: f32, x1: f32, y1: f32) -> Vec<Segment> {
+		let corners = [[x0, y0], [x1, y0], [x1, y1], [x0, y1]];
+		(0..4)
+			.map(|i| Segment::Line(corners[i], corners[(i + 1) % 4]))
+			.collect()
+	}
+
+	#[test]
+	fn line_transform() {
+		let mut out = [0.0; 5];
+		transform_line(&[FAR, 0.0, FAR, FAR, FAR], &mut out);
+		assert_eq!(out, [1.0, 0.0, 1.0, 4.0, 9.0]);
+		transform_line(&[FAR, 0.0, FAR, FAR, 0.0], &mut out);
+		assert_eq!(out, [1.0, 0.0, 1.0, 1.0, 0.0]);
+		// a closer start further away still wins
+		transform_line(&[9.0, FAR, FAR, 0.0, FAR], &mut out);
+		assert_eq!(out, [9.0, 4.0, 1.0, 0.0, 1.0]);
+	}
+
+	#[test]
+	fn distances_match_brute_force() {
+		let (mask, w, h) = mask(&[
+			"..........",
+			"..###.....",
+			"..####....",
+			"......#...",
+			".........#",
+			"..........",
+		]);
+		for target in [true, false] {
+			let distances = squared_distances(&mask, w, h, target);
+			for (i, &distance) in distances.iter().enumerate() {
+				let expected = (0..w * h)
+					.filter(|&j| mask[j] == target)
+					.map(|j| {
+						let (dx, dy) = (
+							(i % w) as f32 - (j % w) as f32,
+							(i / w) as f32 - (j / w) as f32,
+						);
+						dx * dx + dy * dy
+					})
+					.fold(FAR, f32::min);
+				assert_eq!(distance, expected, "{} from {}", target, i);
+			}
+		}
+	}
+
+	#[test]
+	fn disc_edge_is_half_way() {
+		let spread = 4;
+		let disc = image(24, 24, |x, y| (x - 12.0).hypot(y - 12.0) < 8.0);
+		let field = from_alpha(&disc, RectXYWH::new(0, 0, 24, 24), spread);
+		assert_eq!(field.size, RectWH::new(32, 32));
+		let inside = |x: usize, y: usize| value(&disc, x - 4, y - 4, 3) != 0;
+		let mut edges = 0;
+		for y in 4..28 {
+			for x in 4..27 {
+				if inside(x, y) != inside(x + 1, y) {
+					let pair = value(&field, x, y, 3) as i32 + value(&field, x + 1, y, 3) as i32;
+					assert!((pair - 0x100).abs() <= 2, "{} {}: {}", x, y, pair);
+					edges += 1;
+				}
+			}
+		}
+		assert!(edges >= 2 * 16);
+		// the same distance goes in every channel
+		assert!(field
+			.data
+			.chunks_exact(4)
+			.all(|v| v[0] == v[3] && v[1] == v[3] && v[2] == v[3]));
+	}
+
+	#[test]
+	fn distances_go_one_way() {
+		let spread = 4;
+		let square = image(12, 12, |x, y| {
+			(2.0..10.0).contains(&x) && (2.0..10.0).contains(&y)
+		});
+		let field = from_alpha(&square, RectXYWH::new(0, 0, 12, 12), spread);
+		// across the middle the field rises to the centre and then falls
+		let row = (0..20).map(|x| value(&field, x, 10, 3)).collect::<Vec<_>>();
+		for x in 0..19 {
+			let (a, b) = (row[x], row[x + 1]);
+			if x < 9 {
+				assert!(a <= b, "{:?}", row);
+			} else if x >= 10 {
+				assert!(a >= b, "{:?}", row);
+			}
+		}
+		// strictly while it's in range, inside is above half and outside below
+		for x in 1..9 {
+			assert!(row[x] < row[x + 1], "{:?}", row);
+		}
+		for (x, &v) in row.iter().enumerate() {
+			assert_eq!(v >= 0x80, (6..14).contains(&x), "{:?}", row);
+		}
+		assert_eq!(row[0], 0);
+		assert_eq!(row[9], row[10]);
+	}
+
+	#[test]
+	fn corners_never_share_more_than_a_channel() {
+		let triangle = [
+			Segment::Line([0.0, 0.0], [8.0, 0.0]),
+			Segment::Line([8.0, 0.0], [4.0, 6.0]),
+			Segment::Line([4.0, 6.0], [0.0, 0.0]),
+		];
+		for contour in [square(0.0, 0.0, 8.0, 8.0), triangle.to_vec()] {
+			let edges = color_contour(&contour);
+			assert_eq!(edges.len(), contour.len());
+			for i in 0..edges.len() {
+				let (a, b) = (edges[i].color, edges[(i + 1) % edges.len()].color);
+				assert_eq!(
+					(a & b).count_ones(),
+					1,
+					"{:?}",
+					edges.iter().map(|v| v.color).collect::<Vec<_>>()
+				);
+				assert_eq!(a.count_ones(), 2);
+			}
+		}
+		// nothing to keep sharp on a smooth curve
+		let circle = [
+			Segment::Quad([0.0, 4.0], [0.0, 0.0], [4.0, 0.0]),
+			Segment::Quad([4.0, 0.0], [8.0, 0.0], [8.0, 4.0]),
+			Segment::Quad([8.0, 4.0], [8.0, 8.0], [4.0, 8.0]),
+			Segment::Quad([4.0, 8.0], [0.0, 8.0], [0.0, 4.0]),
+		];
+		assert!(color_contour(&circle).iter().all(|v| v.color == WHITE));
+	}
+
+	#[test]
+	fn msdf_median_has_the_right_side() {
+		let spread = 3;
+		let size = RectWH::new(16, 16);
+		// either way round
+		for outline in [square(4.0, 4.0, 12.0, 12.0), square(4.0, 12.0, 12.0, 4.0)] {
+			let field = from_outline(&outline, [0.0, 0.0], size, spread, true);
+			let mut split = 0;
+			for y in 0..16 {
+				for x in 0..16 {
+					let inside = (4..12).contains(&x) && (4..12).contains(&y);
+					let rgb = [0, 1, 2].map(|c| value(&field, x, y, c));
+					let alpha = value(&field, x, y, 3);
+					assert_eq!(median(rgb) >= 0x80, inside, "{} {}: {:?}", x, y, rgb);
+					assert_eq!(alpha >= 0x80, inside, "{} {}", x, y);
+					if rgb.iter().any(|&v| v != alpha) {
+						split += 1;
+					}
+					// along the middle of a side the median is the real distance
+					if (6..10).contains(&x) || (6..10).contains(&y) {
+						assert!(median(rgb).abs_diff(alpha) <= 1, "{} {}", x, y);
+					}
+				}
+			}
+			// the channels really were split up and not thrown away
+			assert!(split > 16, "{}", split);
+			// the corners stay sharp, outside one the median is further out
+			// than the round distance
+			let corner = [0, 1, 2].map(|c| value(&field, 3, 3, c));
+			assert!(median(corner) < value(&field, 3, 3, 3) + 2);
+		}
+	}
+}
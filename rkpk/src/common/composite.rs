@@ -0,0 +1,494 @@
+//! putting images together
+//!
+//! images are straight alpha unless a function says otherwise, horizon
+//! blends premultiplied alpha so anything drawn as-is needs `premultiply`
+
+use super::{CompositeImage, Flip, ImagePos, RectWH, RectXYWH, RkPkError, RkPkResult};
+
+/// `a * b / 255`, rounded
+fn mul(a: u32, b: u32) -> u32 {
+	(a * b + 127) / 255
+}
+
+fn to_linear(v: u8) -> f32 {
+	let v = v as f32 / 255.0;
+	if v <= 0.04045 {
+		v / 12.92
+	} else {
+		((v + 0.055) / 1.055).powf(2.4)
+	}
+}
+
+fn to_srgb(v: f32) -> u8 {
+	let v = v.clamp(0.0, 1.0);
+	let v = if v <= 0.0031308 {
+		v * 12.92
+	} else {
+		1.055 * v.powf(1.0 / 2.4) - 0.055
+	};
+	(v * 255.0).round() as u8
+}
+
+impl CompositeImage {
+	fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+		let i = (y * self.size.w as usize + x) * 4;
+		[
+			self.data[i],
+			self.data[i + 1],
+			self.data[i + 2],
+			self.data[i + 3],
+		]
+	}
+	/// error if `other_uv` isn't inside `other` or doesn't fit in this image
+	/// at `self_offset`
+	fn check_copy(
+		&self,
+		other: &CompositeImage,
+		self_offset: RectWH,
+		other_uv: RectXYWH,
+	) -> RkPkResult<()> {
+		// in u32 so it can't overflow
+		let end = |a: ImagePos, b: ImagePos| a as u32 + b as u32;
+		if end(other_uv.x, other_uv.w) > other.size.w as u32
+			|| end(other_uv.y, other_uv.h) > other.size.h as u32
+			|| end(self_offset.w, other_uv.w) > self.size.w as u32
+			|| end(self_offset.h, other_uv.h) > self.size.h as u32
+		{
+			return Err(RkPkError::BadCopy {
+				from: other.size,
+				uv: other_uv,
+				to: self.size,
+				offset: self_offset,
+			});
+		}
+		Ok(())
+	}
+	/// call `f` with every row of `other_uv` and the row it lands on
+	fn rows_from(
+		&mut self,
+		other: &CompositeImage,
+		self_offset: RectWH,
+		other_uv: RectXYWH,
+		mut f: impl FnMut(&mut [u8], &[u8]),
+	) -> RkPkResult<()> {
+		self.check_copy(other, self_offset, other_uv)?;
+		let step = other_uv.w as usize * 4;
+		for y in 0..other_uv.h as usize {
+			let s_start =
+				((other_uv.y as usize + y) * other.size.w as usize + other_uv.x as usize) * 4;
+			let d_start =
+				((self_offset.h as usize + y) * self.size.w as usize + self_offset.w as usize) * 4;
+			f(
+				&mut self.data[d_start..d_start + step],
+				&other.data[s_start..s_start + step],
+			);
+		}
+		Ok(())
+	}
+	/// copy `other_uv` of another image over the pixels at `self_offset`,
+	/// which uses `w` as x and `h` as y
+	pub fn copy_from(
+		&mut self,
+		other: &CompositeImage,
+		self_offset: RectWH,
+		other_uv: RectXYWH,
+	) -> RkPkResult<()> {
+		let whole_rows = other_uv.x == 0
+			&& self_offset.w == 0
+			&& other_uv.w == other.size.w
+			&& other_uv.w == self.size.w;
+		if whole_rows {
+			// the rows are next to each other in both images
+			self.check_copy(other, self_offset, other_uv)?;
+			let row = self.size.w as usize * 4;
+			let s_start = other_uv.y as usize * row;
+			let d_start = self_offset.h as usize * row;
+			let len = other_uv.h as usize * row;
+			self.data[d_start..d_start + len].copy_from_slice(&other.data[s_start..s_start + len]);
+			Ok(())
+		} else {
+			self.rows_from(other, self_offset, other_uv, |to, from| {
+				to.copy_from_slice(from)
+			})
+		}
+	}
+	/// draw `other_uv` of another image over the pixels at `self_offset`
+	pub fn blend_from(
+		&mut self,
+		other: &CompositeImage,
+		self_offset: RectWH,
+		other_uv: RectXYWH,
+	) -> RkPkResult<()> {
+		self.rows_from(other, self_offset, other_uv, |to, from| {
+			for (to, from) in to.chunks_exact_mut(4).zip(from.chunks_exact(4)) {
+				// everything scaled by 255 * 255 until the end
+				let src_a = from[3] as u32 * 255;
+				let dst_a = to[3] as u32 * (255 - from[3] as u32);
+				let out_a = src_a + dst_a;
+				if out_a == 0 {
+					to.fill(0);
+					continue;
+				}
+				for c in 0..3 {
+					to[c] =
+						((from[c] as u32 * src_a + to[c] as u32 * dst_a + out_a / 2) / out_a) as u8;
+				}
+				to[3] = ((out_a + 127) / 255) as u8;
+			}
+		})
+	}
+	/// `blend_from` for when both images are premultiplied
+	pub fn blend_premultiplied_from(
+		&mut self,
+		other: &CompositeImage,
+		self_offset: RectWH,
+		other_uv: RectXYWH,
+	) -> RkPkResult<()> {
+		self.rows_from(other, self_offset, other_uv, |to, from| {
+			for (to, from) in to.chunks_exact_mut(4).zip(from.chunks_exact(4)) {
+				let keep = 255 - from[3] as u32;
+				for c in 0..4 {
+					to[c] = (from[c] as u32 + mul(to[c] as u32, keep)).min(255) as u8;
+				}
+			}
+		})
+	}
+	/// multiply colours by their alpha
+	pub fn premultiply(&mut self) {
+		for v in self.data.chunks_exact_mut(4) {
+			let a = v[3] as u32;
+			for c in &mut v[..3] {
+				*c = mul(*c as u32, a) as u8;
+			}
+		}
+	}
+	/// `premultiply` for pages that get sampled as srgb, the colours are
+	/// multiplied in linear light so the gpu decodes them to the right thing
+	pub fn premultiply_srgb(&mut self) {
+		for v in self.data.chunks_exact_mut(4) {
+			let a = v[3] as f32 / 255.0;
+			for c in &mut v[..3] {
+				*c = to_srgb(to_linear(*c) * a);
+			}
+		}
+	}
+	/// half size copy of a `premultiply_srgb`ed image, averaged in linear
+	/// light so edges don't get darker
+	pub fn downsample_srgb(&self) -> CompositeImage {
+		let size = RectWH::new((self.size.w / 2).max(1), (self.size.h / 2).max(1));
+		let mut data = Vec::with_capacity(size.area() as usize * 4);
+		for y in 0..size.h as usize {
+			for x in 0..size.w as usize {
+				let mut sum = [0.0; 3];
+				let mut alpha = 0;
+				for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+					let sx = (x * 2 + dx).min(self.size.w as usize - 1);
+					let sy = (y * 2 + dy).min(self.size.h as usize - 1);
+					let v = self.pixel(sx, sy);
+					for (sum, &c) in sum.iter_mut().zip(&v[..3]) {
+						*sum += to_linear(c);
+					}
+					alpha += v[3] as u32;
+				}
+				data.extend(sum.map(|v| to_srgb(v / 4.0)));
+				data.push(((alpha + 2) / 4) as u8);
+			}
+		}
+		CompositeImage { size, data }
+	}
+	/// undo `premultiply`, fully transparent pixels end up black
+	pub fn unpremultiply(&mut self) {
+		for v in self.data.chunks_exact_mut(4) {
+			let a = v[3] as u32;
+			for c in &mut v[..3] {
+				*c = match a {
+					0 => 0,
+					a => ((*c as u32 * 255 + a / 2) / a).min(255) as u8,
+				};
+			}
+		}
+	}
+	/// copy with the pixel at `x, y` taken from `pick(x, y)` of this image
+	fn remap(&self, size: RectWH, pick: impl Fn(usize, usize) -> (usize, usize)) -> CompositeImage {
+		let mut data = Vec::with_capacity(size.area() as usize * 4);
+		for y in 0..size.h as usize {
+			for x in 0..size.w as usize {
+				let (x, y) = pick(x, y);
+				data.extend_from_slice(&self.pixel(x, y));
+			}
+		}
+		CompositeImage { size, data }
+	}
+	/// mirrored copy of `uv`
+	pub fn flipped(&self, uv: RectXYWH, flip: Flip) -> CompositeImage {
+		let (x0, y0) = (uv.x as usize, uv.y as usize);
+		let (w, h) = (uv.w as usize, uv.h as usize);
+		self.remap(uv.to_wh(), |x, y| {
+			(
+				x0 + if flip.x { w - 1 - x } else { x },
+				y0 + if flip.y { h - 1 - y } else { y },
+			)
+		})
+	}
+	/// copy turned clockwise by `turns` quarter turns
+	pub fn rotated(&self, turns: u8) -> CompositeImage {
+		let RectWH { w, h } = self.size;
+		let (w, h) = (w as usize, h as usize);
+		match turns % 4 {
+			0 => self.remap(self.size, |x, y| (x, y)),
+			1 => self.remap(RectWH::new(h as ImagePos, w as ImagePos), |x, y| {
+				(y, h - 1 - x)
+			}),
+			2 => self.remap(self.size, |x, y| (w - 1 - x, h - 1 - y)),
+			_ => self.remap(RectWH::new(h as ImagePos, w as ImagePos), |x, y| {
+				(w - 1 - y, x)
+			}),
+		}
+	}
+	/// copy resized to `size` with every pixel taken from the closest one,
+	/// for pixel art
+	pub fn scaled_nearest(&self, size: RectWH) -> CompositeImage {
+		if self.size.area() == 0 {
+			return CompositeImage {
+				size,
+				data: vec![0; size.area() as usize * 4],
+			};
+		}
+		// sample the middle of every pixel
+		let pick = |v: usize, from: ImagePos, to: ImagePos| {
+			(v * 2 + 1) * from as usize / (to as usize * 2)
+		};
+		self.remap(size, |x, y| {
+			(pick(x, self.size.w, size.w), pick(y, self.size.h, size.h))
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// image where every pixel is `[x, y, i, 255]`
+	fn numbered(w: ImagePos, h: ImagePos) -> CompositeImage {
+		let data = (0..h)
+			.flat_map(|y| (0..w).map(move |x| (x, y)))
+			.enumerate()
+			.flat_map(|(i, (x, y))| [x as u8, y as u8, i as u8, 255])
+			.collect();
+		CompositeImage {
+			size: RectWH::new(w, h),
+			data,
+		}
+	}
+
+	fn filled(w: ImagePos, h: ImagePos, color: [u8; 4]) -> CompositeImage {
+		CompositeImage {
+			size: RectWH::new(w, h),
+			data: color.repeat(w as usize * h as usize),
+		}
+	}
+
+	#[test]
+	fn copy_whole_rows_at_an_offset() {
+		let from = numbered(3, 4);
+		let mut to = filled(3, 5, [0; 4]);
+		to.copy_from(&from, RectWH::new(0, 2), RectXYWH::new(0, 1, 3, 2))
+			.unwrap();
+		assert_eq!(to.pixel(0, 1), [0; 4]);
+		assert_eq!(to.pixel(0, 2), from.pixel(0, 1));
+		assert_eq!(to.pixel(2, 3), from.pixel(2, 2));
+		assert_eq!(to.pixel(0, 4), [0; 4]);
+	}
+
+	#[test]
+	fn copy_part_of_a_row() {
+		let from = numbered(4, 4);
+		let mut to = filled(5, 5, [0; 4]);
+		to.copy_from(&from, RectWH::new(3, 1), RectXYWH::new(1, 2, 2, 2))
+			.unwrap();
+		assert_eq!(to.pixel(3, 1), from.pixel(1, 2));
+		assert_eq!(to.pixel(4, 2), from.pixel(2, 3));
+		assert_eq!(to.pixel(2, 1), [0; 4]);
+		assert_eq!(to.pixel(3, 3), [0; 4]);
+	}
+
+	#[test]
+	fn copy_out_of_bounds() {
+		let from = numbered(4, 4);
+		let mut to = filled(4, 4, [0; 4]);
+		assert!(to
+			.copy_from(&from, RectWH::new(1, 0), RectXYWH::new(0, 0, 4, 4))
+			.is_err());
+		assert!(to
+			.copy_from(&from, RectWH::new(0, 0), RectXYWH::new(0, 3, 2, 2))
+			.is_err());
+		assert!(to
+			.copy_from(
+				&from,
+				RectWH::new(0, 0),
+				RectXYWH::new(ImagePos::MAX, 0, 2, 2)
+			)
+			.is_err());
+	}
+
+	#[test]
+	fn blend() {
+		let mut to = filled(1, 1, [0, 0, 255, 255]);
+		to.blend_from(
+			&filled(1, 1, [255, 0, 0, 128]),
+			RectWH::default(),
+			RectXYWH::new(0, 0, 1, 1),
+		)
+		.unwrap();
+		assert_eq!(to.pixel(0, 0), [128, 0, 127, 255]);
+		// over nothing the colour stays the same
+		let mut to = filled(1, 1, [0; 4]);
+		to.blend_from(
+			&filled(1, 1, [255, 0, 0, 128]),
+			RectWH::default(),
+			RectXYWH::new(0, 0, 1, 1),
+		)
+		.unwrap();
+		assert_eq!(to.pixel(0, 0), [255, 0, 0, 128]);
+		// and a transparent pixel doesn't change anything
+		let mut to = filled(1, 1, [10, 20, 30, 40]);
+		to.blend_from(
+			&filled(1, 1, [255, 0, 0, 0]),
+			RectWH::default(),
+			RectXYWH::new(0, 0, 1, 1),
+		)
+		.unwrap();
+		assert_eq!(to.pixel(0, 0), [10, 20, 30, 40]);
+	}
+
+	#[test]
+	fn blend_premultiplied_matches_straight() {
+		let (dst, src) = ([0, 200, 100, 200], [255, 50, 0, 100]);
+		let mut straight = filled(1, 1, dst);
+		straight
+			.blend_from(
+				&filled(1, 1, src),
+				RectWH::default(),
+				RectXYWH::new(0, 0, 1, 1),
+			)
+			.unwrap();
+		straight.premultiply();
+		let mut premultiplied = filled(1, 1, dst);
+		premultiplied.premultiply();
+		let mut src = filled(1, 1, src);
+		src.premultiply();
+		premultiplied
+			.blend_premultiplied_from(&src, RectWH::default(), RectXYWH::new(0, 0, 1, 1))
+			.unwrap();
+		for (a, b) in straight.data.iter().zip(&premultiplied.data) {
+			assert!(
+				a.abs_diff(*b) <= 1,
+				"{:?} {:?}",
+				straight,
+				premultiplied.data
+			);
+		}
+	}
+
+	#[test]
+	fn premultiply() {
+		let mut image = filled(1, 1, [255, 128, 0, 128]);
+		image.premultiply();
+		assert_eq!(image.pixel(0, 0), [128, 64, 0, 128]);
+		image.unpremultiply();
+		assert_eq!(image.pixel(0, 0), [255, 128, 0, 128]);
+		let mut image = filled(1, 1, [255, 255, 255, 0]);
+		image.premultiply();
+		assert_eq!(image.pixel(0, 0), [0; 4]);
+	}
+
+	#[test]
+	fn premultiply_srgb() {
+		let mut image = CompositeImage {
+			size: RectWH::new(3, 1),
+			data: vec![255, 255, 255, 128, 10, 100, 200, 255, 255, 255, 255, 0],
+		};
+		image.premultiply_srgb();
+		// half of white in linear light is brighter than half of 255
+		assert_eq!(image.pixel(0, 0), [188, 188, 188, 128]);
+		assert_eq!(image.pixel(1, 0), [10, 100, 200, 255]);
+		assert_eq!(image.pixel(2, 0), [0; 4]);
+	}
+
+	#[test]
+	fn downsample_srgb() {
+		let image = CompositeImage {
+			size: RectWH::new(2, 2),
+			data: [[0, 0, 0, 255], [255, 255, 255, 255]].concat().repeat(2),
+		};
+		assert_eq!(image.downsample_srgb().data, [188, 188, 188, 255]);
+		// transparent pixels are black once premultiplied so they don't tint
+		let image = CompositeImage {
+			size: RectWH::new(2, 1),
+			data: vec![255, 0, 0, 255, 0, 0, 0, 0],
+		};
+		assert_eq!(image.downsample_srgb().data, [188, 0, 0, 128]);
+	}
+
+	#[test]
+	fn flips() {
+		let image = numbered(3, 2);
+		let whole = RectXYWH::new(0, 0, 3, 2);
+		let x = image.flipped(whole, Flip { x: true, y: false });
+		assert_eq!(x.pixel(0, 0), image.pixel(2, 0));
+		assert_eq!(x.pixel(2, 1), image.pixel(0, 1));
+		let y = image.flipped(whole, Flip { x: false, y: true });
+		assert_eq!(y.pixel(0, 0), image.pixel(0, 1));
+		let both = image.flipped(whole, Flip { x: true, y: true });
+		assert_eq!(both.pixel(0, 0), image.pixel(2, 1));
+		assert_eq!(
+			both.flipped(whole, Flip { x: true, y: true }).data,
+			image.data
+		);
+		// only the uv comes out
+		let part = numbered(4, 4).flipped(RectXYWH::new(1, 2, 2, 1), Flip { x: true, y: false });
+		assert_eq!(part.size, RectWH::new(2, 1));
+		assert_eq!(part.data, [2, 2, 10, 255, 1, 2, 9, 255]);
+		assert_eq!(
+			numbered(4, 4)
+				.flipped(RectXYWH::new(1, 2, 2, 1), Flip::default())
+				.data,
+			[1, 2, 9, 255, 2, 2, 10, 255]
+		);
+	}
+
+	#[test]
+	fn rotations() {
+		let image = numbered(3, 2);
+		let once = image.rotated(1);
+		assert_eq!(once.size, RectWH::new(2, 3));
+		// the bottom left corner ends up in the top left
+		assert_eq!(once.pixel(0, 0), image.pixel(0, 1));
+		assert_eq!(once.pixel(1, 0), image.pixel(0, 0));
+		assert_eq!(once.pixel(1, 2), image.pixel(2, 0));
+		assert_eq!(image.rotated(2).data, once.rotated(1).data);
+		assert_eq!(image.rotated(3).data, once.rotated(2).data);
+		assert_eq!(image.rotated(4).data, image.data);
+		assert_eq!(image.rotated(3).rotated(1).data, image.data);
+		assert_eq!(
+			image.rotated(2).data,
+			image
+				.flipped(RectXYWH::new(0, 0, 3, 2), Flip { x: true, y: true })
+				.data
+		);
+	}
+
+	#[test]
+	fn nearest_scaling() {
+		let image = numbered(2, 2);
+		let big = image.scaled_nearest(RectWH::new(4, 6));
+		assert_eq!(big.size, RectWH::new(4, 6));
+		assert_eq!(big.pixel(1, 2), image.pixel(0, 0));
+		assert_eq!(big.pixel(2, 3), image.pixel(1, 1));
+		assert_eq!(big.scaled_nearest(RectWH::new(2, 2)).data, image.data);
+		let image = numbered(4, 1);
+		let small = image.scaled_nearest(RectWH::new(2, 1));
+		assert_eq!(small.pixel(0, 0), image.pixel(1, 0));
+		assert_eq!(small.pixel(1, 0), image.pixel(3, 0));
+	}
+}
@@ -1,19 +1,101 @@
+use std::collections::HashMap;
 use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::format::PixelFormat;
+
+pub mod composite;
+
 pub type ImagePos = u16;
 pub type ImageArea = u32;
 
 #[derive(Error, Debug)]
 pub enum RkPkError {
-	#[error("io error")]
+	#[error("io error: {0}")]
 	IoError(#[from] std::io::Error),
-	#[error("image error")]
+	#[error("image error: {0}")]
 	ImageError(#[from] image::ImageError),
+	#[error("asset error: {0}")]
+	AssetError(#[from] asset::common::AssetError),
+	#[error("postcard error: {0}")]
+	PostcardError(#[from] postcard::Error),
+	/// something went wrong reading a file, the error doesn't know which
+	#[error("{path:?}: {source}")]
+	File {
+		path: PathBuf,
+		source: Box<RkPkError>,
+	},
+	#[error("bad sidecar {path:?}: {source}")]
+	BadSidecar {
+		path: PathBuf,
+		source: toml::de::Error,
+	},
+	#[error("{path:?} is {}x{}, images can't be bigger than {max}x{max}", size[0], size[1], max = ImagePos::MAX)]
+	TooBig { path: PathBuf, size: [u32; 2] },
+	#[error("{path:?} has a frame at {rect:?} outside of its {size:?}")]
+	OutOfBounds {
+		path: PathBuf,
+		rect: RectXYWH,
+		size: RectWH,
+	},
+	#[error("{:?} and {:?} would both be {name:?}", paths[0], paths[1])]
+	NameCollision { name: String, paths: [PathBuf; 2] },
+	#[error("{path:?}: {what} aren't supported yet")]
+	Unsupported { path: PathBuf, what: &'static str },
+	#[error("animation {0:?} uses frames that don't exist")]
+	BadAnimation(String),
+	#[error("companion map {0:?} doesn't match its image")]
+	BadCompanion(String),
+	#[error("bad palette for {0:?}")]
+	BadPalette(String),
+	#[error("bad font {0:?}")]
+	BadFont(String),
+	#[error("bad aseprite file: {0}")]
+	BadAseprite(&'static str),
+	#[cfg(feature = "build")]
+	#[error("json error: {0}")]
+	JsonError(#[from] serde_json::Error),
+	/// frames bigger than a whole page, by name and size
+	#[error("{layer} has frames that don't fit in a page: {frames:?}")]
+	PackingFailed {
+		layer: String,
+		frames: Vec<(String, RectWH)>,
+	},
+	#[error("can't copy {uv:?} of a {from:?} image to {offset:?} in a {to:?} image")]
+	BadCopy {
+		from: RectWH,
+		uv: RectXYWH,
+		to: RectWH,
+		/// `w` is x and `h` is y
+		offset: RectWH,
+	},
+	#[error("bad pixel data for {0:?}")]
+	BadPixelData(PixelFormat),
 }
 
 pub type RkPkResult<T> = Result<T, RkPkError>;
 
+/// attach the file an error came from
+pub trait WithPath<T> {
+	fn with_path(self, path: impl AsRef<Path>) -> RkPkResult<T>;
+}
+
+impl<T, E: Into<RkPkError>> WithPath<T> for Result<T, E> {
+	fn with_path(self, path: impl AsRef<Path>) -> RkPkResult<T> {
+		self.map_err(|e| match e.into() {
+			// the innermost path is the most useful one
+			e @ RkPkError::File { .. } => e,
+			e => RkPkError::File {
+				path: path.as_ref().to_path_buf(),
+				source: Box::new(e),
+			},
+		})
+	}
+}
+
 /// composite image
 pub struct CompositeImage {
 	pub size: RectWH,
@@ -39,37 +121,110 @@ impl CompositeImage {
 		}
 		Self { data, size }
 	}
-	// copy from another image
-	pub fn copy_from(
-		&mut self,
-		other: &CompositeImage,
-		self_offset: RectWH,
-		other_uv: RectXYWH,
-	) {
-		// bounds checking
-		if other_uv.x + other_uv.w > other.size.w
-			|| other_uv.y + other_uv.h > other.size.h
-			|| self_offset.w + other_uv.w > self.size.w
-			|| self_offset.h + other_uv.h > self.size.h
-		{
-			panic!("out of bounds copy");
-		}
-		let step = other_uv.w as usize * 4;
-		if self.size.w == other.size.w && other_uv.w == self.size.w {
-			// direct copy
-			let s_start = (other_uv.y * other_uv.w) as usize * 4;
-			let d_start = (self_offset.h * other_uv.w) as usize * 4;
-			let step = step * other_uv.h as usize;
-			self.data[d_start..d_start + step]
-				.copy_from_slice(&other.data[s_start..s_start + step]);
-		} else {
-			for y in 0..other_uv.h {
-				let s_start = ((y + other_uv.y) * other.size.w + other_uv.x) as usize * 4;
-				let d_start = ((y + self_offset.h) * self.size.w + self_offset.w) as usize * 4;
-				self.data[d_start..d_start + step]
-					.copy_from_slice(&other.data[s_start..s_start + step]);
+	/// open an image file, anything too big for `ImagePos` is an error
+	/// instead of getting cut off
+	pub fn open(path: impl AsRef<Path>) -> RkPkResult<Self> {
+		let path = path.as_ref();
+		let image = image::open(path).with_path(path)?.to_rgba8();
+		if image.width() > ImagePos::MAX as u32 || image.height() > ImagePos::MAX as u32 {
+			return Err(RkPkError::TooBig {
+				path: path.to_path_buf(),
+				size: [image.width(), image.height()],
+			});
+		}
+		Ok(image.into())
+	}
+	/// half size copy, every pixel is the alpha weighted average of a 2x2
+	/// block so transparent pixels don't darken the edges
+	pub fn downsample(&self) -> CompositeImage {
+		let size = RectWH::new((self.size.w / 2).max(1), (self.size.h / 2).max(1));
+		let mut data = Vec::with_capacity(size.area() as usize * 4);
+		for y in 0..size.h as usize {
+			for x in 0..size.w as usize {
+				let mut sum = [0u32; 4];
+				for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+					let sx = (x * 2 + dx).min(self.size.w as usize - 1);
+					let sy = (y * 2 + dy).min(self.size.h as usize - 1);
+					let i = (sy * self.size.w as usize + sx) * 4;
+					let a = self.data[i + 3] as u32;
+					for (sum, &v) in sum.iter_mut().zip(&self.data[i..i + 3]) {
+						*sum += v as u32 * a;
+					}
+					sum[3] += a;
+				}
+				let alpha = sum[3];
+				for &sum in &sum[..3] {
+					data.push((sum + alpha / 2).checked_div(alpha).unwrap_or(0) as u8);
+				}
+				data.push(((alpha + 2) / 4) as u8);
 			}
 		}
+		CompositeImage { size, data }
+	}
+	/// half size copy that averages every channel on its own, for pages that
+	/// don't hold colours
+	pub fn downsample_average(&self) -> CompositeImage {
+		let size = RectWH::new((self.size.w / 2).max(1), (self.size.h / 2).max(1));
+		let mut data = Vec::with_capacity(size.area() as usize * 4);
+		for y in 0..size.h as usize {
+			for x in 0..size.w as usize {
+				let mut sum = [0u32; 4];
+				for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+					let sx = (x * 2 + dx).min(self.size.w as usize - 1);
+					let sy = (y * 2 + dy).min(self.size.h as usize - 1);
+					let i = (sy * self.size.w as usize + sx) * 4;
+					for (sum, &v) in sum.iter_mut().zip(&self.data[i..i + 4]) {
+						*sum += v as u32;
+					}
+				}
+				data.extend(sum.map(|v| ((v + 2) / 4) as u8));
+			}
+		}
+		CompositeImage { size, data }
+	}
+	/// half size copy that keeps the top left pixel of every 2x2 block, for
+	/// pages where averaging pixels makes no sense
+	pub fn downsample_nearest(&self) -> CompositeImage {
+		let size = RectWH::new((self.size.w / 2).max(1), (self.size.h / 2).max(1));
+		let mut data = Vec::with_capacity(size.area() as usize * 4);
+		for y in 0..size.h as usize {
+			let sy = (y * 2).min(self.size.h as usize - 1);
+			for x in 0..size.w as usize {
+				let sx = (x * 2).min(self.size.w as usize - 1);
+				let i = (sy * self.size.w as usize + sx) * 4;
+				data.extend_from_slice(&self.data[i..i + 4]);
+			}
+		}
+		CompositeImage { size, data }
+	}
+	/// smallest rect inside `uv` that contains every non-transparent pixel,
+	/// `None` if every pixel is transparent
+	pub fn opaque_bounds(&self, uv: RectXYWH) -> Option<RectXYWH> {
+		let alpha = |x: ImagePos, y: ImagePos| {
+			self.data[(y as usize * self.size.w as usize + x as usize) * 4 + 3]
+		};
+		let (mut min_x, mut min_y) = (ImagePos::MAX, ImagePos::MAX);
+		let (mut max_x, mut max_y) = (0, 0);
+		for y in uv.y..uv.y + uv.h {
+			for x in uv.x..uv.x + uv.w {
+				if alpha(x, y) != 0 {
+					min_x = min_x.min(x);
+					min_y = min_y.min(y);
+					max_x = max_x.max(x);
+					max_y = max_y.max(y);
+				}
+			}
+		}
+		if min_x > max_x {
+			None
+		} else {
+			Some(RectXYWH::new(
+				min_x,
+				min_y,
+				max_x - min_x + 1,
+				max_y - min_y + 1,
+			))
+		}
 	}
 }
 
@@ -88,7 +243,281 @@ impl From<CompositeImage> for image::RgbaImage {
 	}
 }
 
-#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// atlas metadata, written by `build` and read by `runtime`
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct AtlasMeta {
+	/// every page, pages of the same layer are next to each other
+	pub pages: Vec<PageMeta>,
+	/// how many half size copies every page has besides the full size one
+	pub mip_levels: u32,
+	pub layers: Vec<LayerMeta>,
+	/// page holding every palette, one per row, 256 pixels wide
+	pub palette_page: Option<usize>,
+	/// colour pages and the palette page have their colours multiplied by
+	/// their alpha in linear light, draw them with premultiplied blending
+	pub premultiplied: bool,
+}
+
+impl AtlasMeta {
+	/// where a page's mip level is stored in the bundle, level 0 is the page
+	/// itself
+	pub fn level_path(&self, data_path: &str, page: usize, level: u32) -> String {
+		let ext = self.pages[page].format.extension();
+		if level == 0 {
+			format!("{}/{}.{}", data_path, page, ext)
+		} else {
+			format!("{}/{}.{}.{}", data_path, page, level, ext)
+		}
+	}
+	pub fn from_bytes(data: &[u8]) -> RkPkResult<Self> {
+		Ok(postcard::from_bytes(data)?)
+	}
+	pub fn to_bytes(&self) -> RkPkResult<Vec<u8>> {
+		Ok(postcard::to_stdvec(self)?)
+	}
+	/// find a packed layer
+	pub fn layer(&self, group: &str, layer: &str) -> Option<&LayerMeta> {
+		self.layers
+			.iter()
+			.find(|v| v.group == group && v.layer == layer)
+	}
+}
+
+/// a single page of packed frames
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PageMeta {
+	pub size: RectWH,
+	pub format: PixelFormat,
+	/// the page holds distances to edges instead of colours, see `SdfMeta`
+	pub distance_field: bool,
+	/// set if this is a companion page of another one
+	pub companion: Option<Companion>,
+	/// page with the normal maps of this page's frames, at the same places
+	pub normal: Option<usize>,
+	/// page with the emissive maps of this page's frames, at the same places
+	pub emissive: Option<usize>,
+}
+
+impl PageMeta {
+	pub fn companion_page(&self, companion: Companion) -> Option<usize> {
+		match companion {
+			Companion::Normal => self.normal,
+			Companion::Emissive => self.emissive,
+		}
+	}
+}
+
+/// maps that go along with a frame, read from `[image].[suffix].png` and
+/// packed into pages of their own with the same placements
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Companion {
+	Normal,
+	Emissive,
+}
+
+impl Companion {
+	pub const ALL: [Companion; 2] = [Companion::Normal, Companion::Emissive];
+	pub fn suffix(self) -> &'static str {
+		match self {
+			Companion::Normal => "normal",
+			Companion::Emissive => "emissive",
+		}
+	}
+	/// what frames without this map get, a flat normal or no light
+	pub fn fill(self) -> [u8; 4] {
+		match self {
+			Companion::Normal => [0x80, 0x80, 0xFF, 0xFF],
+			Companion::Emissive => [0x00, 0x00, 0x00, 0x00],
+		}
+	}
+}
+
+/// metadata for a single packed layer
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct LayerMeta {
+	pub group: String,
+	pub layer: String,
+	/// every frame of every image in the layer
+	pub images: HashMap<String, Vec<SpriteMeta>>,
+	/// frames in this layer that reuse another frame's pixels
+	pub aliases: Vec<AliasMeta>,
+	/// named clips of each image's frames
+	pub animations: HashMap<String, HashMap<String, AnimationMeta>>,
+	/// where the palettes are if the layer is stored as palette indices
+	pub palette: Option<PaletteMeta>,
+	/// fonts in the layer, each glyph is a frame of the image with the same
+	/// name
+	pub fonts: HashMap<String, FontMeta>,
+	/// set if the layer is stored as distance fields
+	pub sdf: Option<SdfMeta>,
+	/// shape of every frame of every image traced from its alpha, only for
+	/// layers with a `[layer].collision` sidecar
+	pub collision: HashMap<String, Vec<CollisionMeta>>,
+}
+
+/// how a layer's distance fields were made
+///
+/// distances are stored as `0.5 + distance / (2 * spread)`, so the edge of a
+/// shape is at 0.5 and higher is inside
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SdfMeta {
+	/// pixels the field reaches past an edge, every frame is padded by this
+	/// much on each side
+	pub spread: ImagePos,
+	/// the edges of outlines are split between the colour channels so the
+	/// median of them keeps sharp corners, alpha has the plain distance
+	pub multi: bool,
+}
+
+/// collision shape of a frame, in pixels from the top left of the untrimmed
+/// frame. everything is empty if the frame has nothing solid in it
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CollisionMeta {
+	/// tightest rect around every solid pixel
+	pub aabb: RectXYWH,
+	/// convex hull of the solid pixels, clockwise on screen
+	pub hull: Vec<[f32; 2]>,
+	/// simplified outlines, outer edges go clockwise on screen and holes
+	/// counter clockwise
+	pub polygons: Vec<Vec<[f32; 2]>>,
+}
+
+/// a font at a single pixel size
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FontMeta {
+	/// pixels between baselines
+	pub line_height: f32,
+	/// pixels from the top of a line to the baseline
+	pub ascent: f32,
+	pub glyphs: HashMap<char, GlyphMeta>,
+	/// extra advance between pairs of characters, only pairs that aren't 0
+	#[serde(with = "kerning_pairs")]
+	pub kerning: HashMap<(char, char), f32>,
+}
+
+/// kerning as a list of pairs since json keys can't be tuples, postcard
+/// writes maps and lists the same way so bundles don't change
+mod kerning_pairs {
+	use std::collections::HashMap;
+
+	use serde::{Deserialize, Deserializer, Serializer};
+
+	type Kerning = HashMap<(char, char), f32>;
+
+	pub fn serialize<S: Serializer>(kerning: &Kerning, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_seq(kerning)
+	}
+	pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Kerning, D::Error> {
+		Ok(Vec::<((char, char), f32)>::deserialize(deserializer)?
+			.into_iter()
+			.collect())
+	}
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GlyphMeta {
+	/// frame of the font's image
+	pub frame: usize,
+	/// top left of the untrimmed frame relative to the pen on the baseline
+	pub bearing: [f32; 2],
+	/// how far the pen moves after this glyph
+	pub advance: f32,
+}
+
+/// rows of `AtlasMeta::palette_page` that belong to a layer
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PaletteMeta {
+	/// row of the layer's own colours, swaps follow right after it
+	pub row: u32,
+	/// how many palettes the layer has, including its own
+	pub rows: u32,
+}
+
+impl PaletteMeta {
+	/// row of a palette swap, 0 being the layer's own colours
+	pub fn swap(&self, index: u32) -> Option<u32> {
+		(index < self.rows).then_some(self.row + index)
+	}
+}
+
+/// what a clip does after its last frame
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoopMode {
+	/// stop on the last frame
+	Once,
+	/// start again from the first frame
+	#[default]
+	Loop,
+	/// play backwards to the first frame and then forwards again
+	PingPong,
+}
+
+/// a named sequence of frames
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnimationMeta {
+	/// frame numbers in the image, in play order
+	pub frames: Vec<usize>,
+	/// seconds each frame is shown for
+	pub durations: Vec<f32>,
+	pub mode: LoopMode,
+}
+
+/// a frame in a group
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FrameRef {
+	pub layer: String,
+	pub image: String,
+	pub frame: usize,
+}
+
+/// a frame that turned out to be a duplicate of another frame in the same group
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct AliasMeta {
+	pub image: String,
+	pub frame: usize,
+	/// the frame that actually got packed
+	pub of: FrameRef,
+	/// how the packed frame is flipped to get this one
+	pub flip: Flip,
+}
+
+/// mirroring applied to a frame
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Flip {
+	pub x: bool,
+	pub y: bool,
+}
+
+/// border insets of a stretchable frame, in pixels of the untrimmed frame
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NineSlice {
+	pub left: ImagePos,
+	pub top: ImagePos,
+	pub right: ImagePos,
+	pub bottom: ImagePos,
+}
+
+/// location of a single frame in a packed layer
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SpriteMeta {
+	/// index into `AtlasMeta::pages`
+	pub page: usize,
+	/// packed rect in the page, zero-sized if the frame was fully transparent
+	pub rect: RectXYWH,
+	/// size of the frame before trimming
+	pub size: RectWH,
+	/// offset of `rect` inside the untrimmed frame
+	pub offset: RectWH,
+	/// how the packed rect has to be flipped when drawn
+	pub flip: Flip,
+	/// borders that stay the same size when the frame is stretched
+	pub nine_slice: Option<NineSlice>,
+}
+
+#[derive(
+	Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
 pub struct RectWH {
 	pub w: ImagePos,
 	pub h: ImagePos,
@@ -119,11 +548,13 @@ impl RectWH {
 		2 * (self.w as ImageArea + self.h as ImageArea)
 	}
 	pub fn path_mul(&self) -> f64 {
-		self.max_size() as f64 / (self.min_size() as ImageArea * self.area()) as f64
+		self.max_size() as f64 / self.min_size() as f64 * self.area() as f64
 	}
 }
 
-#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(
+	Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
 pub struct RectXYWH {
 	pub x: ImagePos,
 	pub y: ImagePos,
@@ -142,3 +573,45 @@ impl RectXYWH {
 		RectWH::new(self.w, self.h)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// transparent white apart from the `opaque` pixels
+	fn image(w: ImagePos, h: ImagePos, opaque: &[(ImagePos, ImagePos)]) -> CompositeImage {
+		let mut data = [0xFF, 0xFF, 0xFF, 0].repeat(w as usize * h as usize);
+		for &(x, y) in opaque {
+			data[(y as usize * w as usize + x as usize) * 4 + 3] = 0xFF;
+		}
+		CompositeImage {
+			size: RectWH::new(w, h),
+			data,
+		}
+	}
+
+	#[test]
+	fn opaque_bounds() {
+		let whole = RectXYWH::new(0, 0, 8, 6);
+		// colours don't count, only alpha
+		assert_eq!(image(8, 6, &[]).opaque_bounds(whole), None);
+		let dots = image(8, 6, &[(2, 1), (5, 4)]);
+		assert_eq!(dots.opaque_bounds(whole), Some(RectXYWH::new(2, 1, 4, 4)));
+		// only the pixels inside uv get looked at
+		assert_eq!(
+			dots.opaque_bounds(RectXYWH::new(4, 3, 4, 3)),
+			Some(RectXYWH::new(5, 4, 1, 1))
+		);
+		assert_eq!(dots.opaque_bounds(RectXYWH::new(0, 2, 4, 4)), None);
+		// an opaque border keeps the whole frame
+		let border = (0..8)
+			.flat_map(|x| [(x, 0), (x, 5)])
+			.chain((0..6).flat_map(|y| [(0, y), (7, y)]))
+			.collect::<Vec<_>>();
+		let border = image(8, 6, &border);
+		assert_eq!(border.opaque_bounds(whole), Some(whole));
+		let half = RectXYWH::new(4, 0, 4, 6);
+		assert_eq!(border.opaque_bounds(half), Some(half));
+		assert_eq!(border.opaque_bounds(RectXYWH::new(1, 1, 6, 4)), None);
+	}
+}
@@ -0,0 +1,593 @@
+//! block compression, every block is 4x4 pixels
+
+use crate::common::{CompositeImage, RectWH};
+
+pub type Block = [[u8; 4]; 16];
+
+/// encode every block of an image, pixels past the edge repeat the last row
+/// or column
+pub fn encode(image: &CompositeImage, block_bytes: usize, f: fn(&Block, &mut Vec<u8>)) -> Vec<u8> {
+	let blocks_w = (image.size.w as usize).div_ceil(4);
+	let blocks_h = (image.size.h as usize).div_ceil(4);
+	let mut res = Vec::with_capacity(blocks_w * blocks_h * block_bytes);
+	for by in 0..blocks_h {
+		for bx in 0..blocks_w {
+			let mut block = [[0; 4]; 16];
+			for (i, pixel) in block.iter_mut().enumerate() {
+				let x = (bx * 4 + i % 4).min(image.size.w as usize - 1);
+				let y = (by * 4 + i / 4).min(image.size.h as usize - 1);
+				let start = (y * image.size.w as usize + x) * 4;
+				pixel.copy_from_slice(&image.data[start..start + 4]);
+			}
+			f(&block, &mut res);
+		}
+	}
+	res
+}
+
+/// decode every block of an image, `None` if the data is too short or has
+/// blocks that can't be decoded
+pub fn decode(
+	size: RectWH,
+	data: &[u8],
+	block_bytes: usize,
+	f: fn(&[u8]) -> Option<Block>,
+) -> Option<Vec<u8>> {
+	let blocks_w = (size.w as usize).div_ceil(4);
+	let blocks_h = (size.h as usize).div_ceil(4);
+	let mut res = vec![0; size.area() as usize * 4];
+	for by in 0..blocks_h {
+		for bx in 0..blocks_w {
+			let start = (by * blocks_w + bx) * block_bytes;
+			let block = f(data.get(start..start + block_bytes)?)?;
+			for (i, pixel) in block.iter().enumerate() {
+				let x = bx * 4 + i % 4;
+				let y = by * 4 + i / 4;
+				if x < size.w as usize && y < size.h as usize {
+					let start = (y * size.w as usize + x) * 4;
+					res[start..start + 4].copy_from_slice(pixel);
+				}
+			}
+		}
+	}
+	Some(res)
+}
+
+/// endpoints of the line through the first `channels` channels of `pixels`
+/// that fits them best
+fn fit_endpoints(pixels: &[[u8; 4]], channels: usize) -> ([f32; 4], [f32; 4]) {
+	let n = pixels.len().max(1) as f32;
+	let mut mean = [0.0; 4];
+	for pixel in pixels {
+		for c in 0..channels {
+			mean[c] += pixel[c] as f32 / n;
+		}
+	}
+	let mut cov = [[0.0f32; 4]; 4];
+	for pixel in pixels {
+		for a in 0..channels {
+			for b in 0..channels {
+				cov[a][b] += (pixel[a] as f32 - mean[a]) * (pixel[b] as f32 - mean[b]);
+			}
+		}
+	}
+	// power iteration for the principal axis, starting from the channel that
+	// changes the most. a fixed start can be square to the axis, like red
+	// going up while blue goes down
+	let widest = (0..channels)
+		.max_by(|&a, &b| cov[a][a].total_cmp(&cov[b][b]))
+		.unwrap_or(0);
+	let mut axis = cov[widest];
+	for _ in 0..8 {
+		let mut next = [0.0; 4];
+		for a in 0..channels {
+			for b in 0..channels {
+				next[a] += cov[a][b] * axis[b];
+			}
+		}
+		let len = next.iter().map(|v| v * v).sum::<f32>().sqrt();
+		if len < 1e-6 {
+			return (mean, mean);
+		}
+		axis = next.map(|v| v / len);
+	}
+	let project = |pixel: &[u8; 4]| {
+		(0..channels)
+			.map(|c| (pixel[c] as f32 - mean[c]) * axis[c])
+			.sum::<f32>()
+	};
+	let min = pixels.iter().map(project).fold(f32::MAX, f32::min);
+	let max = pixels.iter().map(project).fold(f32::MIN, f32::max);
+	let point = |t: f32| {
+		let mut res = [0.0; 4];
+		for c in 0..channels {
+			res[c] = (mean[c] + axis[c] * t).clamp(0.0, 255.0);
+		}
+		res
+	};
+	(point(min), point(max))
+}
+
+fn distance(a: &[u8; 4], b: &[u8; 4], channels: usize) -> i32 {
+	(0..channels)
+		.map(|c| (a[c] as i32 - b[c] as i32).pow(2))
+		.sum()
+}
+
+/// index of the closest palette entry to every pixel, 2 bits each
+fn color_indices(block: &Block, palette: &[[u8; 4]], channels: usize) -> Vec<usize> {
+	block
+		.iter()
+		.map(|pixel| {
+			(0..palette.len())
+				.min_by_key(|&i| distance(pixel, &palette[i], channels))
+				.unwrap_or(0)
+		})
+		.collect()
+}
+
+fn to_565(c: [f32; 4]) -> u16 {
+	let r = (c[0] * 31.0 / 255.0).round() as u16;
+	let g = (c[1] * 63.0 / 255.0).round() as u16;
+	let b = (c[2] * 31.0 / 255.0).round() as u16;
+	(r << 11) | (g << 5) | b
+}
+
+fn from_565(c: u16) -> [u8; 4] {
+	let r = (c >> 11) as u8 & 0x1F;
+	let g = (c >> 5) as u8 & 0x3F;
+	let b = c as u8 & 0x1F;
+	[
+		(r << 3) | (r >> 2),
+		(g << 2) | (g >> 4),
+		(b << 3) | (b >> 2),
+		0xFF,
+	]
+}
+
+fn mix(a: [u8; 4], b: [u8; 4], wa: u32, wb: u32) -> [u8; 4] {
+	let mut res = [0; 4];
+	for c in 0..4 {
+		res[c] = ((a[c] as u32 * wa + b[c] as u32 * wb) / (wa + wb)) as u8;
+	}
+	res
+}
+
+fn bc1_palette(c0: u16, c1: u16) -> [[u8; 4]; 4] {
+	let (a, b) = (from_565(c0), from_565(c1));
+	if c0 > c1 {
+		[a, b, mix(a, b, 2, 1), mix(a, b, 1, 2)]
+	} else {
+		[a, b, mix(a, b, 1, 1), [0; 4]]
+	}
+}
+
+/// the colour half of bc1 and bc3, pixels with low alpha only get the
+/// transparent index if `alpha` is set
+fn encode_color(block: &Block, alpha: bool, out: &mut Vec<u8>) {
+	let opaque = block
+		.iter()
+		.filter(|v| !alpha || v[3] >= 0x80)
+		.copied()
+		.collect::<Vec<_>>();
+	let transparent = opaque.len() < 16;
+	let (e0, e1) = fit_endpoints(&opaque, 3);
+	let (mut c0, mut c1) = (to_565(e0), to_565(e1));
+	// the order of the endpoints picks the mode
+	if (transparent && c0 > c1) || (!transparent && c0 < c1) {
+		std::mem::swap(&mut c0, &mut c1);
+	}
+	let palette = bc1_palette(c0, c1);
+	let colors = if transparent {
+		&palette[..3]
+	} else {
+		&palette[..]
+	};
+	let indices = color_indices(block, colors, 3);
+	let mut bits = 0u32;
+	for (i, (pixel, index)) in block.iter().zip(indices).enumerate() {
+		let index = if transparent && pixel[3] < 0x80 {
+			3
+		} else if c0 == c1 {
+			0
+		} else {
+			index
+		};
+		bits |= (index as u32) << (i * 2);
+	}
+	out.extend_from_slice(&c0.to_le_bytes());
+	out.extend_from_slice(&c1.to_le_bytes());
+	out.extend_from_slice(&bits.to_le_bytes());
+}
+
+fn decode_color(data: &[u8], alpha: bool) -> Block {
+	let c0 = u16::from_le_bytes([data[0], data[1]]);
+	let c1 = u16::from_le_bytes([data[2], data[3]]);
+	let bits = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+	let palette = if alpha {
+		bc1_palette(c0, c1)
+	} else {
+		// bc3 always uses four colours
+		let (a, b) = (from_565(c0), from_565(c1));
+		[a, b, mix(a, b, 2, 1), mix(a, b, 1, 2)]
+	};
+	let mut res = [[0; 4]; 16];
+	for (i, pixel) in res.iter_mut().enumerate() {
+		*pixel = palette[(bits >> (i * 2)) as usize & 3];
+	}
+	res
+}
+
+pub fn encode_bc1(block: &Block, out: &mut Vec<u8>) {
+	encode_color(block, true, out);
+}
+
+pub fn decode_bc1(data: &[u8]) -> Option<Block> {
+	Some(decode_color(data, true))
+}
+
+fn bc3_alpha_palette(a0: u8, a1: u8) -> [u8; 8] {
+	let (a0, a1) = (a0 as u32, a1 as u32);
+	let mut res = [0; 8];
+	res[0] = a0 as u8;
+	res[1] = a1 as u8;
+	// 6 steps between the endpoints, or 4 steps and then fully transparent and
+	// fully opaque
+	let steps = if a0 > a1 { 7 } else { 5 };
+	for (i, v) in res.iter_mut().enumerate().take(steps + 1).skip(2) {
+		let i = i as u32;
+		*v = (((steps as u32 + 1 - i) * a0 + (i - 1) * a1) / steps as u32) as u8;
+	}
+	if a0 <= a1 {
+		res[7] = 0xFF;
+	}
+	res
+}
+
+pub fn encode_bc3(block: &Block, out: &mut Vec<u8>) {
+	let a0 = block.iter().map(|v| v[3]).max().unwrap_or(0);
+	let a1 = block.iter().map(|v| v[3]).min().unwrap_or(0);
+	let palette = bc3_alpha_palette(a0, a1);
+	let mut bits = 0u64;
+	for (i, pixel) in block.iter().enumerate() {
+		let index = (0..8)
+			.min_by_key(|&j| (palette[j] as i32 - pixel[3] as i32).abs())
+			.unwrap_or(0);
+		bits |= (index as u64) << (i * 3);
+	}
+	out.push(a0);
+	out.push(a1);
+	out.extend_from_slice(&bits.to_le_bytes()[..6]);
+	encode_color(block, false, out);
+}
+
+pub fn decode_bc3(data: &[u8]) -> Option<Block> {
+	let palette = bc3_alpha_palette(data[0], data[1]);
+	let mut bits = [0; 8];
+	bits[..6].copy_from_slice(&data[2..8]);
+	let bits = u64::from_le_bytes(bits);
+	let mut res = decode_color(&data[8..], false);
+	for (i, pixel) in res.iter_mut().enumerate() {
+		pixel[3] = palette[(bits >> (i * 3)) as usize & 7];
+	}
+	Some(res)
+}
+
+const BC7_WEIGHTS: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+fn bc7_palette(e0: [u8; 4], e1: [u8; 4]) -> [[u8; 4]; 16] {
+	BC7_WEIGHTS.map(|w| {
+		let mut res = [0; 4];
+		for c in 0..4 {
+			res[c] = (((64 - w) * e0[c] as u32 + w * e1[c] as u32 + 32) >> 6) as u8;
+		}
+		res
+	})
+}
+
+/// 7 bit endpoint plus the shared p bit that gets closest to `c`
+fn bc7_endpoint(c: [f32; 4]) -> ([u8; 4], u8) {
+	(0..2)
+		.map(|p| {
+			let q = c.map(|v| ((v - p as f32) / 2.0).round().clamp(0.0, 127.0) as u8);
+			let error = (0..4)
+				.map(|i| (((q[i] << 1) | p) as f32 - c[i]).powi(2))
+				.sum::<f32>();
+			(q, p, error)
+		})
+		.min_by(|a, b| a.2.total_cmp(&b.2))
+		.map(|(q, p, _)| (q, p))
+		.unwrap()
+}
+
+/// always mode 6, one subset with rgba endpoints and 4 bit indices
+pub fn encode_bc7(block: &Block, out: &mut Vec<u8>) {
+	let (e0, e1) = fit_endpoints(block, 4);
+	let (mut q0, mut p0) = bc7_endpoint(e0);
+	let (mut q1, mut p1) = bc7_endpoint(e1);
+	let expand = |q: [u8; 4], p: u8| q.map(|v| (v << 1) | p);
+	let palette = bc7_palette(expand(q0, p0), expand(q1, p1));
+	let mut indices = color_indices(block, &palette, 4);
+	// the first index only has 3 bits, so its top bit has to be 0
+	if indices[0] >= 8 {
+		std::mem::swap(&mut q0, &mut q1);
+		std::mem::swap(&mut p0, &mut p1);
+		for index in &mut indices {
+			*index = 15 - *index;
+		}
+	}
+	let mut bits = 1u128 << 6;
+	let mut offset = 7;
+	let mut push = |value: u128, len: usize| {
+		bits |= value << offset;
+		offset += len;
+	};
+	for c in 0..4 {
+		push(q0[c] as u128, 7);
+		push(q1[c] as u128, 7);
+	}
+	push(p0 as u128, 1);
+	push(p1 as u128, 1);
+	for (i, &index) in indices.iter().enumerate() {
+		push(index as u128, if i == 0 { 3 } else { 4 });
+	}
+	out.extend_from_slice(&bits.to_le_bytes());
+}
+
+/// only decodes mode 6, which is the only one `encode_bc7` writes
+pub fn decode_bc7(data: &[u8]) -> Option<Block> {
+	let bits = u128::from_le_bytes(data.try_into().ok()?);
+	if bits & 0x7F != 1 << 6 {
+		return None;
+	}
+	let mut offset = 7;
+	let mut take = |len: usize| {
+		let value = (bits >> offset) as u32 & ((1 << len) - 1);
+		offset += len;
+		value as u8
+	};
+	// both endpoints of each channel are next to each other
+	let q = [(); 4].map(|_| [take(7), take(7)]);
+	let p = [take(1), take(1)];
+	let palette = bc7_palette(q.map(|v| (v[0] << 1) | p[0]), q.map(|v| (v[1] << 1) | p[1]));
+	let mut res = [[0; 4]; 16];
+	for (i, pixel) in res.iter_mut().enumerate() {
+		*pixel = palette[take(if i == 0 { 3 } else { 4 }) as usize];
+	}
+	Some(res)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn solid(color: [u8; 4]) -> Block {
+		[color; 16]
+	}
+
+	/// `a` in the first column to `b` in the last, every row the same
+	fn gradient(a: [u8; 4], b: [u8; 4]) -> Block {
+		let mut res = [[0; 4]; 16];
+		for (i, pixel) in res.iter_mut().enumerate() {
+			let t = (i % 4) as u32;
+			for c in 0..4 {
+				pixel[c] = ((a[c] as u32 * (3 - t) + b[c] as u32 * t) / 3) as u8;
+			}
+		}
+		res
+	}
+
+	fn round_trip(
+		block: &Block,
+		encode: fn(&Block, &mut Vec<u8>),
+		decode: fn(&[u8]) -> Option<Block>,
+		len: usize,
+	) -> (Vec<u8>, Block) {
+		let mut data = vec![];
+		encode(block, &mut data);
+		assert_eq!(data.len(), len);
+		let decoded = decode(&data).unwrap();
+		(data, decoded)
+	}
+
+	/// biggest difference in any of the first `channels` channels
+	fn max_error(a: &Block, b: &Block, channels: usize) -> u8 {
+		a.iter()
+			.zip(b)
+			.flat_map(|(a, b)| (0..channels).map(|c| a[c].abs_diff(b[c])))
+			.max()
+			.unwrap()
+	}
+
+	fn bc1_parts(data: &[u8]) -> (u16, u16, [usize; 16]) {
+		let bits = u32::from_le_bytes(data[4..8].try_into().unwrap());
+		(
+			u16::from_le_bytes([data[0], data[1]]),
+			u16::from_le_bytes([data[2], data[3]]),
+			std::array::from_fn(|i| (bits >> (i * 2)) as usize & 3),
+		)
+	}
+
+	fn bc3_alpha_indices(data: &[u8]) -> [usize; 16] {
+		let mut bits = [0; 8];
+		bits[..6].copy_from_slice(&data[2..8]);
+		let bits = u64::from_le_bytes(bits);
+		std::array::from_fn(|i| (bits >> (i * 3)) as usize & 7)
+	}
+
+	/// mode, endpoints, p bits and indices of a mode 6 block
+	fn bc7_parts(data: &[u8]) -> (u8, [[u8; 2]; 4], [u8; 2], [usize; 16]) {
+		let bits = u128::from_le_bytes(data.try_into().unwrap());
+		let field = |offset: usize, len: usize| (bits >> offset) as usize & ((1 << len) - 1);
+		let endpoints =
+			std::array::from_fn(|c| [field(7 + c * 14, 7) as u8, field(14 + c * 14, 7) as u8]);
+		let indices = std::array::from_fn(|i| match i {
+			0 => field(65, 3),
+			i => field(68 + (i - 1) * 4, 4),
+		});
+		(
+			field(0, 7) as u8,
+			endpoints,
+			[field(63, 1) as u8, field(64, 1) as u8],
+			indices,
+		)
+	}
+
+	#[test]
+	fn bc1_solid() {
+		let block = solid([200, 100, 50, 255]);
+		let (data, decoded) = round_trip(&block, encode_bc1, decode_bc1, 8);
+		let (c0, c1, indices) = bc1_parts(&data);
+		assert_eq!(c0, c1);
+		assert_eq!(c0, to_565([200.0, 100.0, 50.0, 255.0]));
+		assert_eq!(indices, [0; 16]);
+		assert!(max_error(&block, &decoded, 4) <= 4);
+	}
+
+	#[test]
+	fn bc1_gradient() {
+		let block = gradient([255, 0, 0, 255], [0, 0, 255, 255]);
+		let (data, decoded) = round_trip(&block, encode_bc1, decode_bc1, 8);
+		let (c0, c1, indices) = bc1_parts(&data);
+		// opaque blocks are always four colours
+		assert!(c0 > c1);
+		assert_eq!(indices[..4], indices[4..8]);
+		assert_eq!(indices[..4], indices[12..]);
+		// the ends of the gradient are the endpoints
+		assert!(indices[0] < 2 && indices[3] < 2 && indices[0] != indices[3]);
+		assert!(max_error(&block, &decoded, 4) <= 8);
+	}
+
+	#[test]
+	fn bc1_punch_through() {
+		let mut block = gradient([0, 255, 0, 255], [255, 255, 0, 255]);
+		for pixel in block.iter_mut().skip(8) {
+			*pixel = [0; 4];
+		}
+		block[3] = [90, 40, 10, 0x7F];
+		let (data, decoded) = round_trip(&block, encode_bc1, decode_bc1, 8);
+		let (c0, c1, indices) = bc1_parts(&data);
+		assert!(c0 <= c1);
+		for (i, (pixel, index)) in block.iter().zip(indices).enumerate() {
+			if pixel[3] < 0x80 {
+				assert_eq!(index, 3, "{}", i);
+				assert_eq!(decoded[i], [0; 4]);
+			} else {
+				assert_ne!(index, 3, "{}", i);
+				assert_eq!(decoded[i][3], 0xFF);
+				// only the middle of the two endpoints is left between them
+				assert!(max_error(&[*pixel; 16], &[decoded[i]; 16], 3) <= 48);
+			}
+		}
+	}
+
+	#[test]
+	fn bc3_solid() {
+		let block = solid([10, 20, 30, 77]);
+		let (data, decoded) = round_trip(&block, encode_bc3, decode_bc3, 16);
+		assert_eq!(data[..2], [77, 77]);
+		assert_eq!(bc3_alpha_indices(&data), [0; 16]);
+		let (c0, c1, _) = bc1_parts(&data[8..]);
+		assert_eq!(c0, c1);
+		assert!(max_error(&block, &decoded, 3) <= 4);
+		assert_eq!(
+			max_error(&block, &decoded, 4),
+			max_error(&block, &decoded, 3)
+		);
+	}
+
+	#[test]
+	fn bc3_alpha_edge() {
+		let mut block = gradient([255, 255, 255, 0], [0, 0, 0, 0]);
+		for (i, pixel) in block.iter_mut().enumerate() {
+			pixel[3] = if i % 4 < 2 { 0 } else { 0xFF };
+		}
+		let (data, decoded) = round_trip(&block, encode_bc3, decode_bc3, 16);
+		// eight alpha values between the two endpoints
+		assert_eq!(data[..2], [0xFF, 0]);
+		for (i, index) in bc3_alpha_indices(&data).into_iter().enumerate() {
+			assert_eq!(index, if i % 4 < 2 { 1 } else { 0 });
+		}
+		for (pixel, decoded) in block.iter().zip(&decoded) {
+			assert_eq!(pixel[3], decoded[3]);
+		}
+		// colour of transparent pixels is kept too
+		let (c0, c1, _) = bc1_parts(&data[8..]);
+		assert!(c0 > c1);
+		assert!(max_error(&block, &decoded, 3) <= 8);
+	}
+
+	#[test]
+	fn bc3_alpha_gradient() {
+		let mut block = solid([40, 80, 120, 0]);
+		for (i, pixel) in block.iter_mut().enumerate() {
+			pixel[3] = (i * 17) as u8;
+		}
+		let (data, decoded) = round_trip(&block, encode_bc3, decode_bc3, 16);
+		assert_eq!(data[..2], [255, 0]);
+		// half of a step between the eight values
+		assert!(block
+			.iter()
+			.zip(&decoded)
+			.all(|(a, b)| a[3].abs_diff(b[3]) <= 19));
+	}
+
+	#[test]
+	fn bc7_solid() {
+		for color in [[200, 100, 50, 255], [1, 2, 3, 4], [255; 4], [0; 4]] {
+			let block = solid(color);
+			let (data, decoded) = round_trip(&block, encode_bc7, decode_bc7, 16);
+			let (mode, endpoints, p, indices) = bc7_parts(&data);
+			assert_eq!(mode, 1 << 6);
+			assert!(endpoints.iter().all(|v| v[0] == v[1]));
+			assert_eq!(p[0], p[1]);
+			assert_eq!(indices, [0; 16]);
+			// a shared p bit can be off by one in some channels
+			assert!(max_error(&block, &decoded, 4) <= 1, "{:?}", color);
+		}
+	}
+
+	#[test]
+	fn bc7_gradient() {
+		for (a, b) in [
+			([255, 0, 0, 255], [0, 0, 255, 255]),
+			([0, 0, 255, 255], [255, 0, 0, 255]),
+			([10, 200, 30, 40], [250, 20, 130, 220]),
+		] {
+			let block = gradient(a, b);
+			let (data, decoded) = round_trip(&block, encode_bc7, decode_bc7, 16);
+			let (mode, _, _, indices) = bc7_parts(&data);
+			assert_eq!(mode, 1 << 6);
+			// the anchor index lost its top bit
+			assert!(indices[0] < 8);
+			assert_eq!(indices[..4], indices[12..]);
+			assert!(indices[0] == 0 && indices[3] == 15);
+			assert!(max_error(&block, &decoded, 4) <= 4, "{:?} {:?}", a, b);
+		}
+	}
+
+	#[test]
+	fn bc7_alpha_edge() {
+		let mut block = solid([30, 60, 90, 0]);
+		for (i, pixel) in block.iter_mut().enumerate() {
+			pixel[3] = if i / 4 < 3 { 0xFF } else { 0 };
+		}
+		let (data, decoded) = round_trip(&block, encode_bc7, decode_bc7, 16);
+		let (_, endpoints, _, indices) = bc7_parts(&data);
+		// colour stays put and alpha goes from end to end
+		assert!(endpoints[..3].iter().all(|v| v[0] == v[1]));
+		assert_eq!(endpoints[3][0].abs_diff(endpoints[3][1]), 127);
+		assert_eq!(indices[..12], [0; 12]);
+		assert_eq!(indices[12..], [15; 4]);
+		assert!(max_error(&block, &decoded, 4) <= 1);
+	}
+
+	#[test]
+	fn bc7_rejects_other_modes() {
+		let mut data = vec![];
+		encode_bc7(&solid([1; 4]), &mut data);
+		data[0] = 1 << 5;
+		assert!(decode_bc7(&data).is_none());
+		assert!(decode_bc7(&data[..15]).is_none());
+	}
+}
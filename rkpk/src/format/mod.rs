@@ -0,0 +1,334 @@
+//! pixel formats pages can be stored in
+
+use std::collections::HashMap;
+
+use image::ImageEncoder;
+use serde::{Deserialize, Serialize};
+
+use crate::common::{CompositeImage, ImagePos, RectWH, RkPkError, RkPkResult};
+
+pub mod bc;
+
+/// how a page's pixels are stored
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PixelFormat {
+	/// png encoded rgba
+	#[default]
+	Rgba8,
+	/// one byte per pixel taken from the alpha channel, for masks that get
+	/// tinted when drawn
+	R8,
+	/// two bytes per pixel, 4 bits per channel, little endian with red in the
+	/// top bits
+	Rgba4444,
+	/// 256 rgba palette entries followed by one palette index per pixel
+	Indexed,
+	/// one byte per pixel indexing into the layer's row of the atlas palette
+	/// page, pages keep the index in the red channel until they're encoded
+	Palette,
+	/// 4x4 blocks of 565 colours with 1 bit alpha, 8 bytes per block
+	Bc1,
+	/// 4x4 blocks with interpolated alpha, 16 bytes per block
+	Bc3,
+	/// 4x4 blocks, 16 bytes per block, only written as mode 6
+	Bc7,
+}
+
+impl PixelFormat {
+	/// width and height of the blocks pixels are encoded in
+	pub fn block_size(self) -> ImagePos {
+		match self {
+			Self::Bc1 | Self::Bc3 | Self::Bc7 => 4,
+			_ => 1,
+		}
+	}
+	/// file extension in the bundle
+	pub fn extension(self) -> &'static str {
+		match self {
+			Self::Rgba8 => "png",
+			_ => "bin",
+		}
+	}
+	pub fn encode(self, image: &CompositeImage) -> RkPkResult<Vec<u8>> {
+		let pixels = image.data.chunks_exact(4);
+		Ok(match self {
+			Self::Rgba8 => {
+				let mut png = vec![];
+				image::codecs::png::PngEncoder::new(&mut png).write_image(
+					&image.data,
+					image.size.w as u32,
+					image.size.h as u32,
+					image::ColorType::Rgba8,
+				)?;
+				png
+			}
+			Self::R8 => pixels.map(|v| v[3]).collect(),
+			Self::Palette => pixels.map(|v| v[0]).collect(),
+			Self::Rgba4444 => pixels
+				.flat_map(|v| {
+					let [r, g, b, a] =
+						[v[0], v[1], v[2], v[3]].map(|c| (c as u16 * 15 + 127) / 255);
+					((r << 12) | (g << 8) | (b << 4) | a).to_le_bytes()
+				})
+				.collect(),
+			Self::Indexed => encode_indexed(image),
+			Self::Bc1 => bc::encode(image, 8, bc::encode_bc1),
+			Self::Bc3 => bc::encode(image, 16, bc::encode_bc3),
+			Self::Bc7 => bc::encode(image, 16, bc::encode_bc7),
+		})
+	}
+	/// turn encoded pixels back into rgba, for when the gpu can't use the
+	/// format directly
+	pub fn decode(self, size: RectWH, data: &[u8]) -> RkPkResult<CompositeImage> {
+		let area = size.area() as usize;
+		let bad_size = |len: usize| {
+			if data.len() < len {
+				Err(RkPkError::BadPixelData(self))
+			} else {
+				Ok(())
+			}
+		};
+		let data =
+			match self {
+				Self::Rgba8 => return Ok(image::load_from_memory(data)?.to_rgba8().into()),
+				Self::R8 => {
+					bad_size(area)?;
+					data[..area]
+						.iter()
+						.flat_map(|&a| [0xFF, 0xFF, 0xFF, a])
+						.collect()
+				}
+				Self::Palette => {
+					bad_size(area)?;
+					data[..area].iter().flat_map(|&i| [i, 0, 0, 0xFF]).collect()
+				}
+				Self::Rgba4444 => {
+					bad_size(area * 2)?;
+					data.chunks_exact(2)
+						.take(area)
+						.flat_map(|v| {
+							let v = u16::from_le_bytes([v[0], v[1]]);
+							[12, 8, 4, 0].map(|shift| ((v >> shift) & 0xF) as u8 * 17)
+						})
+						.collect()
+				}
+				Self::Indexed => {
+					bad_size(256 * 4 + area)?;
+					let (palette, indices) = data.split_at(256 * 4);
+					indices[..area]
+						.iter()
+						.flat_map(|&i| {
+							let i = i as usize * 4;
+							[palette[i], palette[i + 1], palette[i + 2], palette[i + 3]]
+						})
+						.collect()
+				}
+				Self::Bc1 => bc::decode(size, data, 8, bc::decode_bc1)
+					.ok_or(RkPkError::BadPixelData(self))?,
+				Self::Bc3 => bc::decode(size, data, 16, bc::decode_bc3)
+					.ok_or(RkPkError::BadPixelData(self))?,
+				Self::Bc7 => bc::decode(size, data, 16, bc::decode_bc7)
+					.ok_or(RkPkError::BadPixelData(self))?,
+			};
+		Ok(CompositeImage { size, data })
+	}
+}
+
+/// exact palette if there's at most 256 colours, otherwise median cut and
+/// everything gets the closest colour
+fn encode_indexed(image: &CompositeImage) -> Vec<u8> {
+	let mut counts = HashMap::<[u8; 4], usize>::new();
+	for v in image.data.chunks_exact(4) {
+		*counts.entry([v[0], v[1], v[2], v[3]]).or_default() += 1;
+	}
+	let mut colors = counts.into_iter().collect::<Vec<_>>();
+	colors.sort();
+	let palette = median_cut(colors, 256);
+	let mut res = Vec::with_capacity(256 * 4 + image.data.len() / 4);
+	for i in 0..256 {
+		res.extend_from_slice(&palette.get(i).copied().unwrap_or_default());
+	}
+	let mut lookup = palette
+		.iter()
+		.enumerate()
+		.map(|(i, &v)| (v, i as u8))
+		.collect::<HashMap<_, _>>();
+	for v in image.data.chunks_exact(4) {
+		let color = [v[0], v[1], v[2], v[3]];
+		let index = *lookup.entry(color).or_insert_with(|| {
+			let distance = |p: &[u8; 4]| {
+				p.iter()
+					.zip(color)
+					.map(|(&a, b)| (a as i32 - b as i32).pow(2))
+					.sum::<i32>()
+			};
+			(0..palette.len())
+				.min_by_key(|&i| distance(&palette[i]))
+				.unwrap_or(0) as u8
+		});
+		res.push(index);
+	}
+	res
+}
+
+/// split the colours into at most `count` boxes, each one becomes the average
+/// of the colours in it
+fn median_cut(colors: Vec<([u8; 4], usize)>, count: usize) -> Vec<[u8; 4]> {
+	let range = |colors: &[([u8; 4], usize)], c: usize| {
+		let min = colors.iter().map(|v| v.0[c]).min().unwrap_or(0);
+		let max = colors.iter().map(|v| v.0[c]).max().unwrap_or(0);
+		max - min
+	};
+	let mut boxes = vec![colors];
+	while boxes.len() < count {
+		// split the box with the widest channel
+		let (i, c) = match (0..boxes.len())
+			.flat_map(|i| (0..4).map(move |c| (i, c)))
+			.filter(|&(i, _)| boxes[i].len() > 1)
+			.max_by_key(|&(i, c)| range(&boxes[i], c))
+		{
+			Some(v) => v,
+			None => break,
+		};
+		let mut colors = boxes.swap_remove(i);
+		colors.sort_by_key(|v| v.0[c]);
+		let total = colors.iter().map(|v| v.1).sum::<usize>();
+		let mut seen = 0;
+		let median = colors
+			.iter()
+			.position(|v| {
+				seen += v.1;
+				seen * 2 >= total
+			})
+			.unwrap_or(0)
+			.min(colors.len() - 2);
+		let rest = colors.split_off(median + 1);
+		boxes.push(colors);
+		boxes.push(rest);
+	}
+	boxes
+		.iter()
+		.filter(|v| !v.is_empty())
+		.map(|colors| {
+			let total = colors.iter().map(|v| v.1).sum::<usize>();
+			let mut sum = [0; 4];
+			for (color, n) in colors {
+				for (sum, &c) in sum.iter_mut().zip(color) {
+					*sum += c as usize * n;
+				}
+			}
+			sum.map(|v| ((v + total / 2) / total) as u8)
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// a `w`x`h` image with a different colour in every pixel
+	fn gradient(w: ImagePos, h: ImagePos) -> CompositeImage {
+		CompositeImage {
+			size: RectWH::new(w, h),
+			data: (0..h)
+				.flat_map(|y| (0..w).map(move |x| (x, y)))
+				.flat_map(|(x, y)| {
+					[
+						(x * 8) as u8,
+						(y * 8) as u8,
+						(x * 3 + y * 5) as u8,
+						0xFF - x as u8,
+					]
+				})
+				.collect(),
+		}
+	}
+
+	fn round_trip(format: PixelFormat, image: &CompositeImage) -> CompositeImage {
+		let data = format.encode(image).unwrap();
+		let decoded = format.decode(image.size, &data).unwrap();
+		assert_eq!(decoded.size, image.size);
+		decoded
+	}
+
+	/// largest difference of any channel between two images
+	fn max_error(a: &CompositeImage, b: &CompositeImage) -> u8 {
+		a.data
+			.iter()
+			.zip(&b.data)
+			.map(|(&a, &b)| a.abs_diff(b))
+			.max()
+			.unwrap_or(0)
+	}
+
+	#[test]
+	fn r8_keeps_alpha() {
+		let image = gradient(8, 4);
+		let decoded = round_trip(PixelFormat::R8, &image);
+		for (a, b) in image.data.chunks_exact(4).zip(decoded.data.chunks_exact(4)) {
+			assert_eq!(b, [0xFF, 0xFF, 0xFF, a[3]]);
+		}
+	}
+
+	#[test]
+	fn rgba4444_rounds_to_nearest() {
+		let image = gradient(16, 16);
+		let decoded = round_trip(PixelFormat::Rgba4444, &image);
+		// 17 apart per step, so never more than half a step off
+		assert!(max_error(&image, &decoded) <= 8);
+		// colours that are already 4 bits come back exactly
+		let exact = CompositeImage {
+			size: RectWH::new(16, 1),
+			data: (0..16u8)
+				.flat_map(|i| [i * 17, 255 - i * 17, i * 17, 0xFF])
+				.collect(),
+		};
+		assert_eq!(round_trip(PixelFormat::Rgba4444, &exact).data, exact.data);
+	}
+
+	#[test]
+	fn palette_keeps_indices() {
+		let image = CompositeImage {
+			size: RectWH::new(16, 16),
+			data: (0..=255u8).flat_map(|i| [i, 0, 0, 0xFF]).collect(),
+		};
+		assert_eq!(round_trip(PixelFormat::Palette, &image).data, image.data);
+	}
+
+	#[test]
+	fn indexed_is_exact_up_to_256_colours() {
+		let image = gradient(16, 16);
+		assert_eq!(round_trip(PixelFormat::Indexed, &image).data, image.data);
+	}
+
+	#[test]
+	fn indexed_quantizes_more_than_256_colours() {
+		let image = gradient(32, 32);
+		let data = PixelFormat::Indexed.encode(&image).unwrap();
+		assert_eq!(data.len(), 256 * 4 + 32 * 32);
+		let decoded = PixelFormat::Indexed.decode(image.size, &data).unwrap();
+		assert_ne!(decoded.data, image.data);
+		// every palette entry gets used and every pixel stays close
+		let used = data[256 * 4..]
+			.iter()
+			.collect::<std::collections::HashSet<_>>();
+		assert_eq!(used.len(), 256);
+		assert!(max_error(&image, &decoded) <= 16);
+	}
+
+	#[test]
+	fn short_data_is_an_error() {
+		let size = RectWH::new(4, 4);
+		for (format, len) in [
+			(PixelFormat::R8, 15),
+			(PixelFormat::Palette, 15),
+			(PixelFormat::Rgba4444, 31),
+			(PixelFormat::Indexed, 256 * 4 + 15),
+		] {
+			assert!(matches!(
+				format.decode(size, &vec![0; len]),
+				Err(RkPkError::BadPixelData(v)) if v == format
+			));
+		}
+	}
+}
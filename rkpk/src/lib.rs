@@ -1,6 +1,7 @@
-#![feature(label_break_value)]
 #[cfg(feature = "build")]
 pub mod build;
 pub mod common;
+pub mod format;
+pub mod rectpack2d;
 #[cfg(feature = "runtime")]
 pub mod runtime;
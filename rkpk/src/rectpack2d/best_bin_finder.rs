@@ -1,7 +1,6 @@
 use crate::common::{ImageArea, RectWH, RectXYWH};
 
-use super::empty_spaces;
-use super::finders_interface;
+use super::finders_interface::{self, PackingRoot};
 
 #[derive(Debug, Copy, Clone)]
 pub enum BinDimension {
@@ -17,8 +16,8 @@ pub enum BestPackingForOrderingResult {
 }
 
 fn best_packing_for_ordering_impl(
-	root: &mut empty_spaces::EmptySpaces,
-	ordering: &Vec<&mut RectXYWH>,
+	root: &mut dyn PackingRoot,
+	ordering: &[RectXYWH],
 	starting_bin: RectWH,
 	discard_step: finders_interface::DiscardStep,
 	tried_dimension: BinDimension,
@@ -32,7 +31,7 @@ fn best_packing_for_ordering_impl(
 		BinDimension::Both => {
 			candidate_bin.w /= 2;
 			candidate_bin.h /= 2;
-			// why the width here?
+			// bins start out square so the width works for both
 			candidate_bin.w / 2
 		}
 		BinDimension::Width => {
@@ -51,7 +50,7 @@ fn best_packing_for_ordering_impl(
 		// in c++ this is a lambda, that's stupid
 		let all_inserted = 'ch: {
 			for rect in ordering {
-				match root.insert(**rect) {
+				match root.insert(*rect) {
 					Some(_) => total_inserted_area += rect.area(),
 					None => break 'ch false,
 				}
@@ -96,8 +95,8 @@ fn best_packing_for_ordering_impl(
 }
 
 pub fn best_packing_for_ordering(
-	root: &mut empty_spaces::EmptySpaces,
-	ordering: &Vec<&mut RectXYWH>,
+	root: &mut dyn PackingRoot,
+	ordering: &[RectXYWH],
 	starting_bin: RectWH,
 	discard_step: finders_interface::DiscardStep,
 ) -> BestPackingForOrderingResult {
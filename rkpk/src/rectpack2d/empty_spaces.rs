@@ -0,0 +1,105 @@
+use crate::common::{RectWH, RectXYWH};
+
+use super::finders_interface::PackingRoot;
+use super::insert_and_split::CreatedSplits;
+
+#[derive(Debug, Clone)]
+pub struct EmptySpaces {
+	pub current_aabb: RectWH,
+	pub spaces: Vec<RectXYWH>,
+}
+
+impl EmptySpaces {
+	#[allow(clippy::new_without_default)]
+	pub fn new() -> Self {
+		Self {
+			current_aabb: RectWH::new(0, 0),
+			spaces: Vec::new(),
+		}
+	}
+	/// like `insert` but uses the smallest space that fits instead of the
+	/// newest one, which holds up better when rects get freed in random order
+	pub fn insert_best_fit(&mut self, image_rectangle: RectXYWH) -> Option<RectXYWH> {
+		let im = image_rectangle.to_wh();
+		let (i, splits) = self
+			.spaces
+			.iter()
+			.enumerate()
+			.map(|(i, &sp)| (i, CreatedSplits::new(im, sp)))
+			.filter(|(_, splits)| splits.valid())
+			.min_by_key(|&(i, _)| self.spaces[i].area())?;
+		let space = self.spaces.swap_remove(i);
+		self.spaces.extend(splits.vec());
+		let result = RectXYWH::new(space.x, space.y, im.w, im.h);
+		self.current_aabb.w = self.current_aabb.w.max(result.x + result.w);
+		self.current_aabb.h = self.current_aabb.h.max(result.y + result.h);
+		Some(result)
+	}
+	/// give a rect back, merging it with free spaces that share a whole edge
+	/// with it
+	pub fn free(&mut self, mut r: RectXYWH) {
+		if r.area() == 0 {
+			return;
+		}
+		while let Some(i) = self.spaces.iter().position(|sp| {
+			(sp.y == r.y && sp.h == r.h && (sp.x + sp.w == r.x || r.x + r.w == sp.x))
+				|| (sp.x == r.x && sp.w == r.w && (sp.y + sp.h == r.y || r.y + r.h == sp.y))
+		}) {
+			let sp = self.spaces.swap_remove(i);
+			let x = sp.x.min(r.x);
+			let y = sp.y.min(r.y);
+			r = RectXYWH::new(
+				x,
+				y,
+				(sp.x + sp.w).max(r.x + r.w) - x,
+				(sp.y + sp.h).max(r.y + r.h) - y,
+			);
+		}
+		self.spaces.push(r);
+	}
+	/// make the bin bigger without moving anything that's already in it
+	pub fn grow(&mut self, from: RectWH, to: RectWH) {
+		self.free(RectXYWH::new(from.w, 0, to.w - from.w, to.h));
+		self.free(RectXYWH::new(0, from.h, from.w, to.h - from.h));
+	}
+}
+
+impl PackingRoot for EmptySpaces {
+	fn reset(&mut self, r: RectWH) {
+		self.current_aabb = RectWH::new(0, 0);
+		self.spaces.clear();
+		self.spaces.push(RectXYWH::new(0, 0, r.w, r.h));
+	}
+	fn insert(&mut self, image_rectangle: RectXYWH) -> Option<RectXYWH> {
+		for (i, candidate_space) in self.spaces.clone().iter().enumerate().rev() {
+			let normal = CreatedSplits::new(image_rectangle.to_wh(), *candidate_space);
+			let res = if normal.valid() {
+				Option::Some(normal)
+			} else {
+				Option::None
+			};
+			let splits = match res {
+				Option::None => continue,
+				Option::Some(rect) => rect,
+			};
+			self.spaces.swap_remove(i);
+			for split in splits.vec() {
+				self.spaces.push(split);
+			}
+			// no allow_flip shit here since !allow_flipping will never call the flipping codepath
+			let result = RectXYWH::new(
+				candidate_space.x,
+				candidate_space.y,
+				image_rectangle.w,
+				image_rectangle.h,
+			);
+			self.current_aabb.w = self.current_aabb.w.max(result.x + result.w);
+			self.current_aabb.h = self.current_aabb.h.max(result.y + result.h);
+			return Some(result);
+		}
+		None
+	}
+	fn current_aabb(&self) -> RectWH {
+		self.current_aabb
+	}
+}
@@ -0,0 +1,161 @@
+use std::cmp;
+
+use crate::common::{ImagePos, RectWH, RectXYWH};
+
+use super::best_bin_finder::{best_packing_for_ordering, BestPackingForOrderingResult};
+use super::empty_spaces::EmptySpaces;
+use super::max_rects::{MaxRects, MaxRectsHeuristic};
+use super::skyline::Skyline;
+
+type Comparator = dyn Fn(RectWH, RectWH) -> cmp::Ordering;
+
+#[derive(Debug, Copy, Clone)]
+pub enum DiscardStep {
+	Tries(u16),
+	Step(u16),
+}
+
+/// places rects inside of a bin one at a time
+pub trait PackingRoot {
+	/// remove everything and start over with an empty bin
+	fn reset(&mut self, r: RectWH);
+	/// place a rect somewhere, `None` if there's no room for it
+	fn insert(&mut self, image_rectangle: RectXYWH) -> Option<RectXYWH>;
+	/// bounding box of everything placed since the last reset
+	fn current_aabb(&self) -> RectWH;
+}
+
+/// which `PackingRoot` to use
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+	/// guillotine splitting from rectpack2d
+	EmptySpaces,
+	MaxRects(MaxRectsHeuristic),
+	/// skyline bottom-left
+	Skyline,
+}
+
+impl Algorithm {
+	/// every algorithm and heuristic
+	pub const ALL: &'static [Algorithm] = &[
+		Algorithm::EmptySpaces,
+		Algorithm::MaxRects(MaxRectsHeuristic::BestShortSideFit),
+		Algorithm::MaxRects(MaxRectsHeuristic::BestAreaFit),
+		Algorithm::MaxRects(MaxRectsHeuristic::ContactPoint),
+		Algorithm::Skyline,
+	];
+	pub fn root(self) -> Box<dyn PackingRoot> {
+		match self {
+			Algorithm::EmptySpaces => Box::new(EmptySpaces::new()),
+			Algorithm::MaxRects(heuristic) => Box::new(MaxRects::new(heuristic)),
+			Algorithm::Skyline => Box::new(Skyline::new()),
+		}
+	}
+}
+
+pub fn find_best_packing(
+	root: &mut dyn PackingRoot,
+	subjects: &mut [RectXYWH],
+	start_size: ImagePos,
+	discard_step: DiscardStep,
+	comparators: &[&Comparator],
+) -> Option<RectWH> {
+	let max_bin = RectWH::new(start_size, start_size);
+	let mut best_order = None;
+	let mut best_total_inserted = 0;
+	let mut best_bin = max_bin;
+	// every order starts from the subjects as they were given, so ties don't
+	// depend on the comparators before it
+	let orders = comparators.iter().map(|comparator| {
+		let mut order = (0..subjects.len()).collect::<Vec<_>>();
+		order.sort_by(|&a, &b| comparator(subjects[a].to_wh(), subjects[b].to_wh()));
+		order
+	});
+	for order in orders {
+		let rects = order.iter().map(|&i| subjects[i]).collect::<Vec<_>>();
+		match best_packing_for_ordering(root, &rects, max_bin, discard_step) {
+			BestPackingForOrderingResult::TotalArea(total_inserted) => {
+				if best_order.is_none() && total_inserted > best_total_inserted {
+					best_order = Some(order);
+					best_total_inserted = total_inserted;
+				}
+			}
+			BestPackingForOrderingResult::Rect(result_bin) => {
+				// this will be like 0.0001% faster if i change the <= with a <
+				// that messes up the case where the smallest area is equal to the bin area
+				if result_bin.area() <= best_bin.area() {
+					best_order = Some(order);
+					best_bin = result_bin;
+				}
+			}
+		}
+	}
+	let best_order = best_order?;
+	root.reset(best_bin);
+	for i in best_order {
+		subjects[i] = root.insert(subjects[i])?;
+	}
+	Some(root.current_aabb())
+}
+
+/// like `find_best_packing`, but rects that don't fit spill over into extra
+/// bins. writes the bin of every subject into `bins` and returns the size of
+/// every bin, fails if a single rect doesn't fit in `max_size`
+pub fn find_best_packing_bins(
+	root: &mut dyn PackingRoot,
+	subjects: &mut [RectXYWH],
+	bins: &mut [usize],
+	max_size: ImagePos,
+	discard_step: DiscardStep,
+	comparators: &[&Comparator],
+) -> Option<Vec<RectWH>> {
+	let max_bin = RectWH::new(max_size, max_size);
+	let mut sizes = vec![];
+	let mut remaining = (0..subjects.len()).collect::<Vec<_>>();
+	while !remaining.is_empty() {
+		let mut rects = remaining.iter().map(|&i| subjects[i]).collect::<Vec<_>>();
+		if let Some(size) = find_best_packing(root, &mut rects, max_size, discard_step, comparators)
+		{
+			// everything left fits in a single bin
+			for (&i, rect) in remaining.iter().zip(rects) {
+				subjects[i] = rect;
+				bins[i] = sizes.len();
+			}
+			sizes.push(size);
+			break;
+		}
+		// fill up an entire bin, biggest first, and try again with the rest
+		remaining.sort_by_key(|&i| cmp::Reverse(subjects[i].area()));
+		root.reset(max_bin);
+		let mut spilled = vec![];
+		for &i in &remaining {
+			match root.insert(subjects[i]) {
+				Some(rect) => {
+					subjects[i] = rect;
+					bins[i] = sizes.len();
+				}
+				None => spilled.push(i),
+			}
+		}
+		if spilled.len() == remaining.len() {
+			return None;
+		}
+		sizes.push(root.current_aabb());
+		remaining = spilled;
+	}
+	Some(sizes)
+}
+
+/// biggest first by a few different measures, same as upstream
+pub const DEFAULT_COMPARATORS: &[&Comparator; 6] = &[
+	&|a, b| b.area().cmp(&a.area()),
+	&|a, b| b.perimeter().cmp(&a.perimeter()),
+	&|a, b| b.max_size().cmp(&a.max_size()),
+	&|a, b| b.w.cmp(&a.w),
+	&|a, b| b.h.cmp(&a.h),
+	&|a, b| {
+		b.path_mul()
+			.partial_cmp(&a.path_mul())
+			.unwrap_or(cmp::Ordering::Equal)
+	},
+];
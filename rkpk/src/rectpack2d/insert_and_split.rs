@@ -50,8 +50,8 @@ impl CreatedSplits {
 			)
 		} else {
 			Self::Two(
-				RectXYWH::new(sp.x, sp.y + im.h, im.w, free_h),
-				RectXYWH::new(sp.x + im.w, sp.y, free_w, sp.h),
+				RectXYWH::new(sp.x, sp.y + im.h, sp.w, free_h),
+				RectXYWH::new(sp.x + im.w, sp.y, free_w, im.h),
 			)
 		}
 	}
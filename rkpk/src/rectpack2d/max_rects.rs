@@ -0,0 +1,168 @@
+use crate::common::{ImageArea, ImagePos, RectWH, RectXYWH};
+
+use super::finders_interface::PackingRoot;
+
+/// how `MaxRects` picks a free rect
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MaxRectsHeuristic {
+	/// smallest leftover on the shorter side
+	BestShortSideFit,
+	/// smallest leftover area
+	BestAreaFit,
+	/// touches the most edges of the bin and other rects
+	ContactPoint,
+}
+
+/// maximal rectangles packer, keeps every free rect even if they overlap
+pub struct MaxRects {
+	pub heuristic: MaxRectsHeuristic,
+	pub current_aabb: RectWH,
+	pub bin: RectWH,
+	pub free: Vec<RectXYWH>,
+	pub used: Vec<RectXYWH>,
+}
+
+impl MaxRects {
+	pub fn new(heuristic: MaxRectsHeuristic) -> Self {
+		Self {
+			heuristic,
+			current_aabb: RectWH::new(0, 0),
+			bin: RectWH::new(0, 0),
+			free: Vec::new(),
+			used: Vec::new(),
+		}
+	}
+	/// mark `r` as used without going through a heuristic, fails if any of
+	/// it is already taken
+	pub fn occupy(&mut self, r: RectXYWH) -> bool {
+		// every free area is inside of at least one maximal free rect
+		if !self.free.iter().any(|&sp| contains(sp, r)) {
+			return false;
+		}
+		self.split(r);
+		self.used.push(r);
+		self.current_aabb.w = self.current_aabb.w.max(r.x + r.w);
+		self.current_aabb.h = self.current_aabb.h.max(r.y + r.h);
+		true
+	}
+	/// score for placing `im` in the corner of `sp`, lower is better
+	fn score(&self, im: RectWH, sp: RectXYWH) -> (i64, i64) {
+		let leftover_w = (sp.w - im.w) as i64;
+		let leftover_h = (sp.h - im.h) as i64;
+		let short = leftover_w.min(leftover_h);
+		let long = leftover_w.max(leftover_h);
+		match self.heuristic {
+			MaxRectsHeuristic::BestShortSideFit => (short, long),
+			MaxRectsHeuristic::BestAreaFit => (sp.area() as i64 - im.area() as i64, short),
+			MaxRectsHeuristic::ContactPoint => (
+				-(self.contact(RectXYWH::new(sp.x, sp.y, im.w, im.h)) as i64),
+				0,
+			),
+		}
+	}
+	/// length of `r`'s edges that touch the bin or another used rect
+	fn contact(&self, r: RectXYWH) -> ImageArea {
+		let mut res = 0;
+		if r.x == 0 || r.x + r.w == self.bin.w {
+			res += r.h as ImageArea;
+		}
+		if r.y == 0 || r.y + r.h == self.bin.h {
+			res += r.w as ImageArea;
+		}
+		for used in &self.used {
+			if used.x == r.x + r.w || used.x + used.w == r.x {
+				res += overlap(used.y, used.h, r.y, r.h);
+			}
+			if used.y == r.y + r.h || used.y + used.h == r.y {
+				res += overlap(used.x, used.w, r.x, r.w);
+			}
+		}
+		res
+	}
+	/// cut `placed` out of every free rect it overlaps
+	fn split(&mut self, placed: RectXYWH) {
+		let mut new_free = vec![];
+		self.free.retain(|sp| {
+			if !intersects(*sp, placed) {
+				return true;
+			}
+			if placed.x > sp.x {
+				new_free.push(RectXYWH::new(sp.x, sp.y, placed.x - sp.x, sp.h));
+			}
+			if placed.x + placed.w < sp.x + sp.w {
+				let x = placed.x + placed.w;
+				new_free.push(RectXYWH::new(x, sp.y, sp.x + sp.w - x, sp.h));
+			}
+			if placed.y > sp.y {
+				new_free.push(RectXYWH::new(sp.x, sp.y, sp.w, placed.y - sp.y));
+			}
+			if placed.y + placed.h < sp.y + sp.h {
+				let y = placed.y + placed.h;
+				new_free.push(RectXYWH::new(sp.x, y, sp.w, sp.y + sp.h - y));
+			}
+			false
+		});
+		self.free.append(&mut new_free);
+		// free rects that are inside of other free rects are redundant
+		let mut i = 0;
+		while i < self.free.len() {
+			let a = self.free[i];
+			let redundant = self
+				.free
+				.iter()
+				.enumerate()
+				.any(|(j, &b)| j != i && contains(b, a) && (a != b || j < i));
+			if redundant {
+				self.free.swap_remove(i);
+			} else {
+				i += 1;
+			}
+		}
+	}
+}
+
+impl PackingRoot for MaxRects {
+	fn reset(&mut self, r: RectWH) {
+		self.current_aabb = RectWH::new(0, 0);
+		self.bin = r;
+		self.free.clear();
+		self.free.push(RectXYWH::new(0, 0, r.w, r.h));
+		self.used.clear();
+	}
+	fn insert(&mut self, image_rectangle: RectXYWH) -> Option<RectXYWH> {
+		let im = image_rectangle.to_wh();
+		let best = self
+			.free
+			.iter()
+			.filter(|sp| im.w <= sp.w && im.h <= sp.h)
+			.min_by_key(|&&sp| (self.score(im, sp), sp.y, sp.x))?;
+		let result = RectXYWH::new(best.x, best.y, im.w, im.h);
+		self.split(result);
+		self.used.push(result);
+		self.current_aabb.w = self.current_aabb.w.max(result.x + result.w);
+		self.current_aabb.h = self.current_aabb.h.max(result.y + result.h);
+		Some(result)
+	}
+	fn current_aabb(&self) -> RectWH {
+		self.current_aabb
+	}
+}
+
+fn intersects(a: RectXYWH, b: RectXYWH) -> bool {
+	a.x < b.x + b.w && b.x < a.x + a.w && a.y < b.y + b.h && b.y < a.y + a.h
+}
+
+/// whether `outer` fully contains `inner`
+fn contains(outer: RectXYWH, inner: RectXYWH) -> bool {
+	inner.x >= outer.x
+		&& inner.y >= outer.y
+		&& inner.x + inner.w <= outer.x + outer.w
+		&& inner.y + inner.h <= outer.y + outer.h
+}
+
+/// length of the overlap between two 1d spans
+fn overlap(a: ImagePos, a_len: ImagePos, b: ImagePos, b_len: ImagePos) -> ImageArea {
+	let start = a.max(b);
+	let end = (a + a_len).min(b + b_len);
+	end.saturating_sub(start) as ImageArea
+}
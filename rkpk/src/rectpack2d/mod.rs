@@ -2,5 +2,7 @@ pub mod best_bin_finder;
 pub mod empty_spaces;
 pub mod finders_interface;
 pub mod insert_and_split;
+pub mod max_rects;
+pub mod skyline;
 
 pub use finders_interface::*;
@@ -0,0 +1,111 @@
+use crate::common::{ImagePos, RectWH, RectXYWH};
+
+use super::finders_interface::PackingRoot;
+
+/// a horizontal segment of the skyline
+#[derive(Debug, Copy, Clone)]
+pub struct SkylineNode {
+	pub x: ImagePos,
+	pub y: ImagePos,
+	pub w: ImagePos,
+}
+
+/// skyline bottom-left packer, only keeps track of the top edge of whatever's
+/// been placed so it's fast but leaves holes under tall rects
+pub struct Skyline {
+	pub current_aabb: RectWH,
+	pub bin: RectWH,
+	pub nodes: Vec<SkylineNode>,
+}
+
+impl Skyline {
+	#[allow(clippy::new_without_default)]
+	pub fn new() -> Self {
+		Self {
+			current_aabb: RectWH::new(0, 0),
+			bin: RectWH::new(0, 0),
+			nodes: Vec::new(),
+		}
+	}
+	/// lowest y a rect starting at node `i` can go, `None` if it doesn't fit
+	fn fits(&self, i: usize, im: RectWH) -> Option<ImagePos> {
+		let x = self.nodes[i].x;
+		if x as u32 + im.w as u32 > self.bin.w as u32 {
+			return None;
+		}
+		let mut y = 0;
+		let mut width_left = im.w as i32;
+		for node in &self.nodes[i..] {
+			if width_left <= 0 {
+				break;
+			}
+			y = y.max(node.y);
+			width_left -= node.w as i32;
+		}
+		if y as u32 + im.h as u32 > self.bin.h as u32 {
+			None
+		} else {
+			Some(y)
+		}
+	}
+	/// raise the skyline under a newly placed rect
+	fn add_level(&mut self, i: usize, rect: RectXYWH) {
+		self.nodes.insert(
+			i,
+			SkylineNode {
+				x: rect.x,
+				y: rect.y + rect.h,
+				w: rect.w,
+			},
+		);
+		// shrink or remove the nodes that are now covered
+		let end = rect.x + rect.w;
+		while i + 1 < self.nodes.len() {
+			let next = &mut self.nodes[i + 1];
+			if next.x >= end {
+				break;
+			}
+			let shrink = end - next.x;
+			if shrink < next.w {
+				next.x += shrink;
+				next.w -= shrink;
+				break;
+			}
+			self.nodes.remove(i + 1);
+		}
+		// merge neighbours at the same height
+		let mut j = 0;
+		while j + 1 < self.nodes.len() {
+			if self.nodes[j].y == self.nodes[j + 1].y {
+				self.nodes[j].w += self.nodes[j + 1].w;
+				self.nodes.remove(j + 1);
+			} else {
+				j += 1;
+			}
+		}
+	}
+}
+
+impl PackingRoot for Skyline {
+	fn reset(&mut self, r: RectWH) {
+		self.current_aabb = RectWH::new(0, 0);
+		self.bin = r;
+		self.nodes.clear();
+		self.nodes.push(SkylineNode { x: 0, y: 0, w: r.w });
+	}
+	fn insert(&mut self, image_rectangle: RectXYWH) -> Option<RectXYWH> {
+		let im = image_rectangle.to_wh();
+		// lowest bottom edge, then narrowest node
+		let (i, y) = (0..self.nodes.len())
+			.filter_map(|i| Some((i, self.fits(i, im)?)))
+			.min_by_key(|&(i, y)| (y as u32 + im.h as u32, self.nodes[i].w))?;
+		let result = RectXYWH::new(self.nodes[i].x, y, im.w, im.h);
+		self.add_level(i, result);
+		self.current_aabb.w = self.current_aabb.w.max(result.x + result.w);
+		self.current_aabb.h = self.current_aabb.h.max(result.y + result.h);
+		Some(result)
+	}
+	fn current_aabb(&self) -> RectWH {
+		self.current_aabb
+	}
+}
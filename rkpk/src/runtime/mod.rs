@@ -1 +1,664 @@
+//! loading packed atlases at runtime
 
+use crate::common::{
+	AnimationMeta, AtlasMeta, CollisionMeta, FontMeta, ImagePos, LayerMeta, LoopMode, RectWH,
+	RkPkResult, SdfMeta, SpriteMeta,
+};
+
+/// packed atlas metadata
+#[derive(Debug, Default, Clone)]
+pub struct Atlas {
+	pub meta: AtlasMeta,
+}
+
+impl Atlas {
+	/// load the metadata saved by `Packer::save_build_info`
+	pub fn from_bytes(data: &[u8]) -> RkPkResult<Self> {
+		Ok(Self {
+			meta: AtlasMeta::from_bytes(data)?,
+		})
+	}
+	pub fn layer(&self, group: &str, layer: &str) -> Option<&LayerMeta> {
+		self.meta.layer(group, layer)
+	}
+	pub fn sprite(
+		&self,
+		group: &str,
+		layer: &str,
+		image: &str,
+		frame: usize,
+	) -> Option<&SpriteMeta> {
+		self.layer(group, layer)?.images.get(image)?.get(frame)
+	}
+	pub fn animation(
+		&self,
+		group: &str,
+		layer: &str,
+		image: &str,
+		name: &str,
+	) -> Option<&AnimationMeta> {
+		self.layer(group, layer)?.animations.get(image)?.get(name)
+	}
+	/// collision shape of a frame, only layers packed with a
+	/// `[layer].collision` sidecar have them
+	pub fn collision(
+		&self,
+		group: &str,
+		layer: &str,
+		image: &str,
+		frame: usize,
+	) -> Option<&CollisionMeta> {
+		self.layer(group, layer)?.collision.get(image)?.get(frame)
+	}
+	/// a font packed from `[font].[ext]`, ttf/otf fonts are named
+	/// `[font].[size]`
+	pub fn font(&self, group: &str, layer: &str, name: &str) -> Option<&FontMeta> {
+		self.layer(group, layer)?.fonts.get(name)
+	}
+}
+
+impl SdfMeta {
+	/// origin of a padded frame that lands on the same spot `origin` would
+	/// on the frame without its padding
+	pub fn origin(&self, sprite: &SpriteMeta, origin: [f32; 2]) -> [f32; 2] {
+		let spread = self.spread as f32;
+		let padded = [sprite.size.w as f32, sprite.size.h as f32];
+		[0, 1].map(|i| {
+			if padded[i] == 0.0 {
+				origin[i]
+			} else {
+				(origin[i] * (padded[i] - spread * 2.0) + spread) / padded[i]
+			}
+		})
+	}
+}
+
+impl FontMeta {
+	/// frame and top left corner of every glyph in `text`, relative to the
+	/// top left of the first line
+	///
+	/// the pen stays on whole pixels so glyphs aren't sampled between texels
+	pub fn layout(&self, text: &str, scale: f32) -> Vec<(usize, [f32; 2])> {
+		let mut res = vec![];
+		let (mut x, mut y) = (0.0, self.ascent * scale);
+		let mut prev = None;
+		for c in text.chars() {
+			if c == '\n' {
+				x = 0.0;
+				y += self.line_height * scale;
+				prev = None;
+				continue;
+			}
+			let glyph = match self.glyphs.get(&c) {
+				Some(v) => v,
+				None => continue,
+			};
+			if let Some(prev) = prev {
+				x += self.kerning.get(&(prev, c)).copied().unwrap_or(0.0) * scale;
+			}
+			res.push((
+				glyph.frame,
+				[
+					(x + glyph.bearing[0] * scale).round(),
+					(y + glyph.bearing[1] * scale).round(),
+				],
+			));
+			x += glyph.advance * scale;
+			prev = Some(c);
+		}
+		res
+	}
+	/// width of the widest line and height of all lines of `text`
+	pub fn measure(&self, text: &str, scale: f32) -> [f32; 2] {
+		let width = text
+			.lines()
+			.map(|line| {
+				let mut prev = None;
+				line.chars()
+					.filter_map(|c| {
+						let glyph = self.glyphs.get(&c)?;
+						let kern = prev
+							.and_then(|prev| self.kerning.get(&(prev, c)))
+							.copied()
+							.unwrap_or(0.0);
+						prev = Some(c);
+						Some(glyph.advance + kern)
+					})
+					.sum::<f32>()
+			})
+			.fold(0.0, f32::max);
+		let lines = text.split('\n').count();
+		[width * scale, lines as f32 * self.line_height * scale]
+	}
+}
+
+impl AnimationMeta {
+	/// order `frames` gets stepped through in one cycle
+	fn steps(&self) -> impl Iterator<Item = usize> + Clone {
+		let len = self.frames.len();
+		let back = if self.mode == LoopMode::PingPong {
+			1..len.saturating_sub(1)
+		} else {
+			0..0
+		};
+		(0..len).chain(back.rev())
+	}
+	fn duration(&self, step: usize) -> f64 {
+		self.durations.get(step).copied().unwrap_or(0.0) as f64
+	}
+	/// seconds one cycle takes, ping-pong clips count both directions
+	pub fn length(&self) -> f64 {
+		self.steps().map(|v| self.duration(v)).sum()
+	}
+	/// whether a clip that doesn't loop has reached its end
+	pub fn finished(&self, time: f64) -> bool {
+		self.mode == LoopMode::Once && time >= self.length()
+	}
+	/// frame number shown `time` seconds after the clip started
+	pub fn frame_at(&self, time: f64) -> Option<usize> {
+		let length = self.length();
+		if length <= 0.0 {
+			// nothing to play through, so it sits on the last frame whatever the mode
+			return self.frames.last().copied();
+		}
+		let mut time = match self.mode {
+			LoopMode::Once => time.min(length),
+			LoopMode::Loop | LoopMode::PingPong => time.rem_euclid(length),
+		};
+		let mut last = None;
+		for step in self.steps() {
+			let duration = self.duration(step);
+			if time < duration {
+				return self.frames.get(step).copied();
+			}
+			time -= duration;
+			last = Some(step);
+		}
+		self.frames.get(last?).copied()
+	}
+}
+
+impl CollisionMeta {
+	/// hull and polygons with `origin`, as a fraction of the frame's `size`,
+	/// moved to 0,0 and then scaled by `scale`. a negative scale on one axis
+	/// mirrors them, which also swaps which way they wind
+	pub fn transformed(
+		&self,
+		size: RectWH,
+		origin: [f32; 2],
+		scale: [f32; 2],
+	) -> (Vec<[f32; 2]>, Vec<Vec<[f32; 2]>>) {
+		let origin = [size.w as f32 * origin[0], size.h as f32 * origin[1]];
+		let point = |v: &[f32; 2]| [(v[0] - origin[0]) * scale[0], (v[1] - origin[1]) * scale[1]];
+		(
+			self.hull.iter().map(point).collect(),
+			self.polygons
+				.iter()
+				.map(|v| v.iter().map(point).collect())
+				.collect(),
+		)
+	}
+}
+
+impl SpriteMeta {
+	/// uv corners of the packed rect in layer pixels, clockwise from the top
+	/// left, with flipping applied
+	pub fn uv(&self) -> [[f32; 2]; 4] {
+		let mut x0 = self.rect.x as f32;
+		let mut y0 = self.rect.y as f32;
+		let mut x1 = x0 + self.rect.w as f32;
+		let mut y1 = y0 + self.rect.h as f32;
+		if self.flip.x {
+			std::mem::swap(&mut x0, &mut x1);
+		}
+		if self.flip.y {
+			std::mem::swap(&mut y0, &mut y1);
+		}
+		[[x0, y0], [x1, y0], [x1, y1], [x0, y1]]
+	}
+	/// origin and size of the trimmed quad so that it lands exactly where the
+	/// untrimmed frame would with the given origin and scale
+	pub fn trimmed_quad(&self, origin: [f32; 2], scale: [f32; 2]) -> ([f32; 2], [f32; 2]) {
+		let size = [self.rect.w as f32 * scale[0], self.rect.h as f32 * scale[1]];
+		let full = [self.size.w as f32 * scale[0], self.size.h as f32 * scale[1]];
+		let offset = [
+			self.offset.w as f32 * scale[0],
+			self.offset.h as f32 * scale[1],
+		];
+		let axis = |i: usize| {
+			if size[i] == 0.0 {
+				0.0
+			} else {
+				(full[i] * origin[i] - offset[i]) / size[i]
+			}
+		};
+		([axis(0), axis(1)], size)
+	}
+	/// cut the frame into the quads needed to cover `size`, borders from
+	/// `nine_slice` get scaled by `scale` and rounded to whole pixels so they
+	/// stay crisp, everything between them stretches. frames without insets
+	/// come out as a single stretched quad
+	pub fn nine_slice_quads(&self, size: [f32; 2], scale: f32) -> Vec<NineSliceQuad> {
+		let nine = self.nine_slice.unwrap_or_default();
+		let xs = nine_slice_axis(
+			[self.size.w, nine.left, nine.right],
+			[self.offset.w, self.rect.x, self.rect.w],
+			self.flip.x,
+			size[0],
+			scale,
+		);
+		let ys = nine_slice_axis(
+			[self.size.h, nine.top, nine.bottom],
+			[self.offset.h, self.rect.y, self.rect.h],
+			self.flip.y,
+			size[1],
+			scale,
+		);
+		let mut res = Vec::with_capacity(xs.len() * ys.len());
+		for y in &ys {
+			for x in &xs {
+				res.push(NineSliceQuad {
+					position: [x[0], y[0]],
+					size: [x[1] - x[0], y[1] - y[0]],
+					uv: [[x[2], y[2]], [x[3], y[2]], [x[3], y[3]], [x[2], y[3]]],
+				});
+			}
+		}
+		res
+	}
+}
+
+/// a piece of a stretched frame, relative to the top left of the target
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct NineSliceQuad {
+	pub position: [f32; 2],
+	pub size: [f32; 2],
+	/// uv corners in layer pixels, same order as `SpriteMeta::uv`
+	pub uv: [[f32; 2]; 4],
+}
+
+/// spans of one axis as `[start, end, uv start, uv end]`, skipping anything
+/// that was trimmed away or squashed to nothing
+fn nine_slice_axis(
+	[full, low, high]: [ImagePos; 3],
+	[offset, start, len]: [ImagePos; 3],
+	flip: bool,
+	target: f32,
+	scale: f32,
+) -> Vec<[f32; 4]> {
+	let full = full as f32;
+	let low = (low as f32).min(full);
+	let high = (high as f32).min(full - low);
+	// borders shrink evenly if they don't fit
+	let mut low_size = (low * scale).round();
+	let mut high_size = (high * scale).round();
+	if low_size + high_size > target {
+		let fit = target / (low_size + high_size);
+		low_size = (low_size * fit).round();
+		high_size = target - low_size;
+	}
+	let source = [0.0, low, full - high, full];
+	let dest = [0.0, low_size, target - high_size, target];
+	let (offset, start, len) = (offset as f32, start as f32, len as f32);
+	let uv = |v: f32| {
+		if flip {
+			start + len - (v - offset)
+		} else {
+			start + (v - offset)
+		}
+	};
+	let mut res = vec![];
+	for i in 0..3 {
+		let (s0, s1, d0, d1) = (source[i], source[i + 1], dest[i], dest[i + 1]);
+		// only the part that survived trimming gets drawn
+		let c0 = s0.max(offset);
+		let c1 = s1.min(offset + len);
+		if c1 <= c0 || d1 <= d0 {
+			continue;
+		}
+		let d = |c: f32| d0 + (c - s0) * (d1 - d0) / (s1 - s0);
+		res.push([d(c0), d(c1), uv(c0), uv(c1)]);
+	}
+	res
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::common::{Flip, NineSlice, RectXYWH};
+
+	/// frames 10, 11, 12 shown for 1, 2 and 3 seconds
+	fn clip(mode: LoopMode) -> AnimationMeta {
+		AnimationMeta {
+			frames: vec![10, 11, 12],
+			durations: vec![1.0, 2.0, 3.0],
+			mode,
+		}
+	}
+
+	fn frames(clip: &AnimationMeta, times: &[f64]) -> Vec<Option<usize>> {
+		times.iter().map(|&v| clip.frame_at(v)).collect()
+	}
+
+	#[test]
+	fn loop_wraps_around() {
+		let clip = clip(LoopMode::Loop);
+		assert_eq!(clip.length(), 6.0);
+		assert_eq!(
+			frames(&clip, &[0.0, 0.5, 1.0, 2.9, 3.0, 5.9, 6.0, 6.5, 13.0, -0.5]),
+			[10, 10, 11, 11, 12, 12, 10, 10, 11, 12].map(Some)
+		);
+		assert!(!clip.finished(100.0));
+	}
+
+	#[test]
+	fn ping_pong_turns_around_once() {
+		let clip = clip(LoopMode::PingPong);
+		// 10, 11, 12 and back through 11, neither end gets shown twice
+		assert_eq!(clip.length(), 8.0);
+		assert_eq!(
+			frames(&clip, &[0.0, 1.0, 3.0, 5.9, 6.0, 7.9, 8.0, 9.0, 14.0]),
+			[10, 11, 12, 12, 11, 11, 10, 11, 11].map(Some)
+		);
+		assert!(!clip.finished(100.0));
+		// too short to have a middle
+		let two = AnimationMeta {
+			frames: vec![1, 2],
+			durations: vec![1.0, 1.0],
+			mode: LoopMode::PingPong,
+		};
+		assert_eq!(two.length(), 2.0);
+		assert_eq!(frames(&two, &[0.5, 1.5, 2.5]), [1, 2, 1].map(Some));
+		let one = AnimationMeta {
+			frames: vec![7],
+			durations: vec![1.0],
+			mode: LoopMode::PingPong,
+		};
+		assert_eq!(one.length(), 1.0);
+		assert_eq!(frames(&one, &[0.0, 1.5]), [7, 7].map(Some));
+	}
+
+	#[test]
+	fn once_stops_on_the_last_frame() {
+		let clip = clip(LoopMode::Once);
+		assert_eq!(clip.length(), 6.0);
+		assert_eq!(
+			frames(&clip, &[-1.0, 0.0, 1.0, 5.9, 6.0, 100.0]),
+			[10, 10, 11, 12, 12, 12].map(Some)
+		);
+		assert!(!clip.finished(5.9));
+		assert!(clip.finished(6.0));
+		assert!(clip.finished(100.0));
+	}
+
+	#[test]
+	fn zero_length() {
+		for mode in [LoopMode::Once, LoopMode::Loop, LoopMode::PingPong] {
+			let empty = AnimationMeta {
+				mode,
+				..Default::default()
+			};
+			assert_eq!(empty.length(), 0.0);
+			assert_eq!(empty.frame_at(0.0), None);
+			assert_eq!(empty.frame_at(3.0), None);
+			// every frame takes no time, so the last one is all there is to see
+			let instant = AnimationMeta {
+				frames: vec![1, 2, 3],
+				durations: vec![0.0; 3],
+				mode,
+			};
+			assert_eq!(instant.length(), 0.0);
+			assert_eq!(frames(&instant, &[0.0, 3.0]), [Some(3), Some(3)]);
+			assert_eq!(instant.finished(0.0), mode == LoopMode::Once);
+		}
+		// missing durations count as zero
+		let short = AnimationMeta {
+			frames: vec![1, 2, 3],
+			durations: vec![1.0],
+			mode: LoopMode::Loop,
+		};
+		assert_eq!(short.length(), 1.0);
+		assert_eq!(frames(&short, &[0.5, 1.5]), [Some(1), Some(1)]);
+	}
+
+	/// a 10x8 frame trimmed down to the 4x5 at 3,2, packed at 20,30
+	fn trimmed() -> SpriteMeta {
+		SpriteMeta {
+			rect: RectXYWH::new(20, 30, 4, 5),
+			size: RectWH::new(10, 8),
+			offset: RectWH::new(3, 2),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn trimmed_quads_cover_what_was_left() {
+		let sprite = trimmed();
+		let position = [100.0, 50.0];
+		for origin in [[0.0, 0.0], [0.5, 0.5], [1.0, 0.25]] {
+			for scale in [[1.0, 1.0], [3.0, 2.0], [-1.0, 1.0], [2.0, -0.5]] {
+				let (quad_origin, size) = sprite.trimmed_quad(origin, scale);
+				assert_eq!(size, [4.0 * scale[0], 5.0 * scale[1]]);
+				for axis in 0..2 {
+					// the untrimmed frame drawn at the same place, and where
+					// the pixels that were kept are inside of it
+					let frame = position[axis] - origin[axis] * [10.0, 8.0][axis] * scale[axis];
+					let kept = frame + [3.0, 2.0][axis] * scale[axis];
+					let quad = position[axis] - quad_origin[axis] * size[axis];
+					assert!(
+						(quad - kept).abs() < 1e-4,
+						"{:?} {:?}: {} isn't {}",
+						origin,
+						scale,
+						quad,
+						kept
+					);
+				}
+			}
+		}
+		// frames that weren't trimmed keep their origin
+		let whole = SpriteMeta {
+			rect: RectXYWH::new(0, 0, 10, 8),
+			size: RectWH::new(10, 8),
+			..Default::default()
+		};
+		assert_eq!(
+			whole.trimmed_quad([0.25, 0.75], [2.0, 2.0]),
+			([0.25, 0.75], [20.0, 16.0])
+		);
+		// and fully transparent ones have nothing to draw
+		let empty = SpriteMeta {
+			size: RectWH::new(10, 8),
+			..Default::default()
+		};
+		assert_eq!(
+			empty.trimmed_quad([0.5, 0.5], [1.0, 1.0]),
+			([0.0, 0.0], [0.0, 0.0])
+		);
+	}
+
+	/// a 12x10 frame packed at 100,200 with borders of 3, 2, 4 and 3
+	fn nine() -> SpriteMeta {
+		SpriteMeta {
+			rect: RectXYWH::new(100, 200, 12, 10),
+			size: RectWH::new(12, 10),
+			nine_slice: Some(NineSlice {
+				left: 3,
+				top: 2,
+				right: 4,
+				bottom: 3,
+			}),
+			..Default::default()
+		}
+	}
+
+	/// the quads cover `[x0, y0, x1, y1]` exactly once and nothing else
+	fn assert_tiles(quads: &[NineSliceQuad], [x0, y0, x1, y1]: [f32; 4]) {
+		let end = |v: &NineSliceQuad| [v.position[0] + v.size[0], v.position[1] + v.size[1]];
+		let mut area = 0.0;
+		for (i, a) in quads.iter().enumerate() {
+			assert!(a.size[0] > 0.0 && a.size[1] > 0.0, "{:?}", a);
+			assert!(
+				a.position[0] >= x0 && a.position[1] >= y0 && end(a)[0] <= x1 && end(a)[1] <= y1,
+				"{:?} is outside of {:?}",
+				a,
+				[x0, y0, x1, y1]
+			);
+			for b in &quads[..i] {
+				let apart = (0..2).any(|axis| {
+					a.position[axis] >= end(b)[axis] || b.position[axis] >= end(a)[axis]
+				});
+				assert!(apart, "{:?} and {:?} overlap", a, b);
+			}
+			area += a.size[0] * a.size[1];
+		}
+		assert!((area - (x1 - x0) * (y1 - y0)).abs() < 1e-3, "gaps left");
+	}
+
+	/// `[start, end, uv start, uv end]` of every column, from the top row
+	fn columns(quads: &[NineSliceQuad]) -> Vec<[f32; 4]> {
+		let top = quads[0].position[1];
+		quads
+			.iter()
+			.filter(|v| v.position[1] == top)
+			.map(|v| {
+				[
+					v.position[0],
+					v.position[0] + v.size[0],
+					v.uv[0][0],
+					v.uv[1][0],
+				]
+			})
+			.collect()
+	}
+
+	/// `columns` going down
+	fn rows(quads: &[NineSliceQuad]) -> Vec<[f32; 4]> {
+		let left = quads[0].position[0];
+		quads
+			.iter()
+			.filter(|v| v.position[0] == left)
+			.map(|v| {
+				[
+					v.position[1],
+					v.position[1] + v.size[1],
+					v.uv[0][1],
+					v.uv[3][1],
+				]
+			})
+			.collect()
+	}
+
+	#[test]
+	fn nine_slice_stretches_the_middle() {
+		let quads = nine().nine_slice_quads([30.0, 20.0], 1.0);
+		assert_eq!(quads.len(), 9);
+		assert_tiles(&quads, [0.0, 0.0, 30.0, 20.0]);
+		assert_eq!(
+			columns(&quads),
+			[
+				[0.0, 3.0, 100.0, 103.0],
+				[3.0, 26.0, 103.0, 108.0],
+				[26.0, 30.0, 108.0, 112.0],
+			]
+		);
+		assert_eq!(
+			rows(&quads),
+			[
+				[0.0, 2.0, 200.0, 202.0],
+				[2.0, 17.0, 202.0, 207.0],
+				[17.0, 20.0, 207.0, 210.0],
+			]
+		);
+		// borders scale, and the middle takes whatever is left
+		let quads = nine().nine_slice_quads([30.0, 20.0], 2.0);
+		assert_tiles(&quads, [0.0, 0.0, 30.0, 20.0]);
+		assert_eq!(columns(&quads)[1][..2], [6.0, 22.0]);
+		assert_eq!(rows(&quads)[1][..2], [4.0, 14.0]);
+		// without borders it's one stretched quad
+		let plain = SpriteMeta {
+			nine_slice: None,
+			..nine()
+		};
+		let quads = plain.nine_slice_quads([30.0, 20.0], 1.0);
+		assert_eq!(quads.len(), 1);
+		assert_eq!(quads[0].uv, plain.uv());
+		assert_tiles(&quads, [0.0, 0.0, 30.0, 20.0]);
+	}
+
+	#[test]
+	fn nine_slice_borders_shrink_to_fit() {
+		// 3 and 4 don't fit in 5, so they shrink evenly and the middle goes
+		let quads = nine().nine_slice_quads([5.0, 20.0], 1.0);
+		assert_eq!(quads.len(), 6);
+		assert_tiles(&quads, [0.0, 0.0, 5.0, 20.0]);
+		assert_eq!(
+			columns(&quads),
+			[[0.0, 2.0, 100.0, 103.0], [2.0, 5.0, 108.0, 112.0]]
+		);
+		// and all the way down to nothing
+		assert!(nine().nine_slice_quads([0.0, 20.0], 1.0).is_empty());
+		let quads = nine().nine_slice_quads([1.0, 1.0], 1.0);
+		assert_tiles(&quads, [0.0, 0.0, 1.0, 1.0]);
+	}
+
+	/// `nine` with everything but the 8x7 at 2,1 trimmed away
+	fn trimmed_nine() -> SpriteMeta {
+		SpriteMeta {
+			rect: RectXYWH::new(100, 200, 8, 7),
+			offset: RectWH::new(2, 1),
+			..nine()
+		}
+	}
+
+	#[test]
+	fn nine_slice_of_trimmed_frames() {
+		let quads = trimmed_nine().nine_slice_quads([30.0, 20.0], 1.0);
+		// the kept pixels cover the same part of every slice they did before
+		assert_tiles(&quads, [2.0, 1.0, 28.0, 18.0]);
+		assert_eq!(
+			columns(&quads),
+			[
+				[2.0, 3.0, 100.0, 101.0],
+				[3.0, 26.0, 101.0, 106.0],
+				[26.0, 28.0, 106.0, 108.0],
+			]
+		);
+		assert_eq!(
+			rows(&quads),
+			[
+				[1.0, 2.0, 200.0, 201.0],
+				[2.0, 17.0, 201.0, 206.0],
+				[17.0, 18.0, 206.0, 207.0],
+			]
+		);
+		// trimming a whole border leaves that slice out
+		let no_left = SpriteMeta {
+			rect: RectXYWH::new(100, 200, 9, 10),
+			offset: RectWH::new(3, 0),
+			..nine()
+		};
+		let quads = no_left.nine_slice_quads([30.0, 20.0], 1.0);
+		assert_eq!(quads.len(), 6);
+		assert_tiles(&quads, [3.0, 0.0, 30.0, 20.0]);
+	}
+
+	#[test]
+	fn nine_slice_of_flipped_frames() {
+		let sprite = trimmed_nine();
+		let flipped = SpriteMeta {
+			flip: Flip { x: true, y: false },
+			..sprite
+		};
+		let quads = sprite.nine_slice_quads([30.0, 20.0], 1.0);
+		let flipped_quads = flipped.nine_slice_quads([30.0, 20.0], 1.0);
+		assert_tiles(&flipped_quads, [2.0, 1.0, 28.0, 18.0]);
+		// same places, with the uvs mirrored inside the packed rect
+		for (a, b) in quads.iter().zip(&flipped_quads) {
+			assert_eq!((a.position, a.size), (b.position, b.size));
+			for (a, b) in a.uv.iter().zip(&b.uv) {
+				assert_eq!([208.0 - a[0], a[1]], *b);
+			}
+		}
+	}
+}
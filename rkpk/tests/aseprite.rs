@@ -0,0 +1,156 @@
+//! parsing the files in `aseprite/`, see `aseprite/generate.py` for what's
+//! in each of them
+
+use rkpk::build::aseprite::Aseprite;
+use rkpk::common::{LoopMode, NineSlice, RectWH, RkPkError};
+
+const CELS: &[u8] = include_bytes!("aseprite/cels.aseprite");
+const INDEXED: &[u8] = include_bytes!("aseprite/indexed.aseprite");
+const GROUPS: &[u8] = include_bytes!("aseprite/groups.aseprite");
+const TAGS: &[u8] = include_bytes!("aseprite/tags.aseprite");
+const SLICES: &[u8] = include_bytes!("aseprite/slices.aseprite");
+
+const RED: [u8; 4] = [255, 0, 0, 255];
+const GREEN: [u8; 4] = [0, 255, 0, 255];
+const BLUE: [u8; 4] = [0, 0, 255, 255];
+const CLEAR: [u8; 4] = [0; 4];
+
+/// pixel `x, y` of `frame` in the sheet
+fn pixel(file: &Aseprite, frame: usize, x: usize, y: usize) -> [u8; 4] {
+	let i = (y * file.sheet.size.w as usize + frame * file.size.w as usize + x) * 4;
+	file.sheet.data[i..i + 4].try_into().unwrap()
+}
+
+fn error(data: &[u8]) -> &'static str {
+	match Aseprite::parse(data) {
+		Err(RkPkError::BadAseprite(v)) => v,
+		v => panic!("parsed anyway: {:?}", v),
+	}
+}
+
+/// where `pattern` first starts in `data`
+fn find(data: &[u8], pattern: &[u8]) -> usize {
+	data.windows(pattern.len())
+		.position(|v| v == pattern)
+		.unwrap()
+}
+
+#[test]
+fn raw_compressed_and_linked_cels() {
+	let file = Aseprite::parse(CELS).unwrap();
+	assert_eq!(file.size, RectWH::new(4, 4));
+	assert_eq!(file.sheet.size, RectWH::new(12, 4));
+	assert_eq!(file.durations, [0.1, 0.2, 0.15]);
+	// raw red under compressed blue, the clear corner of the blue shows red
+	assert_eq!(pixel(&file, 0, 0, 0), RED);
+	assert_eq!(pixel(&file, 0, 1, 1), BLUE);
+	assert_eq!(pixel(&file, 0, 2, 1), BLUE);
+	assert_eq!(pixel(&file, 0, 2, 2), CLEAR);
+	assert_eq!(pixel(&file, 0, 3, 3), CLEAR);
+	// linked red with half opacity green over nothing
+	assert_eq!(pixel(&file, 1, 1, 1), RED);
+	assert_eq!(pixel(&file, 1, 2, 2), [0, 255, 0, 128]);
+	assert_eq!(pixel(&file, 1, 3, 3), [0, 255, 0, 128]);
+	// only the part of a cel that's on the canvas gets drawn
+	assert_eq!(pixel(&file, 2, 0, 0), RED);
+	assert_eq!(pixel(&file, 2, 0, 3), GREEN);
+	assert_eq!(pixel(&file, 2, 1, 3), CLEAR);
+	// nothing spills over into the next frame
+	assert_eq!(pixel(&file, 1, 0, 3), CLEAR);
+	// no tags plays every frame
+	let clip = &file.animations["default"];
+	assert_eq!(file.animations.len(), 1);
+	assert_eq!(clip.frames, [0, 1, 2]);
+	assert_eq!(clip.durations, file.durations);
+	assert_eq!(clip.mode, LoopMode::Loop);
+	assert_eq!(file.nine_slice, None);
+}
+
+#[test]
+fn indexed_with_a_transparent_index() {
+	let file = Aseprite::parse(INDEXED).unwrap();
+	assert_eq!(pixel(&file, 0, 0, 0), [0, 0, 0, 255]);
+	assert_eq!(pixel(&file, 0, 1, 0), RED);
+	assert_eq!(pixel(&file, 0, 0, 1), CLEAR);
+	assert_eq!(pixel(&file, 0, 1, 1), [0, 0, 255, 128]);
+	// a single frame doesn't get a clip
+	assert!(file.animations.is_empty());
+}
+
+#[test]
+fn hidden_layers_and_groups() {
+	let file = Aseprite::parse(GROUPS).unwrap();
+	assert_eq!(pixel(&file, 0, 0, 0), CLEAR);
+	assert_eq!(pixel(&file, 0, 0, 1), CLEAR);
+	assert_eq!(pixel(&file, 0, 1, 0), GREEN);
+}
+
+#[test]
+fn tag_directions() {
+	let file = Aseprite::parse(TAGS).unwrap();
+	let expected = [
+		("forward", &[0, 1, 2][..], LoopMode::Loop),
+		("reverse", &[3, 2, 1], LoopMode::Loop),
+		("ping_pong", &[0, 1, 2, 3], LoopMode::PingPong),
+		("ping_pong_reverse", &[2, 1, 0], LoopMode::PingPong),
+		// a single repeat stops at the end
+		("once", &[1, 2], LoopMode::Once),
+		("twice", &[2, 3], LoopMode::PingPong),
+	];
+	assert_eq!(file.animations.len(), expected.len());
+	for (name, frames, mode) in expected {
+		let clip = &file.animations[name];
+		assert_eq!(clip.frames, frames, "{}", name);
+		assert_eq!(clip.mode, mode, "{}", name);
+		let durations = frames
+			.iter()
+			.map(|&v| file.durations[v])
+			.collect::<Vec<_>>();
+		assert_eq!(clip.durations, durations, "{}", name);
+	}
+}
+
+#[test]
+fn first_nine_patch_slice() {
+	let file = Aseprite::parse(SLICES).unwrap();
+	assert_eq!(
+		file.nine_slice,
+		Some(NineSlice {
+			left: 3,
+			top: 2,
+			right: 3,
+			bottom: 3,
+		})
+	);
+}
+
+#[test]
+fn broken_files() {
+	assert_eq!(error(&[]), "unexpected end of file");
+	assert_eq!(error(&CELS[..100]), "unexpected end of file");
+	assert_eq!(error(&CELS[..CELS.len() - 5]), "unexpected end of file");
+	let mut data = CELS.to_vec();
+	data[4] = 0;
+	assert_eq!(error(&data), "not an aseprite file");
+	let mut data = CELS.to_vec();
+	data[12] = 24;
+	assert_eq!(error(&data), "unknown color depth");
+	let mut data = CELS.to_vec();
+	data[132] = 0;
+	assert_eq!(error(&data), "bad frame magic");
+	// the raw cel claims to be wider than its pixels
+	let mut data = CELS.to_vec();
+	let raw = find(&data, &[42, 0, 0, 0, 0x05, 0x20, 0, 0]);
+	data[raw + 22] = 3;
+	assert_eq!(error(&data), "cel is missing pixels");
+	// the linked cel points past the frames before it
+	let mut data = CELS.to_vec();
+	let linked = find(&data, &[24, 0, 0, 0, 0x05, 0x20, 0, 0]);
+	data[linked + 22] = 5;
+	assert_eq!(error(&data), "bad linked cel");
+	// the first tag ends after the last frame
+	let mut data = TAGS.to_vec();
+	let tags = find(&data, &[0x18, 0x20]);
+	data[tags + 2 + 10 + 2] = 9;
+	assert_eq!(error(&data), "tag outside of the frames");
+}
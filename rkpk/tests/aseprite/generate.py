@@ -0,0 +1,216 @@
+#!/usr/bin/env python3
+# writes the .aseprite fixtures used by tests/aseprite.rs, laid out as in
+# https://github.com/aseprite/aseprite/blob/main/docs/ase-file-specs.md
+#
+#   python3 generate.py
+
+import struct
+import zlib
+from pathlib import Path
+
+HERE = Path(__file__).parent
+
+
+def string(v):
+	v = v.encode()
+	return struct.pack("<H", len(v)) + v
+
+
+def chunk(kind, data):
+	return struct.pack("<IH", len(data) + 6, kind) + data
+
+
+def layer(name, kind=0, visible=True, child_level=0, opacity=255):
+	flags = 1 if visible else 0
+	return chunk(
+		0x2004,
+		struct.pack("<HHHHHHB3x", flags, kind, child_level, 0, 0, 0, opacity)
+		+ string(name),
+	)
+
+
+def cel_header(layer, x, y, kind, opacity=255, z_index=0):
+	return struct.pack("<HhhBHh5x", layer, x, y, opacity, kind, z_index)
+
+
+def raw_cel(layer, x, y, w, h, pixels, **kw):
+	return chunk(0x2005, cel_header(layer, x, y, 0, **kw) + struct.pack("<HH", w, h) + pixels)
+
+
+def zlib_cel(layer, x, y, w, h, pixels, **kw):
+	return chunk(
+		0x2005,
+		cel_header(layer, x, y, 2, **kw) + struct.pack("<HH", w, h) + zlib.compress(pixels),
+	)
+
+
+def linked_cel(layer, x, y, frame, **kw):
+	return chunk(0x2005, cel_header(layer, x, y, 1, **kw) + struct.pack("<H", frame))
+
+
+def palette(colors):
+	data = struct.pack("<III8x", len(colors), 0, len(colors) - 1)
+	for i, c in enumerate(colors):
+		# every other entry has a name
+		flags = i % 2
+		data += struct.pack("<H4B", flags, *c)
+		if flags:
+			data += string("colour %d" % i)
+	return chunk(0x2019, data)
+
+
+def tags(entries):
+	data = struct.pack("<H8x", len(entries))
+	for start, end, direction, repeat, name in entries:
+		data += struct.pack("<HHBH6x3Bx", start, end, direction, repeat, 0, 0, 0) + string(name)
+	return chunk(0x2018, data)
+
+
+def slice(name, keys, center=False, pivot=False):
+	flags = (1 if center else 0) | (2 if pivot else 0)
+	data = struct.pack("<III", len(keys), flags, 0) + string(name)
+	for key in keys:
+		frame, x, y, w, h = key[:5]
+		data += struct.pack("<IiiII", frame, x, y, w, h)
+		if center:
+			data += struct.pack("<iiII", *key[5:9])
+		if pivot:
+			data += struct.pack("<ii", 0, 0)
+	return chunk(0x2022, data)
+
+
+def frame(duration, chunks):
+	data = b"".join(chunks)
+	return struct.pack("<IHHH2xI", len(data) + 16, 0xF1FA, len(chunks), duration, len(chunks)) + data
+
+
+def file(name, size, depth, frames, transparent=0, layer_opacity=True):
+	count = len(frames)
+	frames = b"".join(frames)
+	header = struct.pack(
+		"<IHHHHHIH8xB3xHBBhhHH84x",
+		128 + len(frames),
+		0xA5E0,
+		count,
+		size[0],
+		size[1],
+		depth,
+		1 if layer_opacity else 0,
+		100,
+		transparent,
+		256 if depth == 8 else 0,
+		1,
+		1,
+		0,
+		0,
+		16,
+		16,
+	)
+	assert len(header) == 128
+	(HERE / name).write_bytes(header + frames)
+
+
+def rgba(*pixels):
+	return b"".join(bytes(v) for v in pixels)
+
+
+RED = (255, 0, 0, 255)
+GREEN = (0, 255, 0, 255)
+BLUE = (0, 0, 255, 255)
+CLEAR = (0, 0, 0, 0)
+
+# raw, compressed and linked cels, one partly off the canvas and one at half
+# opacity
+file(
+	"cels.aseprite",
+	(4, 4),
+	32,
+	[
+		frame(100, [
+			layer("bottom"),
+			layer("top"),
+			raw_cel(0, 0, 0, 2, 2, rgba(RED, RED, RED, RED)),
+			zlib_cel(1, 1, 1, 2, 2, rgba(BLUE, BLUE, BLUE, CLEAR)),
+		]),
+		frame(200, [
+			linked_cel(0, 0, 0, 0),
+			raw_cel(1, 2, 2, 2, 2, rgba(GREEN, GREEN, GREEN, GREEN), opacity=128),
+		]),
+		frame(150, [
+			linked_cel(0, 0, 0, 0),
+			zlib_cel(1, -1, 3, 2, 2, rgba(BLUE, GREEN, BLUE, GREEN)),
+		]),
+	],
+)
+
+# index 2 is transparent, index 3 is half see through
+file(
+	"indexed.aseprite",
+	(2, 2),
+	8,
+	[
+		frame(100, [
+			palette([(0, 0, 0, 255), (255, 0, 0, 255), (0, 255, 0, 255), (0, 0, 255, 128)]),
+			layer("only"),
+			zlib_cel(0, 0, 0, 2, 2, bytes([0, 1, 2, 3])),
+		]),
+	],
+	transparent=2,
+)
+
+# only `outside` is shown, everything else is hidden or in a hidden group
+file(
+	"groups.aseprite",
+	(2, 2),
+	32,
+	[
+		frame(100, [
+			layer("hidden group", kind=1, visible=False),
+			layer("inside", child_level=1),
+			layer("group", kind=1),
+			layer("hidden", child_level=1, visible=False),
+			layer("outside", child_level=1),
+			raw_cel(1, 0, 0, 1, 1, rgba(RED)),
+			raw_cel(3, 0, 1, 1, 1, rgba(BLUE)),
+			raw_cel(4, 1, 0, 1, 1, rgba(GREEN)),
+		]),
+	],
+)
+
+# one tag for every direction, with and without repeats
+file(
+	"tags.aseprite",
+	(1, 1),
+	32,
+	[
+		frame(10, [
+			layer("only"),
+			tags([
+				(0, 2, 0, 0, "forward"),
+				(1, 3, 1, 0, "reverse"),
+				(0, 3, 2, 0, "ping_pong"),
+				(0, 2, 3, 0, "ping_pong_reverse"),
+				(1, 2, 0, 1, "once"),
+				(2, 3, 2, 2, "twice"),
+			]),
+		]),
+		frame(20, []),
+		frame(30, []),
+		frame(40, []),
+	],
+)
+
+# a plain slice first, then the nine-patch that counts and one more after it
+file(
+	"slices.aseprite",
+	(8, 8),
+	32,
+	[
+		frame(100, [
+			layer("only"),
+			slice("plain", [(0, 0, 0, 8, 8)]),
+			slice("patch", [(0, 1, 1, 6, 6, 2, 1, 2, 3)], center=True, pivot=True),
+			slice("later", [(0, 0, 0, 8, 8, 1, 1, 6, 6)], center=True),
+		]),
+	],
+)
@@ -0,0 +1,130 @@
+//! bad assets give errors that say which file was wrong instead of panicking
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rkpk::build::{DirOutput, PackOptions, Packer};
+use rkpk::common::{RectWH, RectXYWH, RkPkError};
+
+/// an empty `group/layer` folder in a graph of its own
+fn graph(name: &str) -> (PathBuf, PathBuf) {
+	let root = std::env::temp_dir().join(format!("rkpk-errors-{}-{}", name, std::process::id()));
+	let _ = fs::remove_dir_all(&root);
+	let layer = root.join("group").join("layer");
+	fs::create_dir_all(&layer).unwrap();
+	(root, layer)
+}
+
+fn png(path: &Path, w: u32, h: u32) {
+	let data = vec![0xFF; w as usize * h as usize * 4];
+	image::save_buffer(path, &data, w, h, image::ColorType::Rgba8).unwrap();
+}
+
+/// add the graph and pack it
+fn pack(root: &Path, options: PackOptions) -> Result<(), RkPkError> {
+	let mut packer = Packer::with_options(options);
+	let res = packer.add_dir(root).and_then(|_| {
+		packer
+			.save_build_info("graph.meta", "graph", &mut DirOutput::new(root.join("out")))
+			.map(|_| ())
+	});
+	fs::remove_dir_all(root).unwrap();
+	res
+}
+
+#[test]
+fn bad_sidecar() {
+	let (root, layer) = graph("sidecar");
+	png(&layer.join("button.png"), 8, 8);
+	let sidecar = layer.join("button.png.nine");
+	fs::write(&sidecar, "left = \"wide\"").unwrap();
+	match pack(&root, PackOptions::default()) {
+		Err(RkPkError::BadSidecar { path, .. }) => assert_eq!(path, sidecar),
+		v => panic!("{:?}", v),
+	}
+}
+
+#[test]
+fn tiles_outside_the_image() {
+	let (root, layer) = graph("tiled");
+	let image = layer.join("walk.png");
+	png(&image, 8, 4);
+	fs::write(
+		layer.join("walk.png.tiled"),
+		"init = { x = 0, y = 0, w = 4, h = 4 }\ncount = { w = 3, h = 1 }",
+	)
+	.unwrap();
+	match pack(&root, PackOptions::default()) {
+		Err(RkPkError::OutOfBounds { path, rect, size }) => {
+			assert_eq!(path, image);
+			assert_eq!(rect, RectXYWH::new(8, 0, 4, 4));
+			assert_eq!(size, RectWH::new(8, 4));
+		}
+		v => panic!("{:?}", v),
+	}
+}
+
+#[test]
+fn image_too_big_for_positions() {
+	let (root, layer) = graph("too-big");
+	let image = layer.join("sky.png");
+	png(&image, 1 << 16, 1);
+	match pack(&root, PackOptions::default()) {
+		Err(RkPkError::TooBig { path, size }) => {
+			assert_eq!(path, image);
+			assert_eq!(size, [1 << 16, 1]);
+		}
+		v => panic!("{:?}", v),
+	}
+}
+
+#[test]
+fn broken_image() {
+	let (root, layer) = graph("broken");
+	let image = layer.join("broken.png");
+	fs::write(&image, b"not a png").unwrap();
+	match pack(&root, PackOptions::default()) {
+		Err(RkPkError::File { path, source }) => {
+			assert_eq!(path, image);
+			assert!(matches!(*source, RkPkError::ImageError(_)), "{:?}", source);
+		}
+		v => panic!("{:?}", v),
+	}
+}
+
+#[test]
+fn frame_bigger_than_a_page() {
+	let (root, layer) = graph("oversized");
+	png(&layer.join("small.png"), 4, 4);
+	png(&layer.join("wide.png"), 16, 4);
+	let options = PackOptions {
+		max_size: 8,
+		..Default::default()
+	};
+	match pack(&root, options) {
+		Err(RkPkError::PackingFailed { layer, frames }) => {
+			assert_eq!(layer, "group/layer");
+			// only the frame that can't fit is blamed
+			assert_eq!(frames, [("wide frame 0".to_string(), RectWH::new(16, 4))]);
+		}
+		v => panic!("{:?}", v),
+	}
+}
+
+#[test]
+fn name_collision() {
+	let (root, layer) = graph("collision");
+	let image = layer.join("hero.png");
+	png(&image, 4, 4);
+	let aseprite = layer.join("hero.aseprite");
+	fs::write(&aseprite, include_bytes!("aseprite/cels.aseprite")).unwrap();
+	match pack(&root, PackOptions::default()) {
+		Err(RkPkError::NameCollision { name, mut paths }) => {
+			assert_eq!(name, "group/layer/hero");
+			// whichever was read first comes first
+			paths.sort();
+			assert_eq!(paths, [aseprite, image]);
+		}
+		v => panic!("{:?}", v),
+	}
+}
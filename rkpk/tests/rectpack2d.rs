@@ -0,0 +1,293 @@
+//! invariants every packer has to keep over random rects, plus bin sizes
+//! compared against the ones in `rectpack2d_golden.txt`
+
+use rkpk::common::{ImagePos, RectWH, RectXYWH};
+use rkpk::rectpack2d::empty_spaces::EmptySpaces;
+use rkpk::rectpack2d::{
+	find_best_packing, find_best_packing_bins, Algorithm, DiscardStep, PackingRoot,
+	DEFAULT_COMPARATORS,
+};
+
+/// xorshift, so failures can be reproduced from the seed alone
+struct Rng(u64);
+
+impl Rng {
+	fn new(seed: u64) -> Self {
+		Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+	}
+	fn next(&mut self) -> u64 {
+		self.0 ^= self.0 << 13;
+		self.0 ^= self.0 >> 7;
+		self.0 ^= self.0 << 17;
+		self.0
+	}
+	/// somewhere in `min..=max`
+	fn range(&mut self, min: ImagePos, max: ImagePos) -> ImagePos {
+		min + (self.next() % (max - min + 1) as u64) as ImagePos
+	}
+	/// a mix of squares, strips and everything between
+	fn rects(&mut self, count: usize, max: ImagePos) -> Vec<RectXYWH> {
+		(0..count)
+			.map(|_| match self.next() % 4 {
+				0 => {
+					let side = self.range(1, max);
+					RectXYWH::new(0, 0, side, side)
+				}
+				1 => RectXYWH::new(0, 0, self.range(1, max), self.range(1, 4)),
+				2 => RectXYWH::new(0, 0, self.range(1, 4), self.range(1, max)),
+				_ => RectXYWH::new(0, 0, self.range(1, max), self.range(1, max)),
+			})
+			.collect()
+	}
+}
+
+/// every test tries this many sets for each algorithm
+const SEEDS: u64 = 100;
+
+fn overlaps(a: RectXYWH, b: RectXYWH) -> bool {
+	a.x < b.x + b.w && b.x < a.x + a.w && a.y < b.y + b.h && b.y < a.y + a.h
+}
+
+/// every rect kept its size, is inside `bin` and doesn't touch any other
+fn check_placed(seed: u64, input: &[RectXYWH], placed: &[RectXYWH], bin: RectWH) {
+	for (i, (a, b)) in input.iter().zip(placed).enumerate() {
+		assert_eq!(
+			a.to_wh(),
+			b.to_wh(),
+			"seed {}: rect {} changed size",
+			seed,
+			i
+		);
+		assert!(
+			b.x as u32 + b.w as u32 <= bin.w as u32 && b.y as u32 + b.h as u32 <= bin.h as u32,
+			"seed {}: {:?} is outside of {:?}",
+			seed,
+			b,
+			bin
+		);
+		for (j, c) in placed[..i].iter().enumerate() {
+			assert!(
+				!overlaps(*b, *c),
+				"seed {}: rects {} and {} overlap, {:?} {:?}",
+				seed,
+				j,
+				i,
+				c,
+				b
+			);
+		}
+	}
+}
+
+/// smallest size holding every rect
+fn bounds(rects: &[RectXYWH]) -> RectWH {
+	rects.iter().fold(RectWH::default(), |size, v| {
+		RectWH::new(size.w.max(v.x + v.w), size.h.max(v.y + v.h))
+	})
+}
+
+#[test]
+fn roots_never_overlap() {
+	for &algorithm in Algorithm::ALL {
+		let mut root = algorithm.root();
+		for seed in 0..SEEDS {
+			let mut rng = Rng::new(seed);
+			let bin = RectWH::new(rng.range(1, 256), rng.range(1, 256));
+			root.reset(bin);
+			let mut input = vec![];
+			let mut placed = vec![];
+			for rect in rng.rects(64, 96) {
+				if let Some(v) = root.insert(rect) {
+					input.push(rect);
+					placed.push(v);
+				}
+			}
+			check_placed(seed, &input, &placed, bin);
+			assert_eq!(
+				root.current_aabb(),
+				bounds(&placed),
+				"seed {}: {:?} has the wrong bounds",
+				seed,
+				algorithm
+			);
+		}
+	}
+}
+
+#[test]
+fn best_packing_places_everything() {
+	for &algorithm in Algorithm::ALL {
+		let mut root = algorithm.root();
+		for seed in 0..SEEDS {
+			let mut rng = Rng::new(seed);
+			let count = rng.range(1, 32) as usize;
+			let input = rng.rects(count, 64);
+			let mut placed = input.clone();
+			let discard_step = match seed % 3 {
+				0 => DiscardStep::Step(1),
+				1 => DiscardStep::Step(16),
+				_ => DiscardStep::Tries(2),
+			};
+			let bin = find_best_packing(
+				root.as_mut(),
+				&mut placed,
+				512,
+				discard_step,
+				DEFAULT_COMPARATORS,
+			)
+			.unwrap_or_else(|| panic!("seed {}: {:?} didn't fit", seed, algorithm));
+			check_placed(seed, &input, &placed, bin);
+			assert_eq!(bin, bounds(&placed), "seed {}: {:?}", seed, algorithm);
+			let area = input.iter().map(|v| v.area()).sum::<u32>();
+			assert!(bin.area() >= area, "seed {}: {:?}", seed, algorithm);
+		}
+	}
+}
+
+#[test]
+fn bins_place_everything() {
+	for &algorithm in Algorithm::ALL {
+		let mut root = algorithm.root();
+		for seed in 0..SEEDS {
+			let mut rng = Rng::new(seed);
+			let max_size = rng.range(64, 160);
+			let count = rng.range(1, 64) as usize;
+			let input = rng.rects(count, 64);
+			let mut placed = input.clone();
+			let mut bins = vec![usize::MAX; input.len()];
+			let sizes = find_best_packing_bins(
+				root.as_mut(),
+				&mut placed,
+				&mut bins,
+				max_size,
+				DiscardStep::Step(1),
+				DEFAULT_COMPARATORS,
+			)
+			.unwrap_or_else(|| panic!("seed {}: {:?} didn't fit", seed, algorithm));
+			for (bin, &size) in sizes.iter().enumerate() {
+				assert!(size.w <= max_size && size.h <= max_size, "seed {}", seed);
+				let (input, placed): (Vec<_>, Vec<_>) = input
+					.iter()
+					.zip(&placed)
+					.zip(&bins)
+					.filter(|v| *v.1 == bin)
+					.map(|v| v.0)
+					.unzip();
+				assert!(!placed.is_empty(), "seed {}: bin {} is empty", seed, bin);
+				check_placed(seed, &input, &placed, size);
+			}
+			assert!(bins.iter().all(|&v| v < sizes.len()), "seed {}", seed);
+		}
+	}
+}
+
+#[test]
+fn freed_spaces_get_reused() {
+	let mut spaces = EmptySpaces::new();
+	spaces.reset(RectWH::new(64, 64));
+	let a = spaces.insert_best_fit(RectXYWH::new(0, 0, 16, 8)).unwrap();
+	let b = spaces.insert_best_fit(RectXYWH::new(0, 0, 16, 8)).unwrap();
+	spaces.free(a);
+	assert_eq!(spaces.insert_best_fit(RectXYWH::new(0, 0, 16, 8)), Some(a));
+	// something smaller goes in the same hole instead of the rest of the bin
+	spaces.free(b);
+	let c = spaces.insert_best_fit(RectXYWH::new(0, 0, 8, 8)).unwrap();
+	assert!(
+		c.x >= b.x && c.x + c.w <= b.x + b.w && c.y == b.y,
+		"{:?}",
+		c
+	);
+	// freeing a rect that shares an edge with nothing else still works
+	let mut spaces = EmptySpaces::new();
+	spaces.reset(RectWH::new(16, 16));
+	let whole = spaces.insert_best_fit(RectXYWH::new(0, 0, 16, 16)).unwrap();
+	assert!(spaces.insert_best_fit(RectXYWH::new(0, 0, 1, 1)).is_none());
+	spaces.free(whole);
+	assert_eq!(
+		spaces.insert_best_fit(RectXYWH::new(0, 0, 16, 16)),
+		Some(whole)
+	);
+}
+
+#[test]
+fn growing_keeps_what_was_placed() {
+	let mut spaces = EmptySpaces::new();
+	spaces.reset(RectWH::new(32, 32));
+	let a = spaces.insert_best_fit(RectXYWH::new(0, 0, 32, 32)).unwrap();
+	spaces.grow(RectWH::new(32, 32), RectWH::new(64, 48));
+	let placed = [
+		a,
+		spaces.insert_best_fit(RectXYWH::new(0, 0, 32, 48)).unwrap(),
+		spaces.insert_best_fit(RectXYWH::new(0, 0, 32, 16)).unwrap(),
+	];
+	check_placed(0, &placed, &placed, RectWH::new(64, 48));
+	assert!(spaces.insert_best_fit(RectXYWH::new(0, 0, 1, 1)).is_none());
+}
+
+#[test]
+fn insert_and_free_never_overlap() {
+	for seed in 0..SEEDS {
+		let mut rng = Rng::new(seed);
+		let bin = RectWH::new(rng.range(16, 128), rng.range(16, 128));
+		let mut spaces = EmptySpaces::new();
+		spaces.reset(bin);
+		let mut live = vec![];
+		for rect in rng.rects(200, 24) {
+			if rng.next().is_multiple_of(3) && !live.is_empty() {
+				let i = rng.next() as usize % live.len();
+				spaces.free(live.swap_remove(i));
+			} else if let Some(v) = spaces.insert_best_fit(rect) {
+				live.push(v);
+			}
+			check_placed(seed, &live, &live, bin);
+			// nothing free overlaps anything placed or another free space
+			for (i, space) in spaces.spaces.iter().enumerate() {
+				for v in live.iter().chain(&spaces.spaces[..i]) {
+					assert!(!overlaps(*space, *v), "seed {}: {:?} {:?}", seed, space, v);
+				}
+			}
+		}
+	}
+}
+
+/// `<w>x<h>`
+fn parse_size(v: &str) -> RectWH {
+	let (w, h) = v.split_once('x').expect("sizes look like 12x34");
+	RectWH::new(w.parse().unwrap(), h.parse().unwrap())
+}
+
+#[test]
+fn empty_spaces_matches_golden_bins() {
+	let mut mismatches = vec![];
+	for line in include_str!("rectpack2d_golden.txt").lines() {
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+		let (settings, rects) = line.split_once(" : ").unwrap();
+		let mut settings = settings.split_whitespace();
+		let max_size = settings.next().unwrap().parse().unwrap();
+		let discard_step = match settings.next().unwrap().parse::<i32>().unwrap() {
+			v if v <= 0 => DiscardStep::Tries(-v as u16),
+			v => DiscardStep::Step(v as u16),
+		};
+		let expected = parse_size(settings.nth(1).unwrap());
+		let mut rects = rects
+			.split_whitespace()
+			.map(|v| {
+				let size = parse_size(v);
+				RectXYWH::new(0, 0, size.w, size.h)
+			})
+			.collect::<Vec<_>>();
+		let bin = find_best_packing(
+			&mut EmptySpaces::new(),
+			&mut rects,
+			max_size,
+			discard_step,
+			DEFAULT_COMPARATORS,
+		);
+		if bin != Some(expected) {
+			mismatches.push(format!("{}\n  got {:?}", line, bin));
+		}
+	}
+	assert!(mismatches.is_empty(), "\n{}", mismatches.join("\n"));
+}
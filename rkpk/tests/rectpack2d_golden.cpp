@@ -0,0 +1,64 @@
+// fills in the expected bins of rectpack2d_golden.txt, run it from
+// rkpk/tests after changing the sets:
+//
+//   g++ -std=c++17 -O2 -I rectpack2d_golden rectpack2d_golden.cpp -o /tmp/golden
+//   /tmp/golden < rectpack2d_golden.txt > /tmp/golden.txt
+//   mv /tmp/golden.txt rectpack2d_golden.txt
+//
+// the checked in bins came from that command (g++ 12.2) with the headers in
+// rectpack2d_golden/, a stand-in for upstream rectpack2d and not a pinned
+// upstream commit. to check them against upstream itself:
+//
+//   git clone https://github.com/TeamHypersomnia/rectpack2D /tmp/rectpack2D
+//   g++ -std=c++17 -O2 -I /tmp/rectpack2D/src rectpack2d_golden.cpp -o /tmp/golden
+//   /tmp/golden < rectpack2d_golden.txt | diff rectpack2d_golden.txt -
+//
+// and write the commit (`git -C /tmp/rectpack2D rev-parse HEAD`) here and in
+// rectpack2d_golden.txt
+//
+// lines are `<max side> <discard step> = <bin> : <rects...>`, sizes are
+// `<w>x<h>` and whatever is after `=` gets replaced
+
+#include <iostream>
+#include <sstream>
+#include <string>
+#include <vector>
+
+#include <rectpack2d/finders_interface.h>
+
+using namespace rectpack2d;
+
+using spaces_type = empty_spaces<false, default_empty_spaces>;
+using rect_type = output_rect_t<spaces_type>;
+
+int main() {
+	std::string line;
+	while (std::getline(std::cin, line)) {
+		if (line.empty() || line[0] == '#') {
+			std::cout << line << '\n';
+			continue;
+		}
+		std::istringstream in(line);
+		int max_side, discard_step;
+		std::string token;
+		in >> max_side >> discard_step;
+		while (in >> token && token != ":") {
+		}
+		std::vector<rect_type> rects;
+		std::string sizes;
+		while (in >> token) {
+			rect_type r;
+			char x;
+			std::istringstream(token) >> r.w >> x >> r.h;
+			rects.push_back(r);
+			sizes += " " + token;
+		}
+		const auto keep_going = [](rect_type&) { return callback_result::CONTINUE_PACKING; };
+		const auto abort = [](rect_type&) { return callback_result::ABORT_PACKING; };
+		const auto bin = find_best_packing<spaces_type>(
+			rects,
+			make_finder_input(max_side, discard_step, keep_going, abort, flipping_option::DISABLED)
+		);
+		std::cout << max_side << ' ' << discard_step << " = " << bin.w << 'x' << bin.h << " :" << sizes << '\n';
+	}
+}
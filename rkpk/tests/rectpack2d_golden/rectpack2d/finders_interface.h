@@ -0,0 +1,238 @@
+// stand-in for upstream rectpack2d's `finders_interface.h`, written from its
+// documented api and algorithm rather than copied from a checkout, since
+// none was available when the goldens were made. only the parts
+// rectpack2d_golden.cpp uses are here, with flipping left out. build the
+// generator against a real checkout (`-I <rectpack2D>/src`) to check the
+// goldens against upstream itself
+#pragma once
+#include <algorithm>
+#include <array>
+#include <optional>
+#include <variant>
+#include <vector>
+#include <functional>
+
+namespace rectpack2d {
+using total_area_type = int;
+
+struct rect_wh {
+	int w = 0, h = 0;
+	rect_wh() = default;
+	rect_wh(int w, int h) : w(w), h(h) {}
+	int max_side() const { return h > w ? h : w; }
+	int min_side() const { return h < w ? h : w; }
+	int area() const { return w * h; }
+	int perimeter() const { return 2 * w + 2 * h; }
+	double pathological_mult() const { return double(max_side()) / min_side() * area(); }
+	template <class R> void expand_with(const R& r) {
+		w = std::max(w, r.x + r.w);
+		h = std::max(h, r.y + r.h);
+	}
+};
+
+struct rect_xywh {
+	int x = 0, y = 0, w = 0, h = 0;
+	rect_xywh() = default;
+	rect_xywh(int x, int y, int w, int h) : x(x), y(y), w(w), h(h) {}
+	int area() const { return w * h; }
+	auto get_wh() const { return rect_wh(w, h); }
+};
+
+struct created_splits {
+	int count = 0;
+	std::array<rect_xywh, 2> spaces;
+	static auto failed() { created_splits result; result.count = -1; return result; }
+	static auto none() { return created_splits(); }
+	template <class... Args> created_splits(Args&&... args) : spaces({ std::forward<Args>(args)... }) { count = sizeof...(Args); }
+	bool better_than(const created_splits& b) const { return count < b.count; }
+	explicit operator bool() const { return count != -1; }
+};
+
+inline created_splits insert_and_split(const rect_wh& im, const rect_xywh& sp) {
+	const auto free_w = sp.w - im.w;
+	const auto free_h = sp.h - im.h;
+	if (free_w < 0 || free_h < 0) return created_splits::failed();
+	if (free_w == 0 && free_h == 0) return created_splits::none();
+	if (free_w > 0 && free_h == 0) { auto r = sp; r.x += im.w; r.w -= im.w; return created_splits(r); }
+	if (free_w == 0 && free_h > 0) { auto r = sp; r.y += im.h; r.h -= im.h; return created_splits(r); }
+	if (free_w > free_h) {
+		const auto bigger_split = rect_xywh(sp.x + im.w, sp.y, free_w, sp.h);
+		const auto lesser_split = rect_xywh(sp.x, sp.y + im.h, im.w, free_h);
+		return created_splits(bigger_split, lesser_split);
+	}
+	const auto bigger_split = rect_xywh(sp.x, sp.y + im.h, sp.w, free_h);
+	const auto lesser_split = rect_xywh(sp.x + im.w, sp.y, free_w, im.h);
+	return created_splits(bigger_split, lesser_split);
+}
+
+class default_empty_spaces {
+	std::vector<rect_xywh> empty_spaces;
+public:
+	void remove(const int i) { empty_spaces[i] = empty_spaces.back(); empty_spaces.pop_back(); }
+	bool add(const rect_xywh r) { empty_spaces.emplace_back(r); return true; }
+	auto get_count() const { return empty_spaces.size(); }
+	void reset() { empty_spaces.clear(); }
+	const auto& get(const int i) { return empty_spaces[i]; }
+};
+
+template <bool allow_flip, class empty_spaces_provider = default_empty_spaces>
+class empty_spaces {
+	rect_wh current_aabb;
+	empty_spaces_provider spaces;
+public:
+	using output_rect_type = rect_xywh;
+	void reset(const rect_wh r) {
+		current_aabb = {};
+		spaces.reset();
+		spaces.add(rect_xywh(0, 0, r.w, r.h));
+	}
+	std::optional<rect_xywh> insert(const rect_wh image_rectangle) {
+		for (int i = static_cast<int>(spaces.get_count()) - 1; i >= 0; --i) {
+			const auto candidate_space = spaces.get(i);
+			const auto normal = insert_and_split(image_rectangle, candidate_space);
+			if (!normal) continue;
+			spaces.remove(i);
+			for (int s = 0; s < normal.count; ++s) spaces.add(normal.spaces[s]);
+			const auto result = rect_xywh(candidate_space.x, candidate_space.y, image_rectangle.w, image_rectangle.h);
+			current_aabb.expand_with(result);
+			return result;
+		}
+		return std::nullopt;
+	}
+	const auto& get_rects_aabb() const { return current_aabb; }
+};
+
+template <class T> using output_rect_t = typename T::output_rect_type;
+
+enum class callback_result { ABORT_PACKING, CONTINUE_PACKING };
+enum class flipping_option { DISABLED, ENABLED };
+enum class bin_dimension { BOTH, WIDTH, HEIGHT };
+
+template <class empty_spaces_type, class O>
+std::variant<total_area_type, rect_wh> best_packing_for_ordering_impl(
+	empty_spaces_type& root, O ordering, const rect_wh starting_bin, int discard_step, const bin_dimension tried_dimension
+) {
+	auto candidate_bin = starting_bin;
+	int tries_before_discarding = 0;
+	if (discard_step <= 0) { tries_before_discarding = -discard_step; discard_step = 1; }
+	const auto starting_step = [&]() {
+		if (tried_dimension == bin_dimension::BOTH) { candidate_bin.w /= 2; candidate_bin.h /= 2; return candidate_bin.w / 2; }
+		else if (tried_dimension == bin_dimension::WIDTH) { candidate_bin.w /= 2; return candidate_bin.w / 2; }
+		else { candidate_bin.h /= 2; return candidate_bin.h / 2; }
+	}();
+	for (auto step = starting_step;; step = std::max(1, step / 2)) {
+		root.reset(candidate_bin);
+		int total_inserted_area = 0;
+		const bool all_inserted = [&]() {
+			for (const auto& r : ordering) {
+				const auto& rect = r->get_wh();
+				if (root.insert(rect)) total_inserted_area += rect.area();
+				else return false;
+			}
+			return true;
+		}();
+		if (all_inserted) {
+			if (step <= discard_step) {
+				if (tries_before_discarding > 0) tries_before_discarding--;
+				else return candidate_bin;
+			}
+			if (tried_dimension == bin_dimension::BOTH) { candidate_bin.w -= step; candidate_bin.h -= step; }
+			else if (tried_dimension == bin_dimension::WIDTH) candidate_bin.w -= step;
+			else candidate_bin.h -= step;
+			root.reset(candidate_bin);
+		} else {
+			if (tried_dimension == bin_dimension::BOTH) {
+				candidate_bin.w += step; candidate_bin.h += step;
+				if (candidate_bin.area() > starting_bin.area()) return total_inserted_area;
+			} else if (tried_dimension == bin_dimension::WIDTH) {
+				candidate_bin.w += step;
+				if (candidate_bin.w > starting_bin.w) return total_inserted_area;
+			} else {
+				candidate_bin.h += step;
+				if (candidate_bin.h > starting_bin.h) return total_inserted_area;
+			}
+		}
+	}
+}
+
+template <class empty_spaces_type, class O>
+std::variant<total_area_type, rect_wh> best_packing_for_ordering(
+	empty_spaces_type& root, O&& ordering, const rect_wh starting_bin, const int discard_step
+) {
+	const auto try_pack = [&](const bin_dimension tried_dimension, const rect_wh starting_bin) {
+		return best_packing_for_ordering_impl(root, std::forward<O>(ordering), starting_bin, discard_step, tried_dimension);
+	};
+	const auto best_result = try_pack(bin_dimension::BOTH, starting_bin);
+	if (const auto failed = std::get_if<total_area_type>(&best_result)) return *failed;
+	auto best_bin = std::get<rect_wh>(best_result);
+	auto trial = [&](const bin_dimension tried_dimension) {
+		const auto trial = try_pack(tried_dimension, best_bin);
+		if (const auto better = std::get_if<rect_wh>(&trial)) best_bin = *better;
+	};
+	trial(bin_dimension::WIDTH);
+	trial(bin_dimension::HEIGHT);
+	return best_bin;
+}
+
+template <class F, class G>
+struct finder_input {
+	const int max_bin_side;
+	const int discard_step;
+	F handle_successful_insertion;
+	G handle_unsuccessful_insertion;
+	const flipping_option flipping_mode;
+};
+
+template <class F, class G>
+auto make_finder_input(int max_bin_side, int discard_step, F&& f, G&& g, flipping_option flipping_mode) {
+	return finder_input<F, G>{ max_bin_side, discard_step, std::forward<F>(f), std::forward<G>(g), flipping_mode };
+}
+
+template <class empty_spaces_type, class subjects_type, class F, class G>
+rect_wh find_best_packing(subjects_type& subjects, const finder_input<F, G>& input) {
+	using rect_type = output_rect_t<empty_spaces_type>;
+	using order_type = std::vector<rect_type*>;
+	std::array<std::function<bool(rect_type*, rect_type*)>, 6> comparators = {
+		[](auto a, auto b) { return a->get_wh().area() > b->get_wh().area(); },
+		[](auto a, auto b) { return a->get_wh().perimeter() > b->get_wh().perimeter(); },
+		[](auto a, auto b) { return a->get_wh().max_side() > b->get_wh().max_side(); },
+		[](auto a, auto b) { return a->get_wh().w > b->get_wh().w; },
+		[](auto a, auto b) { return a->get_wh().h > b->get_wh().h; },
+		[](auto a, auto b) { return a->get_wh().pathological_mult() > b->get_wh().pathological_mult(); },
+	};
+	std::array<order_type, 6> orders;
+	for (auto& s : subjects) orders[0].push_back(&s);
+	for (std::size_t i = 1; i < orders.size(); ++i) orders[i] = orders[0];
+	for (std::size_t i = 0; i < orders.size(); ++i) std::sort(orders[i].begin(), orders[i].end(), comparators[i]);
+	empty_spaces_type root;
+	root.reset(rect_wh(input.max_bin_side, input.max_bin_side));
+	total_area_type best_total_inserted = -1;
+	auto best_bin = rect_wh(input.max_bin_side, input.max_bin_side);
+	order_type* best_order = nullptr;
+	for (auto& current_order : orders) {
+		const auto packing = best_packing_for_ordering(root, current_order, rect_wh(input.max_bin_side, input.max_bin_side), input.discard_step);
+		if (const auto total_inserted = std::get_if<total_area_type>(&packing)) {
+			if (best_order == nullptr && *total_inserted > best_total_inserted) {
+				best_order = &current_order;
+				best_total_inserted = *total_inserted;
+			}
+		} else if (const auto result_bin = std::get_if<rect_wh>(&packing)) {
+			if (result_bin->area() <= best_bin.area()) {
+				best_order = &current_order;
+				best_bin = *result_bin;
+			}
+		}
+	}
+	root.reset(best_bin);
+	for (auto& rr : *best_order) {
+		auto& rect = *rr;
+		if (const auto ret = root.insert(rect.get_wh())) {
+			rect = *ret;
+			if (callback_result::ABORT_PACKING == input.handle_successful_insertion(rect)) break;
+		} else if (callback_result::ABORT_PACKING == input.handle_unsuccessful_insertion(rect)) {
+			break;
+		}
+	}
+	return root.get_rects_aabb();
+}
+}